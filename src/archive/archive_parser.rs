@@ -1,6 +1,6 @@
 use crate::archive::ParseError;
 use crate::schema::{Document, EnumCase, EnumValue, FieldInstance, FieldType, FieldValue, Schema};
-use crate::util::{CaseID, FieldID, FieldLength, FromByteSlice, PrimInt, SchemaID};
+use crate::util::{CaseID, DocumentID, FieldID, FieldLength, FromByteSlice, PrimInt, SchemaID};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use std::iter::Iterator;
 use std::mem::size_of;
@@ -33,14 +33,18 @@ impl ArchiveParser {
         }
     }
 
-    /// Deserializes a [`Document`], checking the schema identifier
-    /// against the [`ArchiveParser`]'s provided schema.
+    /// Deserializes a [`Document`] with its identifier, checking the
+    /// schema identifier against the [`ArchiveParser`]'s provided
+    /// schema.
     pub fn read_document(&mut self) -> Result<Document, ParseError> {
-        let schema_id = self.parse_int::<SchemaID>();
+        let id = self.parse_int::<DocumentID>()?;
+        let schema_id = self.parse_int::<SchemaID>()?;
         if schema_id != self.schema.id {
             return Err(ParseError::SchemaMismatch);
         }
-        self.read_subdocument()
+        let mut document = self.read_subdocument()?;
+        document.id = Some(id);
+        Ok(document)
     }
 
     /// Deserializes a [`Document`] without a schema identifier.
@@ -48,19 +52,19 @@ impl ArchiveParser {
         let length = self.data.len();
         let mut fields: Vec<FieldInstance> = vec![];
         while self.ptr < length {
-            match self.read_field()? {
-                Some(field_instance) => fields.push(field_instance),
-                None => {}
+            if let Some(field_instance) = self.read_field()? {
+                fields.push(field_instance);
             }
         }
         Ok(Document {
+            id: None,
             schema: self.schema.clone(),
             fields,
         })
     }
 
     fn read_field(&mut self) -> Result<Option<FieldInstance>, ParseError> {
-        let field_id = self.parse_int::<FieldID>();
+        let field_id = self.parse_int::<FieldID>()?;
         let field = &self
             .schema
             .fields
@@ -81,52 +85,38 @@ impl ArchiveParser {
 
     fn parse_value(&mut self, field_type: &FieldType) -> Result<FieldValue, ParseError> {
         match field_type {
-            FieldType::Int => Ok(FieldValue::Int(self.parse_int::<i32>())),
-            FieldType::UInt => Ok(FieldValue::UInt(self.parse_int::<u32>())),
-            FieldType::Long => Ok(FieldValue::Long(self.parse_int::<i64>())),
-            FieldType::ULong => Ok(FieldValue::ULong(self.parse_int::<u64>())),
-            FieldType::Float => Ok(FieldValue::Float(self.parse_float())),
-            FieldType::Bool => Ok(FieldValue::Bool(self.parse_bool())),
-            FieldType::DateTime => Ok(FieldValue::DateTime(self.parse_datetime())),
-            FieldType::String => Ok(FieldValue::String(
-                self.parse_string().or(Err(ParseError::InvalidString))?,
-            )),
-            FieldType::ByteArray => Ok(FieldValue::ByteArray(self.parse_byte_array())),
-            FieldType::Array(element) => Ok(FieldValue::Array(self.parse_array(&*element)?)),
+            FieldType::Int => Ok(FieldValue::Int(self.parse_int::<i32>()?)),
+            FieldType::UInt => Ok(FieldValue::UInt(self.parse_int::<u32>()?)),
+            FieldType::Long => Ok(FieldValue::Long(self.parse_int::<i64>()?)),
+            FieldType::ULong => Ok(FieldValue::ULong(self.parse_int::<u64>()?)),
+            FieldType::Float => Ok(FieldValue::Float(self.parse_float()?)),
+            FieldType::Bool => Ok(FieldValue::Bool(self.parse_bool()?)),
+            FieldType::DateTime => Ok(FieldValue::DateTime(self.parse_datetime()?)),
+            FieldType::String => Ok(FieldValue::String(self.parse_string()?)),
+            FieldType::ByteArray => Ok(FieldValue::ByteArray(self.parse_byte_array()?)),
+            FieldType::Array(element) => Ok(FieldValue::Array(self.parse_array(element)?)),
             FieldType::Object(schema) => {
-                Ok(FieldValue::Object(Box::new(self.parse_object(&**schema)?)))
+                Ok(FieldValue::Object(Box::new(self.parse_object(schema)?)))
             }
             FieldType::Enum(cases) => Ok(FieldValue::Enum(Box::new(self.parse_enum(cases)?))),
         }
     }
 
     fn skip_field(&mut self, field_type: &FieldType) -> Result<(), ParseError> {
-        match field_type {
-            FieldType::Int => self.ptr += size_of::<i32>(),
-            FieldType::UInt => self.ptr += size_of::<u32>(),
-            FieldType::Long => self.ptr += size_of::<i64>(),
-            FieldType::ULong => self.ptr += size_of::<u64>(),
-            FieldType::Float => self.ptr += size_of::<f64>(),
-            FieldType::Bool => self.ptr += size_of::<u8>(),
-            FieldType::DateTime => self.ptr += size_of::<i64>(),
-            FieldType::String => {
-                let length = self.parse_int::<FieldLength>() as usize;
-                self.ptr += length;
-            }
-            FieldType::ByteArray => {
-                let length = self.parse_int::<FieldLength>() as usize;
-                self.ptr += length;
-            }
-            FieldType::Array(_) => {
-                let length = self.parse_int::<FieldLength>() as usize;
-                self.ptr += length;
-            }
-            FieldType::Object(_) => {
-                let length = self.parse_int::<FieldLength>() as usize;
-                self.ptr += length;
-            }
+        let size = match field_type {
+            FieldType::Int => size_of::<i32>(),
+            FieldType::UInt => size_of::<u32>(),
+            FieldType::Long => size_of::<i64>(),
+            FieldType::ULong => size_of::<u64>(),
+            FieldType::Float => size_of::<f64>(),
+            FieldType::Bool => size_of::<u8>(),
+            FieldType::DateTime => size_of::<i64>(),
+            FieldType::String
+            | FieldType::ByteArray
+            | FieldType::Array(_)
+            | FieldType::Object(_) => self.parse_int::<FieldLength>()? as usize,
             FieldType::Enum(cases) => {
-                let case_id = self.parse_int::<CaseID>();
+                let case_id = self.parse_int::<CaseID>()?;
                 let enum_case = cases
                     .iter()
                     .find(|x| x.id == case_id)
@@ -136,50 +126,54 @@ impl ArchiveParser {
                     Option::None => (),
                     Option::Some(value_type) => self.skip_field(&value_type)?,
                 }
+                0
             }
-        }
+        };
+        self.take(size)?;
         Ok(())
     }
 
-    fn parse_int<T: PrimInt>(&mut self) -> T {
-        let size = size_of::<T>();
-        let bytes: T::Array = T::Array::from_slice(&self.data[self.ptr..(self.ptr + size)]);
-        let value = T::from_be_bytes(bytes);
-        self.ptr += size;
-        value
+    fn take(&mut self, size: usize) -> Result<&[u8], ParseError> {
+        let end = self
+            .ptr
+            .checked_add(size)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(ParseError::UnexpectedEnd)?;
+        let bytes = &self.data[self.ptr..end];
+        self.ptr = end;
+        Ok(bytes)
+    }
+
+    fn parse_int<T: PrimInt>(&mut self) -> Result<T, ParseError> {
+        let bytes: T::Array = T::Array::from_slice(self.take(size_of::<T>())?);
+        Ok(T::from_be_bytes(bytes))
     }
 
-    fn parse_float(&mut self) -> f64 {
-        let value = f64::from_be_bytes(self.data[self.ptr..(self.ptr + 8)].try_into().unwrap());
-        self.ptr += 8;
-        value
+    fn parse_float(&mut self) -> Result<f64, ParseError> {
+        Ok(f64::from_be_bytes(self.take(8)?.try_into().unwrap()))
     }
 
-    fn parse_bool(&mut self) -> bool {
-        let value = self.data[self.ptr];
-        self.ptr += 1;
-        value != 0
+    fn parse_bool(&mut self) -> Result<bool, ParseError> {
+        Ok(self.take(1)?[0] != 0)
     }
 
-    fn parse_datetime(&mut self) -> DateTime<Utc> {
-        let timestamp = self.parse_int::<i64>();
+    fn parse_datetime(&mut self) -> Result<DateTime<Utc>, ParseError> {
+        let timestamp = self.parse_int::<i64>()?;
         let naive_time = NaiveDateTime::from_timestamp(timestamp, 0);
-        DateTime::from_utc(naive_time, Utc)
+        Ok(DateTime::from_utc(naive_time, Utc))
     }
 
-    fn parse_byte_array(&mut self) -> Vec<u8> {
-        let length = self.parse_int::<FieldLength>() as usize;
-        let value = (&self.data[self.ptr..(self.ptr + length)]).to_vec();
-        self.ptr += length;
-        value
+    fn parse_byte_array(&mut self) -> Result<Vec<u8>, ParseError> {
+        let length = self.parse_int::<FieldLength>()? as usize;
+        Ok(self.take(length)?.to_vec())
     }
 
-    fn parse_string(&mut self) -> Result<String, std::string::FromUtf8Error> {
-        String::from_utf8(self.parse_byte_array())
+    fn parse_string(&mut self) -> Result<String, ParseError> {
+        String::from_utf8(self.parse_byte_array()?).or(Err(ParseError::InvalidString))
     }
 
     fn parse_array(&mut self, element: &FieldType) -> Result<Vec<FieldValue>, ParseError> {
-        let length = self.parse_int::<FieldLength>() as usize;
+        let length = self.parse_int::<FieldLength>()? as usize;
         let original_ptr = self.ptr;
         let mut values: Vec<FieldValue> = vec![];
         while self.ptr - original_ptr < length {
@@ -189,14 +183,14 @@ impl ArchiveParser {
     }
 
     fn parse_object(&mut self, schema: &Schema) -> Result<Document, ParseError> {
-        let bytes = self.parse_byte_array();
+        let bytes = self.parse_byte_array()?;
         let all_fields = schema.fields.iter().map(|f| f.id).collect();
         let mut parser = Self::new(schema.clone(), bytes, all_fields);
         parser.read_subdocument()
     }
 
     fn parse_enum(&mut self, cases: &[EnumCase]) -> Result<EnumValue, ParseError> {
-        let case_id = self.parse_int::<CaseID>();
+        let case_id = self.parse_int::<CaseID>()?;
         let enum_case = cases
             .iter()
             .find(|x| x.id == case_id)
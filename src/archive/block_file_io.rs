@@ -1,17 +1,35 @@
-use crate::util::{BlockLength, BlockPosition};
-use std::fs::File;
-use std::io::{BufReader, Error, Read, Seek, SeekFrom, Write};
+use super::index::relocate_node;
+use crate::archive::{FileHeader, Index, ParseError, WriteBatch, HEADER_LENGTH};
+use crate::util::{BlockLength, BlockPosition, Crc32c, DocumentID};
+use std::collections::HashMap;
+use std::fs::{rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
 /// A utility to read and write blocks of data to/from a storage file.
 ///
+/// The file begins with a [`FileHeader`], followed by blocks. Each block has the following format:
+///
+/// | Size     | Value                                   |
+/// |----------|-----------------------------------------|
+/// | 1        | Marker: `69` if live, `68` if removed,  |
+/// |          | `70` if an index node                   |
+/// | 8        | Length of the data                      |
+/// | 4        | CRC-32C checksum of the length and data |
+/// | variable | Data                                    |
+///
+/// The data of a live block is a [`Document`], beginning with its
+/// identifier. Index nodes hold the entries of an [`Index`].
+///
 /// An instance of a `BlockFileIO` manager is owned by the [`Backend`],
-/// which calls [`next`], [`read_at_position`], and [`write_block`] to
-/// read and write [`Document`]s.
+/// which calls [`next`] and [`read_at_position`] to read [`Document`]s,
+/// and [`apply`] to write them.
 ///
 /// [`Backend`]: crate::backend::Backend
+/// [`FileHeader`]: crate::archive::FileHeader
 /// [`next`]: BlockFileIO#method.next
 /// [`read_at_position`]: BlockFileIO#method.read_at_position
-/// [`write_block`]: BlockFileIO#method.write
+/// [`apply`]: BlockFileIO#method.apply
 /// [`Document`]: crate::schema::Document
 pub struct BlockFileIO {
     reader: BufReader<File>,
@@ -38,24 +56,37 @@ impl BlockFileIO {
             let mut buf = [0u8; 1];
             self.reader.read_exact(&mut buf)?;
             if buf[0] == 69 {
-                break Ok((self.reader.stream_position()? as usize, self.read_block()?));
-            } else if buf[0] == 68 {
+                let position = self.reader.stream_position()?;
+                break Ok((
+                    position as usize,
+                    Self::read_block(&mut self.reader, position)?,
+                ));
+            } else if buf[0] == 68 || buf[0] == 70 {
                 self.skip_block()?;
             } else if buf[0] != 0 {
-                break Err(Error::new(std::io::ErrorKind::InvalidData, "Invalid byte"));
+                break Err(Error::new(ErrorKind::InvalidData, "Invalid byte"));
             }
         }
     }
 
-    fn read_block(&mut self) -> Result<Vec<u8>, Error> {
-        let mut length_bytes = [0u8; 8];
-        self.reader.read_exact(&mut length_bytes)?;
-        let block_length = BlockLength::from_be_bytes(length_bytes);
+    fn read_block(reader: &mut impl Read, position: BlockPosition) -> Result<Vec<u8>, Error> {
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        let block_length = BlockLength::from_be_bytes(header[..8].try_into().unwrap());
+        let checksum = u32::from_be_bytes(header[8..].try_into().unwrap());
 
-        let mut handle = self.reader.by_ref().take(block_length);
+        let mut handle = reader.by_ref().take(block_length);
         let mut buffer: Vec<u8> = vec![];
         handle.read_to_end(&mut buffer)?;
 
+        if buffer.len() as BlockLength != block_length
+            || block_checksum(&header[..8], &buffer) != checksum
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                ParseError::ChecksumMismatch(position),
+            ));
+        }
         Ok(buffer)
     }
 
@@ -64,41 +95,402 @@ impl BlockFileIO {
         self.reader.read_exact(&mut length_bytes)?;
         let block_length = BlockLength::from_be_bytes(length_bytes);
 
-        self.reader.seek(SeekFrom::Current(block_length as i64))?;
+        self.reader
+            .seek(SeekFrom::Current(4 + block_length as i64))?;
         Ok(())
     }
 
     /// Read the data of a block at a certain position.
     pub fn read_at_position(&mut self, position: BlockPosition) -> Result<Vec<u8>, Error> {
         self.reader.seek(SeekFrom::Start(position))?;
-        self.read_block()
+        Self::read_block(&mut self.reader, position)
+    }
+
+    /// Read the data of an index node at a certain position.
+    ///
+    /// The node is read through the write handle, which is opened for
+    /// reading too, so that an [`Index`] can be read while it is
+    /// shared, and the position [`next`] reads from is kept.
+    ///
+    /// [`next`]: BlockFileIO#method.next
+    pub fn read_node(&self, position: BlockPosition) -> Result<Vec<u8>, Error> {
+        let mut file = &self.writer;
+        file.seek(SeekFrom::Start(position))?;
+        Self::read_block(&mut BufReader::new(file), position)
     }
 
-    /// Seek to the beginning of the file.
+    /// Seek to the first block in the file, after the header.
     pub fn reset_position(&mut self) -> Result<(), Error> {
+        self.reader.seek(SeekFrom::Start(HEADER_LENGTH))?;
+        Ok(())
+    }
+
+    /// Reads the bytes of the file header, or as many as the file
+    /// contains if it is shorter than a header.
+    pub fn read_header(&mut self) -> Result<Vec<u8>, Error> {
         self.reader.seek(SeekFrom::Start(0))?;
+        let mut bytes = Vec::new();
+        self.reader
+            .by_ref()
+            .take(HEADER_LENGTH)
+            .read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Walks every block in the file, checking that it is well-formed
+    /// and that its checksum matches its data.
+    pub fn check_integrity(&mut self) -> Result<Integrity, Error> {
+        let end = self.end_position()?;
+        let mut integrity = Integrity {
+            damaged_tail: None,
+            corrupted: Vec::new(),
+            live_bytes: 0,
+            holes: Vec::new(),
+            documents: Vec::new(),
+            index_nodes: Vec::new(),
+            indexes: Vec::new(),
+        };
+        self.reset_position()?;
+        loop {
+            let start = self.reader.stream_position()?;
+            let mut buf = [0u8; 1];
+            match self.reader.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            }
+            match buf[0] {
+                0 => {}
+                68..=70 => {
+                    let mut length_bytes = [0u8; 8];
+                    match self.reader.read_exact(&mut length_bytes) {
+                        Ok(()) => {}
+                        Err(error) if error.kind() == ErrorKind::UnexpectedEof => {
+                            integrity.damaged_tail = Some(start);
+                            break;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                    let block_length = BlockLength::from_be_bytes(length_bytes);
+                    let block_end = (start + BLOCK_HEADER_LENGTH).checked_add(block_length);
+                    if block_end.is_none_or(|block_end| block_end > end) {
+                        integrity.damaged_tail = Some(start);
+                        break;
+                    }
+                    if buf[0] == 68 {
+                        integrity
+                            .holes
+                            .push((start, BLOCK_HEADER_LENGTH + block_length));
+                        self.reader
+                            .seek(SeekFrom::Current(4 + block_length as i64))?;
+                        continue;
+                    }
+                    self.reader.seek(SeekFrom::Start(start + 1))?;
+                    match Self::read_block(&mut self.reader, start + 1) {
+                        Ok(data) if buf[0] == 70 => {
+                            let size = BLOCK_HEADER_LENGTH + block_length;
+                            integrity.index_nodes.push((start + 1, size));
+                            integrity.indexes.extend(Index::open(start + 1, &data));
+                        }
+                        Ok(data) => {
+                            if let Some(id) = data.get(..8) {
+                                integrity.documents.push((
+                                    DocumentID::from_be_bytes(id.try_into().unwrap()),
+                                    start + 1,
+                                ));
+                            }
+                        }
+                        Err(error) if error.kind() == ErrorKind::InvalidData => {
+                            if block_end == Some(end) {
+                                integrity.damaged_tail = Some(start);
+                                break;
+                            }
+                            integrity.corrupted.push(start + 1);
+                        }
+                        Err(error) => return Err(error),
+                    }
+                    integrity.live_bytes += BLOCK_HEADER_LENGTH + block_length;
+                }
+                _ => {
+                    integrity.damaged_tail = Some(start);
+                    break;
+                }
+            }
+        }
+        Ok(integrity)
+    }
+
+    /// Reads everything from `position` to the end of the file.
+    pub fn read_from(&mut self, position: BlockPosition) -> Result<Vec<u8>, Error> {
+        self.reader.seek(SeekFrom::Start(position))?;
+        let mut bytes = Vec::new();
+        self.reader.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads `length` bytes from `position`, or as many as the file
+    /// contains.
+    pub fn read_range(
+        &mut self,
+        position: BlockPosition,
+        length: BlockPosition,
+    ) -> Result<Vec<u8>, Error> {
+        self.reader.seek(SeekFrom::Start(position))?;
+        let mut bytes = Vec::new();
+        self.reader.by_ref().take(length).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Removes everything from `position` to the end of the file.
+    pub fn truncate(&mut self, position: BlockPosition) -> Result<(), Error> {
+        self.writer.set_len(position)?;
+        self.reset_position()
+    }
+
+    /// Returns the total size of the block at `position`, including
+    /// its marker, length and checksum.
+    pub fn block_size(&mut self, position: usize) -> Result<BlockPosition, Error> {
+        self.reader
+            .seek(SeekFrom::Start(position as BlockPosition))?;
+        let mut length_bytes = [0u8; 8];
+        self.reader.read_exact(&mut length_bytes)?;
+        Ok(BLOCK_HEADER_LENGTH + BlockLength::from_be_bytes(length_bytes))
+    }
+
+    /// Rewrites the file at `path`, which this manager reads and
+    /// writes, without its removed blocks.
+    ///
+    /// The live blocks and index nodes are copied to a new file, which
+    /// then replaces the original file, so that an interrupted
+    /// compaction leaves the original file intact. The positions of
+    /// the nodes' children are updated as they are copied. Returns a
+    /// map from the old position of each live block and index node to
+    /// its new position.
+    pub fn compact(&mut self, path: &str) -> Result<HashMap<usize, usize>, Error> {
+        let header = self.read_header()?;
+        let relocations = self.relocations()?;
+        let mut output = Self::create_rewrite(path)?;
+        output.write_all(&header)?;
+        self.copy_live_blocks(&mut output, &relocations)?;
+        self.replace(path, output)?;
+        Ok(relocations)
+    }
+
+    /// Returns a map from the position of each live block and index
+    /// node to its position once the removed blocks are discarded.
+    fn relocations(&mut self) -> Result<HashMap<usize, usize>, Error> {
+        let mut relocations = HashMap::new();
+        let mut new_position = HEADER_LENGTH;
+        self.reset_position()?;
+        loop {
+            let mut buf = [0u8; 1];
+            match self.reader.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            }
+            match buf[0] {
+                0 => {}
+                68 => self.skip_block()?,
+                69 | 70 => {
+                    let position = self.reader.stream_position()?;
+                    let mut length_bytes = [0u8; 8];
+                    self.reader.read_exact(&mut length_bytes)?;
+                    let block_length = BlockLength::from_be_bytes(length_bytes);
+                    self.reader
+                        .seek(SeekFrom::Current(4 + block_length as i64))?;
+                    relocations.insert(position as usize, new_position as usize + 1);
+                    new_position += BLOCK_HEADER_LENGTH + block_length;
+                }
+                _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid byte")),
+            }
+        }
+        Ok(relocations)
+    }
+
+    /// Rewrites the file at `path`, which this manager reads and
+    /// writes, from the format used before files had a header.
+    ///
+    /// Those files begin with their first block, and their blocks
+    /// have only a marker and a length before their data. Each live
+    /// block is given a checksum and prefixed with a document
+    /// identifier, assigned in order from `header`'s next document
+    /// identifier, which is advanced past them. Removed blocks are
+    /// discarded. The header is written to the new file before it
+    /// replaces the original file, so that an interrupted migration
+    /// leaves either the original file or the migrated file. Fails if
+    /// the last block extends past the end of the file.
+    pub fn migrate(&mut self, path: &str, header: &mut FileHeader) -> Result<(), Error> {
+        let mut output = Self::create_rewrite(path)?;
+        output.write_all(&[0; HEADER_LENGTH as usize])?;
+        self.reader.seek(SeekFrom::Start(0))?;
+        loop {
+            let mut buf = [0u8; 1];
+            match self.reader.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            }
+            match buf[0] {
+                0 => {}
+                68 => {
+                    let mut length_bytes = [0u8; 8];
+                    self.reader.read_exact(&mut length_bytes)?;
+                    let block_length = BlockLength::from_be_bytes(length_bytes);
+                    self.reader.seek(SeekFrom::Current(block_length as i64))?;
+                }
+                69 => {
+                    let mut length_bytes = [0u8; 8];
+                    self.reader.read_exact(&mut length_bytes)?;
+                    let block_length = BlockLength::from_be_bytes(length_bytes);
+                    let mut data = header.next_document_id.to_be_bytes().to_vec();
+                    data.resize(8 + block_length as usize, 0);
+                    self.reader.read_exact(&mut data[8..])?;
+                    header.next_document_id += 1;
+                    let length_bytes = (data.len() as BlockLength).to_be_bytes();
+                    output.write_all(&buf)?;
+                    output.write_all(&length_bytes)?;
+                    output.write_all(&block_checksum(&length_bytes, &data).to_be_bytes())?;
+                    output.write_all(&data)?;
+                }
+                _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid byte")),
+            }
+        }
+        output.seek(SeekFrom::Start(0))?;
+        output.write_all(&header.serialize())?;
+        self.replace(path, output)
+    }
+
+    /// Creates the file which a rewrite of the file at `path` is
+    /// written to.
+    fn create_rewrite(path: &str) -> Result<BufWriter<File>, Error> {
+        Ok(BufWriter::new(File::create(format!("{}.compact", path))?))
+    }
+
+    /// Copies the live blocks and index nodes to `output`, after its
+    /// header, moving the nodes' children to their positions in
+    /// `relocations`.
+    ///
+    /// Fails if an index node's checksum does not match its data,
+    /// rather than writing a new checksum for it.
+    fn copy_live_blocks(
+        &mut self,
+        output: &mut BufWriter<File>,
+        relocations: &HashMap<usize, usize>,
+    ) -> Result<(), Error> {
+        self.reset_position()?;
+        loop {
+            let mut buf = [0u8; 1];
+            match self.reader.read_exact(&mut buf) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            }
+            match buf[0] {
+                0 => {}
+                68 => self.skip_block()?,
+                69 | 70 => {
+                    let position = self.reader.stream_position()?;
+                    let mut length_bytes = [0u8; 8];
+                    self.reader.read_exact(&mut length_bytes)?;
+                    let block_length = BlockLength::from_be_bytes(length_bytes);
+                    let mut rest = vec![0u8; 4 + block_length as usize];
+                    self.reader.read_exact(&mut rest)?;
+                    if buf[0] == 70 {
+                        let checksum = u32::from_be_bytes(rest[..4].try_into().unwrap());
+                        let invalid = || {
+                            Error::new(
+                                ErrorKind::InvalidData,
+                                ParseError::ChecksumMismatch(position),
+                            )
+                        };
+                        if block_checksum(&length_bytes, &rest[4..]) != checksum {
+                            return Err(invalid());
+                        }
+                        let data = relocate_node(&rest[4..], relocations).ok_or_else(invalid)?;
+                        rest = block_checksum(&length_bytes, &data).to_be_bytes().to_vec();
+                        rest.extend_from_slice(&data);
+                    }
+                    output.write_all(&buf)?;
+                    output.write_all(&length_bytes)?;
+                    output.write_all(&rest)?;
+                }
+                _ => return Err(Error::new(ErrorKind::InvalidData, "Invalid byte")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the file at `path` with a completed rewrite.
+    fn replace(&mut self, path: &str, output: BufWriter<File>) -> Result<(), Error> {
+        output.into_inner()?.sync_all()?;
+        rename(format!("{}.compact", path), path)?;
+        if let Some(directory) = Path::new(path).parent().and_then(|p| File::open(p).ok()) {
+            directory.sync_all()?;
+        }
+        self.reader = BufReader::new(File::open(path)?);
+        self.writer = OpenOptions::new().read(true).write(true).open(path)?;
         Ok(())
     }
 
-    /// Write a block, appending it to the end of the file.
+    /// Returns the position of the end of the file.
+    pub fn end_position(&mut self) -> Result<BlockPosition, Error> {
+        self.writer.seek(SeekFrom::End(0))
+    }
+
+    /// Applies the writes in a [`WriteBatch`] to the file.
     ///
-    /// Returns the position at which the block was written.
-    pub fn write_block(&mut self, block: Vec<u8>) -> Result<usize, Error> {
-        let mut block = block;
-        self.writer.seek(SeekFrom::End(0))?;
-        let position = self.writer.stream_position()?;
-        let mut buf = vec![69u8];
-        buf.extend_from_slice(&(block.len() as BlockLength).to_be_bytes());
-        buf.append(&mut block);
-        self.writer.write_all(&buf)?;
-        Ok(position as usize + 1)
-    }
-
-    /// Marks a block as removed
-    pub fn remove_block(&mut self, position: usize) -> Result<(), Error> {
-        self.writer
-            .seek(SeekFrom::Start(position as BlockPosition - 1))?;
-        self.writer.write_all(&[68u8])?;
+    /// The reader's buffer is discarded, so that later reads see the
+    /// new data.
+    pub fn apply(&mut self, batch: &WriteBatch) -> Result<(), Error> {
+        for (position, bytes) in &batch.writes {
+            self.writer.seek(SeekFrom::Start(*position))?;
+            self.writer.write_all(bytes)?;
+        }
+        let position = self.reader.stream_position()?;
+        self.reader.seek(SeekFrom::Start(position))?;
         Ok(())
     }
+
+    /// Flushes all writes to non-volatile storage.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.writer.sync_data()
+    }
+}
+
+/// The result of [`BlockFileIO::check_integrity`].
+pub struct Integrity {
+    /// The position of the first block which is malformed or extends
+    /// past the end of the file, such as a block torn by an
+    /// interrupted write. Everything after this position is damaged.
+    pub damaged_tail: Option<BlockPosition>,
+    /// The positions of well-formed blocks whose checksum does not
+    /// match their data.
+    pub corrupted: Vec<BlockPosition>,
+    /// The total size of live blocks and index nodes before the
+    /// damaged tail.
+    pub live_bytes: u64,
+    /// The position and total size of each removed block before the
+    /// damaged tail.
+    pub holes: Vec<(BlockPosition, BlockPosition)>,
+    /// The identifier and position of each live block before the
+    /// damaged tail, read from the beginning of its data.
+    pub documents: Vec<(DocumentID, BlockPosition)>,
+    /// The position and total size of each index node before the
+    /// damaged tail whose checksum matches its data.
+    pub index_nodes: Vec<(BlockPosition, BlockPosition)>,
+    /// The indexes whose root nodes are among the index nodes.
+    pub indexes: Vec<Index>,
+}
+
+/// The length of the marker, length and checksum preceding the
+/// data of a block.
+pub const BLOCK_HEADER_LENGTH: BlockPosition = 13;
+
+/// Computes the checksum of a block from its length and data.
+pub(super) fn block_checksum(length_bytes: &[u8], data: &[u8]) -> u32 {
+    let mut crc = Crc32c::new();
+    crc.update(length_bytes);
+    crc.update(data);
+    crc.finish()
 }
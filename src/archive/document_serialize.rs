@@ -2,10 +2,10 @@ use crate::schema::{Document, FieldValue};
 use crate::util::FieldLength;
 
 impl Document {
-    /// Serializes this [`Document`] to archive data, with
-    /// a schema identifier.
+    /// Serializes this [`Document`] to archive data, with its
+    /// identifier and a schema identifier.
     pub fn serialize(&self) -> Vec<u8> {
-        let mut vector = Vec::<u8>::new();
+        let mut vector = self.id.unwrap_or_default().to_be_bytes().to_vec();
         let schema_bytes = self.schema.id.to_be_bytes();
         vector.extend_from_slice(&schema_bytes);
         self.serialize_fields(&mut vector);
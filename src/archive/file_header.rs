@@ -0,0 +1,153 @@
+use crate::schema::Schema;
+use crate::util::{BlockPosition, Crc32c, DocumentID};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::SystemTime;
+
+/// The header at the beginning of a storage file.
+///
+/// The header identifies a file as a SwiftDB storage file, and
+/// records the version of the format it was written in. It has the
+/// following format:
+///
+/// | Offset | Size | Value                                     |
+/// |--------|------|-------------------------------------------|
+/// | 0      | 8    | Magic number: `SWIFTDB\0`                 |
+/// | 8      | 2    | Format version                            |
+/// | 10     | 2    | Byte order mark: `0x0102`                 |
+/// | 12     | 8    | Creation time, as a Unix timestamp        |
+/// | 20     | 8    | Fingerprint of the schema definitions     |
+/// | 28     | 8    | The next document identifier to assign    |
+/// | 36     | 24   | Reserved                                  |
+/// | 60     | 4    | CRC-32C checksum of the preceding bytes   |
+///
+/// All integers in the storage file are big-endian; the byte order
+/// mark lets other tools verify this.
+pub struct FileHeader {
+    /// The format version the file was written in.
+    pub version: u16,
+    /// The time at which the file was created.
+    pub created: DateTime<Utc>,
+    /// A fingerprint of the [`Schema`] definitions the file was
+    /// last opened with. See [`FileHeader::fingerprint`].
+    pub schema_fingerprint: u64,
+    /// The identifier to assign to the next document created.
+    pub next_document_id: DocumentID,
+}
+
+/// The length of the file header. Blocks begin after the header.
+pub const HEADER_LENGTH: BlockPosition = 64;
+
+/// The version of the storage file format.
+pub const FORMAT_VERSION: u16 = 1;
+
+const MAGIC: &[u8; 8] = b"SWIFTDB\0";
+const BYTE_ORDER_MARK: u16 = 0x0102;
+
+impl FileHeader {
+    /// Creates a header for a new file, in the current format.
+    pub fn new(collections: &[Schema]) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            created: DateTime::<Utc>::from(SystemTime::now()),
+            schema_fingerprint: Self::fingerprint(collections),
+            next_document_id: 1,
+        }
+    }
+
+    /// Parses and validates a header.
+    ///
+    /// Fails if `bytes` is not a header produced by SwiftDB, or if
+    /// it was written in another version of the format.
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        if bytes.len() < HEADER_LENGTH as usize || &bytes[..8] != MAGIC {
+            return Err(FormatError::InvalidMagic);
+        }
+        let checksum = u32::from_be_bytes(bytes[60..64].try_into().unwrap());
+        if Crc32c::checksum(&bytes[..60]) != checksum {
+            return Err(FormatError::ChecksumMismatch);
+        }
+        let version = u16::from_be_bytes(bytes[8..10].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        if u16::from_be_bytes(bytes[10..12].try_into().unwrap()) != BYTE_ORDER_MARK {
+            return Err(FormatError::ByteOrder);
+        }
+        let timestamp = i64::from_be_bytes(bytes[12..20].try_into().unwrap());
+        let created = NaiveDateTime::from_timestamp_opt(timestamp, 0)
+            .map(|created| DateTime::from_utc(created, Utc))
+            .ok_or(FormatError::InvalidTimestamp)?;
+        Ok(Self {
+            version,
+            created,
+            schema_fingerprint: u64::from_be_bytes(bytes[20..28].try_into().unwrap()),
+            next_document_id: DocumentID::from_be_bytes(bytes[28..36].try_into().unwrap()),
+        })
+    }
+
+    /// Serializes this header.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&self.version.to_be_bytes());
+        bytes.extend_from_slice(&BYTE_ORDER_MARK.to_be_bytes());
+        bytes.extend_from_slice(&self.created.timestamp().to_be_bytes());
+        bytes.extend_from_slice(&self.schema_fingerprint.to_be_bytes());
+        bytes.extend_from_slice(&self.next_document_id.to_be_bytes());
+        bytes.resize(60, 0);
+        bytes.extend_from_slice(&Crc32c::checksum(&bytes).to_be_bytes());
+        bytes
+    }
+
+    /// Computes a fingerprint of a list of [`Schema`] definitions.
+    ///
+    /// This is a 64-bit FNV-1a hash of the definitions' JSON
+    /// serialization, so that it is stable between builds.
+    pub fn fingerprint(collections: &[Schema]) -> u64 {
+        let json = serde_json::to_vec(collections).unwrap_or_default();
+        json.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+}
+
+/// An error raised when a storage file's [`FileHeader`] is invalid.
+///
+/// This error gets wrapped in a [`LifecycleError`].
+///
+/// [`LifecycleError`]: crate::database::LifecycleError
+#[derive(Debug)]
+pub enum FormatError {
+    InvalidMagic,
+    ChecksumMismatch,
+    UnsupportedVersion(u16),
+    ByteOrder,
+    InvalidTimestamp,
+}
+
+impl Display for FormatError {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            FormatError::InvalidMagic => {
+                write!(formatter, "The data file is not a SwiftDB data file")
+            }
+            FormatError::ChecksumMismatch => {
+                write!(formatter, "The data file's header is corrupted")
+            }
+            FormatError::UnsupportedVersion(version) => write!(
+                formatter,
+                "The data file is in format version {}, but only version {} is supported",
+                version, FORMAT_VERSION
+            ),
+            FormatError::ByteOrder => {
+                write!(formatter, "The data file has an unsupported byte order")
+            }
+            FormatError::InvalidTimestamp => {
+                write!(formatter, "The data file has an invalid creation time")
+            }
+        }
+    }
+}
+
+impl Error for FormatError {}
@@ -0,0 +1,63 @@
+use super::block_file_io::BLOCK_HEADER_LENGTH;
+use crate::util::BlockPosition;
+use std::collections::{BTreeSet, HashMap};
+
+/// A map of the removed blocks in a storage file, which can be
+/// reused for new blocks.
+///
+/// Each hole is identified by the position of its marker and its
+/// total size, including the marker, length and checksum. A new
+/// block is placed in the smallest hole it fits in. If the hole is
+/// larger than the block, the rest of the hole is split off into a
+/// smaller removed block, so a hole can only hold a block which is
+/// exactly its size, or which leaves room for another block header.
+#[derive(Default)]
+pub struct FreeSpaceMap {
+    by_size: BTreeSet<(BlockPosition, BlockPosition)>,
+    positions: HashMap<BlockPosition, BlockPosition>,
+}
+
+impl FreeSpaceMap {
+    /// Creates an empty [`FreeSpaceMap`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a hole of `size` bytes at `position`.
+    pub fn insert(&mut self, position: BlockPosition, size: BlockPosition) {
+        if let Some(previous) = self.positions.insert(position, size) {
+            self.by_size.remove(&(previous, position));
+        }
+        self.by_size.insert((size, position));
+    }
+
+    /// Removes the hole at `position`, returning its size, or `None`
+    /// if there is no hole at `position`.
+    pub fn remove(&mut self, position: BlockPosition) -> Option<BlockPosition> {
+        let size = self.positions.remove(&position)?;
+        self.by_size.remove(&(size, position));
+        Some(size)
+    }
+
+    /// Finds and removes a hole which can hold a block of `size`
+    /// bytes.
+    ///
+    /// Returns the position of the hole and its size, or `None` if
+    /// no hole is suitable, in which case the block should be
+    /// appended to the end of the file.
+    pub fn allocate(&mut self, size: BlockPosition) -> Option<(BlockPosition, BlockPosition)> {
+        let hole = *self
+            .by_size
+            .range((size, 0)..)
+            .find(|(hole, _)| *hole == size || *hole >= size + BLOCK_HEADER_LENGTH)?;
+        self.by_size.remove(&hole);
+        self.positions.remove(&hole.1);
+        Some((hole.1, hole.0))
+    }
+
+    /// Removes every hole, such as after the file is compacted.
+    pub fn clear(&mut self) {
+        self.by_size.clear();
+        self.positions.clear();
+    }
+}
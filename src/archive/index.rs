@@ -0,0 +1,579 @@
+use crate::archive::{BlockFileIO, ParseError};
+use crate::schema::{Document, FieldInstance, FieldValue};
+use crate::util::{BlockPosition, DocumentID, FieldID, SchemaID};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, ErrorKind};
+use std::ops::{Bound, RangeBounds};
+
+/// A secondary index on one or more fields of a collection, which
+/// maps the values of the fields to the identifiers of the
+/// documents which have them.
+///
+/// Each document's key is the encoding of its indexed field values,
+/// in order (see [`encode_key`]). Keys are encoded so that comparing
+/// their bytes orders them by their values, so the documents whose
+/// values are in a range are found with a range of keys.
+///
+/// The index is stored in the storage file as a B+ tree of entries,
+/// each a key and a document identifier. Its nodes are blocks with
+/// the marker `70` (see [`BlockFileIO`]), whose data is always
+/// [`NODE_SIZE`] bytes long, so that a node can be rewritten in place.
+/// The root node never moves, except when the file is compacted, and
+/// records the index's definition and number of entries, so that the
+/// index is found when the file is opened. Nodes are not merged when
+/// entries are removed.
+///
+/// [`Index::insert`] and [`Index::remove`] collect the changed nodes,
+/// which the [`Backend`] takes with [`Index::take_changes`] and writes
+/// along with the documents they index.
+///
+/// [`Backend`]: crate::backend::Backend
+pub struct Index {
+    /// The identifier of the indexed collection.
+    pub collection: SchemaID,
+    /// The identifiers of the fields the index is keyed on.
+    pub fields: Vec<FieldID>,
+    /// Whether no two documents may have the same key.
+    pub unique: bool,
+    /// The position of the root node in the storage file.
+    pub root: BlockPosition,
+    /// The number of entries in the index.
+    pub len: u64,
+    /// The nodes changed since the changes were last taken, by
+    /// position. See [`NEW_NODE`].
+    changed: BTreeMap<BlockPosition, Node>,
+    /// The number of new nodes among the changed nodes.
+    new_nodes: u64,
+}
+
+/// The size of the data of an index node.
+pub const NODE_SIZE: BlockPosition = 4096;
+
+/// The length of the longest key an index can hold, so that a node
+/// always has room for several entries.
+pub const MAX_KEY_LENGTH: usize = 1024;
+
+/// The most fields an index can be keyed on, so that the definition
+/// in the root node leaves room for its entries.
+pub const MAX_INDEX_FIELDS: usize = 64;
+
+/// Marks the position of a new node which hasn't been given a
+/// position in the storage file yet. The rest of the position is the
+/// node's number among the new nodes.
+const NEW_NODE: BlockPosition = 1 << 63;
+
+const INTERNAL: u8 = 1;
+const ROOT: u8 = 2;
+
+type Entry = (Vec<u8>, DocumentID);
+
+/// The internal nodes above a node, from the root down, as the
+/// position of each node, the node and the number of the child taken.
+type Path = Vec<(BlockPosition, Node, usize)>;
+
+/// A node of an [`Index`].
+///
+/// A leaf holds entries, in order. An internal node holds the
+/// positions of its children, and between each pair of children the
+/// smallest entry the right child held when it was split off, so that
+/// each child holds the entries from the entry before it up to the
+/// entry after it. A node's data has the following format, followed
+/// by zeros up to [`NODE_SIZE`]:
+///
+/// | Size     | Value                                                |
+/// |----------|------------------------------------------------------|
+/// | 1        | Flags: `1` if the node is internal, `2` if the root  |
+/// | variable | The root's definition (see [`Definition`])           |
+/// | 2        | Number of entries                                    |
+/// | variable | Entries: a 2-byte key length, the key, and an ID     |
+/// | variable | An internal node's children, one more than its entries |
+#[derive(Clone, Default)]
+struct Node {
+    definition: Option<Definition>,
+    entries: Vec<Entry>,
+    children: Vec<BlockPosition>,
+}
+
+/// The definition of an [`Index`], stored in its root node: the
+/// collection identifier, the number of fields and their
+/// identifiers, then the number of entries.
+#[derive(Clone)]
+struct Definition {
+    collection: SchemaID,
+    fields: Vec<FieldID>,
+    len: u64,
+}
+
+impl Index {
+    /// Creates an [`Index`] which hasn't been stored yet. See
+    /// [`Index::create`].
+    pub fn new(collection: SchemaID, fields: Vec<FieldID>) -> Self {
+        Self {
+            collection,
+            fields,
+            unique: false,
+            root: 0,
+            len: 0,
+            changed: BTreeMap::new(),
+            new_nodes: 0,
+        }
+    }
+
+    /// Opens the index whose root node's data is `data` at
+    /// `position`, or returns `None` if `data` isn't a root node.
+    pub fn open(position: BlockPosition, data: &[u8]) -> Option<Self> {
+        let definition = Node::parse(data)?.definition?;
+        let mut index = Self::new(definition.collection, definition.fields);
+        index.root = position;
+        index.len = definition.len;
+        Some(index)
+    }
+
+    /// Starts a new, empty tree for the index, discarding any
+    /// entries it had.
+    pub fn create(&mut self) {
+        self.changed.clear();
+        self.new_nodes = 0;
+        self.len = 0;
+        self.root = self.new_node();
+        self.changed.insert(
+            self.root,
+            Node {
+                definition: Some(self.definition()),
+                ..Node::default()
+            },
+        );
+    }
+
+    /// Returns the key of a [`Document`] in this index, or `None` if
+    /// the document is in another collection, or is missing one of
+    /// the indexed fields.
+    pub fn key(&self, document: &Document) -> Option<Vec<u8>> {
+        if document.schema.id != self.collection {
+            return None;
+        }
+        self.fields_key(&document.fields)
+    }
+
+    /// Returns the key of a document in this index's collection with
+    /// the given fields, or `None` if one of the indexed fields is
+    /// missing.
+    pub fn fields_key(&self, fields: &[FieldInstance]) -> Option<Vec<u8>> {
+        let mut key = Vec::new();
+        for field in &self.fields {
+            let instance = fields.iter().find(|f| f.id == *field)?;
+            if !encode_key(&instance.value, &mut key) {
+                return None;
+            }
+        }
+        Some(key)
+    }
+
+    /// Adds a document to the index, which must not be longer than
+    /// [`MAX_KEY_LENGTH`].
+    ///
+    /// A node which no longer fits is split in two, adding the new
+    /// node to its parent. The root is split by moving its entries
+    /// into two new nodes, so that it stays in place.
+    pub fn insert(&mut self, io: &BlockFileIO, key: Vec<u8>, id: DocumentID) -> Result<(), Error> {
+        let entry = (key, id);
+        let (mut path, mut position, mut node) = self.find_leaf(io, &entry)?;
+        match node.entries.binary_search(&entry) {
+            Ok(_) => return Ok(()),
+            Err(i) => node.entries.insert(i, entry),
+        }
+        self.len += 1;
+        while node.size() > NODE_SIZE as usize {
+            let definition = node.definition.take();
+            let (left, separator, right) = node.split();
+            let right_position = self.new_node();
+            self.changed.insert(right_position, right);
+            let Some((parent_position, mut parent, i)) = path.pop() else {
+                let left_position = self.new_node();
+                self.changed.insert(left_position, left);
+                node = Node {
+                    definition,
+                    entries: vec![separator],
+                    children: vec![left_position, right_position],
+                };
+                break;
+            };
+            self.changed.insert(position, left);
+            parent.entries.insert(i, separator);
+            parent.children.insert(i + 1, right_position);
+            position = parent_position;
+            node = parent;
+        }
+        self.changed.insert(position, node);
+        self.touch_root(io)
+    }
+
+    /// Removes a document from the index.
+    pub fn remove(&mut self, io: &BlockFileIO, key: &[u8], id: DocumentID) -> Result<(), Error> {
+        let entry = (key.to_vec(), id);
+        let (_, position, mut node) = self.find_leaf(io, &entry)?;
+        if let Ok(i) = node.entries.binary_search(&entry) {
+            node.entries.remove(i);
+            self.changed.insert(position, node);
+            self.len -= 1;
+            self.touch_root(io)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the identifiers of the documents whose keys are
+    /// within a range, in key order.
+    pub fn range(
+        &self,
+        io: &BlockFileIO,
+        lower: Bound<Vec<u8>>,
+        upper: Bound<Vec<u8>>,
+    ) -> Result<Vec<DocumentID>, Error> {
+        let lower = match lower {
+            Bound::Included(key) => Bound::Included((key, DocumentID::MIN)),
+            Bound::Excluded(key) => Bound::Excluded((key, DocumentID::MAX)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let upper = match upper {
+            Bound::Included(key) => Bound::Included((key, DocumentID::MAX)),
+            Bound::Excluded(key) => Bound::Excluded((key, DocumentID::MIN)),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        let mut ids = vec![];
+        self.collect(io, self.root, (lower.as_ref(), upper.as_ref()), &mut ids)?;
+        Ok(ids)
+    }
+
+    /// Adds the identifiers of the documents in the subtree at
+    /// `position` whose entries are within `bounds` to `ids`.
+    fn collect(
+        &self,
+        io: &BlockFileIO,
+        position: BlockPosition,
+        bounds: (Bound<&Entry>, Bound<&Entry>),
+        ids: &mut Vec<DocumentID>,
+    ) -> Result<(), Error> {
+        let node = self.node(io, position)?;
+        if node.children.is_empty() {
+            ids.extend(
+                node.entries
+                    .iter()
+                    .filter(|entry| bounds.contains(*entry))
+                    .map(|(_, id)| *id),
+            );
+            return Ok(());
+        }
+        for (i, child) in node.children.iter().enumerate() {
+            let below_lower = node.entries.get(i).is_some_and(|next| match bounds.0 {
+                Bound::Included(lower) | Bound::Excluded(lower) => next <= lower,
+                Bound::Unbounded => false,
+            });
+            if below_lower {
+                continue;
+            }
+            let above_upper = i > 0 && {
+                let previous = &node.entries[i - 1];
+                match bounds.1 {
+                    Bound::Included(upper) => previous > upper,
+                    Bound::Excluded(upper) => previous >= upper,
+                    Bound::Unbounded => false,
+                }
+            };
+            if above_upper {
+                break;
+            }
+            self.collect(io, *child, bounds, ids)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of new nodes among the changed nodes, each
+    /// of which must be given a position in the storage file.
+    pub fn new_nodes(&self) -> usize {
+        self.new_nodes as usize
+    }
+
+    /// Returns the position and data of each node changed since the
+    /// changes were last taken, giving the new nodes the positions in
+    /// `positions`, in order.
+    pub fn take_changes(&mut self, positions: &[BlockPosition]) -> Vec<(BlockPosition, Vec<u8>)> {
+        let locate = |position: BlockPosition| match position & NEW_NODE {
+            0 => position,
+            _ => positions[(position ^ NEW_NODE) as usize],
+        };
+        self.root = locate(self.root);
+        let definition = self.definition();
+        let changed = std::mem::take(&mut self.changed);
+        self.new_nodes = 0;
+        changed
+            .into_iter()
+            .map(|(position, mut node)| {
+                let position = locate(position);
+                for child in &mut node.children {
+                    *child = locate(*child);
+                }
+                if position == self.root {
+                    node.definition = Some(definition.clone());
+                }
+                (position, node.serialize())
+            })
+            .collect()
+    }
+
+    /// Forgets the nodes changed since the changes were last taken.
+    pub fn discard_changes(&mut self) {
+        self.changed.clear();
+        self.new_nodes = 0;
+    }
+
+    fn definition(&self) -> Definition {
+        Definition {
+            collection: self.collection,
+            fields: self.fields.clone(),
+            len: self.len,
+        }
+    }
+
+    fn new_node(&mut self) -> BlockPosition {
+        self.new_nodes += 1;
+        NEW_NODE | (self.new_nodes - 1)
+    }
+
+    /// Reads the node at `position`, including changes to it.
+    fn node(&self, io: &BlockFileIO, position: BlockPosition) -> Result<Node, Error> {
+        if let Some(node) = self.changed.get(&position) {
+            return Ok(node.clone());
+        }
+        let data = io.read_node(position)?;
+        Node::parse(&data)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, ParseError::UnexpectedEnd))
+    }
+
+    /// Finds the leaf which holds `entry`, or would hold it. Returns
+    /// the path to it, and the leaf's position and the leaf.
+    fn find_leaf(
+        &self,
+        io: &BlockFileIO,
+        entry: &Entry,
+    ) -> Result<(Path, BlockPosition, Node), Error> {
+        let mut path = vec![];
+        let mut position = self.root;
+        let mut node = self.node(io, position)?;
+        while !node.children.is_empty() {
+            let i = node.entries.partition_point(|separator| separator <= entry);
+            let child = node.children[i];
+            path.push((position, node, i));
+            position = child;
+            node = self.node(io, position)?;
+        }
+        Ok((path, position, node))
+    }
+
+    /// Marks the root as changed, so that its number of entries is
+    /// written.
+    fn touch_root(&mut self, io: &BlockFileIO) -> Result<(), Error> {
+        if !self.changed.contains_key(&self.root) {
+            let root = self.node(io, self.root)?;
+            self.changed.insert(self.root, root);
+        }
+        Ok(())
+    }
+}
+
+impl Node {
+    /// Returns the length of the node's data, before it is padded.
+    fn size(&self) -> usize {
+        let definition = self
+            .definition
+            .as_ref()
+            .map_or(0, |d| 8 + 2 + 2 * d.fields.len() + 8);
+        let entries: usize = self.entries.iter().map(entry_size).sum();
+        1 + definition + 2 + entries + 8 * self.children.len()
+    }
+
+    /// Splits a node which is too large into two, returning the left
+    /// node, the smallest entry in the right node, and the right node.
+    ///
+    /// The entries are divided by size rather than number, so that
+    /// both nodes fit whatever the lengths of their keys. An internal
+    /// node's middle entry is moved up to the parent, rather than kept
+    /// in either node.
+    fn split(mut self) -> (Node, Entry, Node) {
+        let half: usize = self.entries.iter().map(entry_size).sum::<usize>() / 2;
+        let mut middle = 0;
+        let mut size = 0;
+        while middle + 1 < self.entries.len() && size < half {
+            size += entry_size(&self.entries[middle]);
+            middle += 1;
+        }
+        let middle = middle.max(1);
+        let mut right_entries = self.entries.split_off(middle);
+        let (separator, right_children) = if self.children.is_empty() {
+            (right_entries[0].clone(), vec![])
+        } else {
+            (right_entries.remove(0), self.children.split_off(middle + 1))
+        };
+        let right = Node {
+            definition: None,
+            entries: right_entries,
+            children: right_children,
+        };
+        (self, separator, right)
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if !self.children.is_empty() {
+            flags |= INTERNAL;
+        }
+        if self.definition.is_some() {
+            flags |= ROOT;
+        }
+        let mut data = vec![flags];
+        if let Some(definition) = &self.definition {
+            data.extend_from_slice(&definition.collection.to_be_bytes());
+            data.extend_from_slice(&(definition.fields.len() as u16).to_be_bytes());
+            for field in &definition.fields {
+                data.extend_from_slice(&field.to_be_bytes());
+            }
+            data.extend_from_slice(&definition.len.to_be_bytes());
+        }
+        data.extend_from_slice(&(self.entries.len() as u16).to_be_bytes());
+        for (key, id) in &self.entries {
+            data.extend_from_slice(&(key.len() as u16).to_be_bytes());
+            data.extend_from_slice(key);
+            data.extend_from_slice(&id.to_be_bytes());
+        }
+        for child in &self.children {
+            data.extend_from_slice(&child.to_be_bytes());
+        }
+        data.resize(NODE_SIZE as usize, 0);
+        data
+    }
+
+    fn parse(mut data: &[u8]) -> Option<Self> {
+        let [flags] = take(&mut data)?;
+        let definition = if flags & ROOT != 0 {
+            let collection = SchemaID::from_be_bytes(take(&mut data)?);
+            let count = u16::from_be_bytes(take(&mut data)?);
+            let fields = (0..count)
+                .map(|_| take(&mut data).map(FieldID::from_be_bytes))
+                .collect::<Option<Vec<FieldID>>>()?;
+            let len = u64::from_be_bytes(take(&mut data)?);
+            Some(Definition {
+                collection,
+                fields,
+                len,
+            })
+        } else {
+            None
+        };
+        let count = u16::from_be_bytes(take(&mut data)?);
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let length = u16::from_be_bytes(take(&mut data)?) as usize;
+            let key = data.get(..length)?.to_vec();
+            data = &data[length..];
+            entries.push((key, DocumentID::from_be_bytes(take(&mut data)?)));
+        }
+        let children = if flags & INTERNAL != 0 {
+            (0..=count)
+                .map(|_| take(&mut data).map(BlockPosition::from_be_bytes))
+                .collect::<Option<Vec<BlockPosition>>>()?
+        } else {
+            vec![]
+        };
+        Some(Self {
+            definition,
+            entries,
+            children,
+        })
+    }
+}
+
+fn entry_size((key, _): &Entry) -> usize {
+    2 + key.len() + 8
+}
+
+fn take<const N: usize>(data: &mut &[u8]) -> Option<[u8; N]> {
+    let (bytes, rest) = data.split_first_chunk::<N>()?;
+    *data = rest;
+    Some(*bytes)
+}
+
+/// Rewrites the positions of an index node's children, after the
+/// storage file is compacted, or returns `None` if `data` isn't a
+/// node, or a child wasn't relocated.
+pub(super) fn relocate_node(data: &[u8], relocations: &HashMap<usize, usize>) -> Option<Vec<u8>> {
+    let mut node = Node::parse(data)?;
+    for child in &mut node.children {
+        *child = *relocations.get(&(*child as usize))? as BlockPosition;
+    }
+    Some(node.serialize())
+}
+
+/// Appends the encoding of a field value to an index key, returning
+/// `false` if values of its type can't be indexed.
+///
+/// Numbers are encoded in big-endian order, with the sign bit
+/// flipped so that negative numbers sort first; negative floats
+/// have every bit flipped, so that they sort in reverse. Strings and
+/// byte arrays have each zero byte followed by `0xFF`, and end with
+/// two zero bytes, so that no encoding is a prefix of another.
+pub fn encode_key(value: &FieldValue, key: &mut Vec<u8>) -> bool {
+    match value {
+        FieldValue::Int(i) => key.extend_from_slice(&(*i as u32 ^ 1 << 31).to_be_bytes()),
+        FieldValue::UInt(i) => key.extend_from_slice(&i.to_be_bytes()),
+        FieldValue::Long(i) => key.extend_from_slice(&(*i as u64 ^ 1 << 63).to_be_bytes()),
+        FieldValue::ULong(i) => key.extend_from_slice(&i.to_be_bytes()),
+        FieldValue::Float(f) => {
+            // Negative zero is equal to zero, so it has the same key
+            let bits = if *f == 0.0 { 0 } else { f.to_bits() };
+            let bits = if bits >> 63 == 1 {
+                !bits
+            } else {
+                bits ^ 1 << 63
+            };
+            key.extend_from_slice(&bits.to_be_bytes());
+        }
+        FieldValue::Bool(b) => key.push(*b as u8),
+        FieldValue::DateTime(d) => {
+            key.extend_from_slice(&(d.timestamp() as u64 ^ 1 << 63).to_be_bytes());
+            key.extend_from_slice(&d.timestamp_subsec_nanos().to_be_bytes());
+        }
+        FieldValue::String(s) => encode_bytes(s.as_bytes(), key),
+        FieldValue::ByteArray(b) => encode_bytes(b, key),
+        FieldValue::Array(_) | FieldValue::Object(_) | FieldValue::Enum(_) => return false,
+    }
+    true
+}
+
+fn encode_bytes(bytes: &[u8], key: &mut Vec<u8>) {
+    encode_prefix(bytes, key);
+    key.extend_from_slice(&[0, 0]);
+}
+
+/// Appends the encoding of the start of a string or byte array to an
+/// index key, which every value beginning with it has as a prefix of
+/// its encoding.
+pub fn encode_prefix(bytes: &[u8], key: &mut Vec<u8>) {
+    for byte in bytes {
+        key.push(*byte);
+        if *byte == 0 {
+            key.push(0xFF);
+        }
+    }
+}
+
+/// Returns the smallest key which is greater than every key
+/// beginning with `prefix`, or `None` if there is no such key.
+pub fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xFF {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
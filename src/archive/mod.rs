@@ -7,8 +7,22 @@
 mod archive_parser;
 mod block_file_io;
 mod document_serialize;
+mod file_header;
+mod free_space_map;
+mod index;
 mod parse_error;
+#[cfg(test)]
+mod tests;
+mod write_ahead_log;
+mod write_batch;
 
 pub use archive_parser::ArchiveParser;
-pub use block_file_io::BlockFileIO;
+pub use block_file_io::{BlockFileIO, BLOCK_HEADER_LENGTH};
+pub use file_header::{FileHeader, FormatError, HEADER_LENGTH};
+pub use free_space_map::FreeSpaceMap;
+pub use index::{
+    encode_key, encode_prefix, prefix_end, Index, MAX_INDEX_FIELDS, MAX_KEY_LENGTH, NODE_SIZE,
+};
 pub use parse_error::ParseError;
+pub use write_ahead_log::WriteAheadLog;
+pub use write_batch::WriteBatch;
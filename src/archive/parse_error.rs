@@ -1,11 +1,14 @@
+use crate::util::BlockPosition;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 
-/// An error raised during parsing with an [`ArchiveParser`].
+/// An error raised during parsing with an [`ArchiveParser`], or
+/// while reading a block with a [`BlockFileIO`] manager.
 ///
 /// This error gets wrapped in an [`OperationError`].
 ///
 /// [`ArchiveParser`]: crate::archive::ArchiveParser
+/// [`BlockFileIO`]: crate::archive::BlockFileIO
 /// [`OperationError`]: crate::backend::OperationError
 #[derive(Debug)]
 pub enum ParseError {
@@ -13,6 +16,8 @@ pub enum ParseError {
     UnknownFieldIdentifier,
     UnknownCaseIdentifier,
     InvalidString,
+    UnexpectedEnd,
+    ChecksumMismatch(BlockPosition),
 }
 
 impl Display for ParseError {
@@ -22,6 +27,14 @@ impl Display for ParseError {
             ParseError::UnknownFieldIdentifier => "Unknown field in archive",
             ParseError::UnknownCaseIdentifier => "Unkown enum case in archive",
             ParseError::InvalidString => "Invalid UTF-8 string in archive",
+            ParseError::UnexpectedEnd => "Unexpected end of data in archive",
+            ParseError::ChecksumMismatch(position) => {
+                return write!(
+                    formatter,
+                    "Checksum mismatch in block at offset {}, the data file may be corrupted",
+                    position
+                )
+            }
         };
         write!(formatter, "{}", string)
     }
@@ -0,0 +1,340 @@
+use super::file_header::FORMAT_VERSION;
+use super::*;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+struct Cleanup(String);
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn test_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("swift-db-archive-{}-{}", name, std::process::id()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn open_io(path: &str) -> BlockFileIO {
+    File::create(path)
+        .unwrap()
+        .write_all(&FileHeader::new(&[]).serialize())
+        .unwrap();
+    BlockFileIO::new(
+        File::open(path).unwrap(),
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap(),
+    )
+}
+
+#[test]
+fn write_ahead_log_round_trip() {
+    let path = test_path("wal-round-trip");
+    let _c = Cleanup(path.clone());
+    let mut log = WriteAheadLog::open(&path).expect("Log open failed");
+    let mut batch = WriteBatch::new(0);
+    let position = batch.append_block(vec![1, 2, 3]);
+    batch.remove_block(position);
+    log.append(&batch).expect("Log append failed");
+    let recovered = log.recover().expect("Log recovery failed");
+    assert_eq!(recovered.len(), 1);
+    assert_eq!(recovered[0].writes, batch.writes);
+    log.clear().expect("Log clear failed");
+    assert!(log.recover().expect("Log recovery failed").is_empty());
+}
+
+#[test]
+fn write_ahead_log_discards_torn_record() {
+    let path = test_path("wal-torn");
+    let _c = Cleanup(path.clone());
+    let mut log = WriteAheadLog::open(&path).expect("Log open failed");
+    let mut batch = WriteBatch::new(0);
+    batch.append_block(vec![1, 2, 3]);
+    log.append(&batch).expect("Log append failed");
+    log.append(&batch).expect("Log append failed");
+    let length = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(length - 3)
+        .unwrap();
+    let recovered = log.recover().expect("Log recovery failed");
+    assert_eq!(recovered.len(), 1);
+}
+
+#[test]
+fn apply_write_batch() {
+    let path = test_path("apply");
+    let _c = Cleanup(path.clone());
+    let mut io = open_io(&path);
+    let mut batch = WriteBatch::new(io.end_position().unwrap());
+    let first = batch.append_block(vec![1, 2, 3]);
+    let second = batch.append_block(vec![4, 5]);
+    io.apply(&batch).expect("Apply failed");
+    io.reset_position().unwrap();
+    assert_eq!(io.next().unwrap(), (first, vec![1, 2, 3]));
+    assert_eq!(io.next().unwrap(), (second, vec![4, 5]));
+
+    let mut batch = WriteBatch::new(io.end_position().unwrap());
+    batch.remove_block(first);
+    io.apply(&batch).expect("Apply failed");
+    io.reset_position().unwrap();
+    assert_eq!(io.next().unwrap(), (second, vec![4, 5]));
+}
+
+#[test]
+fn find_damaged_blocks() {
+    let path = test_path("damage");
+    let _c = Cleanup(path.clone());
+    let mut io = open_io(&path);
+    let mut batch = WriteBatch::new(HEADER_LENGTH);
+    batch.append_block(vec![1, 2, 3]);
+    let second = batch.append_block(vec![4, 5]);
+    io.apply(&batch).unwrap();
+    assert_eq!(io.check_integrity().unwrap().damaged_tail, None);
+
+    let end = io.end_position().unwrap();
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[69u8, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0, 0, 0, 1])
+        .unwrap();
+    assert_eq!(io.check_integrity().unwrap().damaged_tail, Some(end));
+    io.truncate(end).unwrap();
+    assert_eq!(io.check_integrity().unwrap().damaged_tail, None);
+
+    file.write_all(&[12u8]).unwrap();
+    assert_eq!(io.check_integrity().unwrap().damaged_tail, Some(end));
+    io.truncate(second as u64 + 2).unwrap();
+    assert_eq!(
+        io.check_integrity().unwrap().damaged_tail,
+        Some(second as u64 - 1)
+    );
+}
+
+#[test]
+fn crc32c_check_value() {
+    assert_eq!(crate::util::Crc32c::checksum(b"123456789"), 0xE306_9283);
+}
+
+#[test]
+fn detect_corrupted_block() {
+    let path = test_path("corrupted");
+    let _c = Cleanup(path.clone());
+    let mut io = open_io(&path);
+    let mut batch = WriteBatch::new(HEADER_LENGTH);
+    let first = batch.append_block(vec![1, 2, 3]);
+    let second = batch.append_block(vec![4, 5]);
+    io.apply(&batch).unwrap();
+
+    let mut batch = WriteBatch::new(0);
+    batch.writes.push((first as u64 + 13, vec![9]));
+    io.apply(&batch).unwrap();
+    let integrity = io.check_integrity().unwrap();
+    assert_eq!(integrity.damaged_tail, None);
+    assert_eq!(integrity.corrupted, vec![first as u64]);
+    let error = io.read_at_position(first as u64).unwrap_err();
+    assert!(matches!(
+        error.into_inner().unwrap().downcast::<ParseError>().map(|e| *e),
+        Ok(ParseError::ChecksumMismatch(position)) if position == first as u64
+    ));
+    assert_eq!(io.read_at_position(second as u64).unwrap(), vec![4, 5]);
+
+    let mut batch = WriteBatch::new(0);
+    batch.writes.push((second as u64 + 12, vec![9]));
+    io.apply(&batch).unwrap();
+    assert_eq!(
+        io.check_integrity().unwrap().damaged_tail,
+        Some(second as u64 - 1)
+    );
+}
+
+#[test]
+fn file_header_round_trip() {
+    let header = FileHeader::new(&[]);
+    let bytes = header.serialize();
+    assert_eq!(bytes.len() as u64, HEADER_LENGTH);
+    let parsed = FileHeader::parse(&bytes).expect("Header parse failed");
+    assert_eq!(parsed.version, FORMAT_VERSION);
+    assert_eq!(parsed.created.timestamp(), header.created.timestamp());
+    assert_eq!(parsed.schema_fingerprint, FileHeader::fingerprint(&[]));
+}
+
+#[test]
+fn reject_invalid_file_header() {
+    let bytes = FileHeader::new(&[]).serialize();
+    assert!(matches!(
+        FileHeader::parse(&[0u8, 1, 2]),
+        Err(FormatError::InvalidMagic)
+    ));
+    let mut corrupted = bytes.clone();
+    corrupted[21] ^= 1;
+    assert!(matches!(
+        FileHeader::parse(&corrupted),
+        Err(FormatError::ChecksumMismatch)
+    ));
+    let resealed = |mut bytes: Vec<u8>| {
+        let checksum = crate::util::Crc32c::checksum(&bytes[..60]);
+        bytes[60..].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    };
+    let mut future = bytes.clone();
+    future[8..10].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+    assert!(matches!(
+        FileHeader::parse(&resealed(future)),
+        Err(FormatError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+    ));
+    let mut timeless = bytes;
+    timeless[12..20].copy_from_slice(&i64::MAX.to_be_bytes());
+    assert!(matches!(
+        FileHeader::parse(&resealed(timeless)),
+        Err(FormatError::InvalidTimestamp)
+    ));
+}
+
+#[test]
+fn allocate_free_space() {
+    let mut map = FreeSpaceMap::new();
+    map.insert(100, 40);
+    map.insert(200, 20);
+    assert_eq!(map.allocate(30), None);
+    assert_eq!(map.allocate(20), Some((200, 20)));
+    assert_eq!(map.remove(200), None);
+    assert_eq!(map.allocate(15), Some((100, 40)));
+    assert_eq!(map.allocate(15), None);
+}
+
+#[test]
+fn split_removed_block() {
+    let path = test_path("split");
+    let _c = Cleanup(path.clone());
+    let mut io = open_io(&path);
+    let mut batch = WriteBatch::new(HEADER_LENGTH);
+    let first = batch.append_block(vec![1; 30]);
+    let second = batch.append_block(vec![2, 3]);
+    batch.remove_block(first);
+    io.apply(&batch).unwrap();
+    let holes = io.check_integrity().unwrap().holes;
+    assert_eq!(holes, vec![(HEADER_LENGTH, 43)]);
+
+    let mut batch = WriteBatch::new(io.end_position().unwrap());
+    let placed = batch.place_block(vec![4, 5, 6], HEADER_LENGTH, 43);
+    io.apply(&batch).unwrap();
+    let integrity = io.check_integrity().unwrap();
+    assert_eq!(integrity.damaged_tail, None);
+    assert!(integrity.corrupted.is_empty());
+    assert_eq!(integrity.holes, vec![(HEADER_LENGTH + 16, 27)]);
+    io.reset_position().unwrap();
+    assert_eq!(io.next().unwrap(), (placed, vec![4, 5, 6]));
+    assert_eq!(io.next().unwrap(), (second, vec![2, 3]));
+}
+
+#[test]
+fn order_index_keys() {
+    use crate::schema::FieldValue;
+    let values = [
+        vec![
+            FieldValue::Int(-300),
+            FieldValue::Int(-1),
+            FieldValue::Int(0),
+            FieldValue::Int(7),
+        ],
+        vec![
+            FieldValue::Float(f64::NEG_INFINITY),
+            FieldValue::Float(-2.5),
+            FieldValue::Float(-0.0),
+            FieldValue::Float(0.5),
+            FieldValue::Float(3.0),
+        ],
+        vec![
+            FieldValue::String(String::new()),
+            FieldValue::String("a".to_string()),
+            FieldValue::String("a\0".to_string()),
+            FieldValue::String("ab".to_string()),
+            FieldValue::String("b".to_string()),
+        ],
+    ];
+    for values in values {
+        let keys: Vec<Vec<u8>> = values
+            .iter()
+            .map(|value| {
+                let mut key = vec![];
+                assert!(encode_key(value, &mut key));
+                key
+            })
+            .collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+    let mut zero = vec![];
+    encode_key(&FieldValue::Float(0.0), &mut zero);
+    let mut negative_zero = vec![];
+    encode_key(&FieldValue::Float(-0.0), &mut negative_zero);
+    assert_eq!(zero, negative_zero);
+    assert_eq!(prefix_end(&[1, 0xFF, 0xFF]), Some(vec![2]));
+    assert_eq!(prefix_end(&[0xFF]), None);
+}
+
+/// Writes the nodes an index changed to the end of the file.
+fn write_index(io: &mut BlockFileIO, index: &mut Index) {
+    let mut batch = WriteBatch::new(io.end_position().unwrap());
+    let size = BLOCK_HEADER_LENGTH + NODE_SIZE;
+    let positions: Vec<_> = (0..index.new_nodes())
+        .map(|_| batch.reserve(size) + 1)
+        .collect();
+    for (position, node) in index.take_changes(&positions) {
+        batch.place_node(node, position - 1, size);
+    }
+    io.apply(&batch).unwrap();
+}
+
+#[test]
+fn index_tree_round_trip() {
+    let path = test_path("index");
+    let _c = Cleanup(path.clone());
+    let mut io = open_io(&path);
+    // A removed block before the nodes, which compaction discards
+    let mut batch = WriteBatch::new(io.end_position().unwrap());
+    let removed = batch.append_block(vec![0; 16]);
+    batch.remove_block(removed);
+    io.apply(&batch).unwrap();
+
+    // Keys this long split the nodes after a few entries
+    let key = |n: u8| vec![n; 400];
+    let mut index = Index::new(0x30, vec![0x1]);
+    index.create();
+    for id in 0..40 {
+        index.insert(&io, key(id as u8 % 10), id).unwrap();
+    }
+    assert!(index.new_nodes() > 3);
+    write_index(&mut io, &mut index);
+    index.remove(&io, &key(2), 12).unwrap();
+    write_index(&mut io, &mut index);
+    let range = |io: &BlockFileIO, index: &Index| {
+        index
+            .range(
+                io,
+                std::ops::Bound::Excluded(key(1)),
+                std::ops::Bound::Included(key(3)),
+            )
+            .unwrap()
+    };
+    assert_eq!(range(&io, &index), vec![2, 22, 32, 3, 13, 23, 33]);
+
+    let integrity = io.check_integrity().unwrap();
+    assert_eq!(integrity.indexes.len(), 1);
+    assert_eq!(integrity.indexes[0].root, index.root);
+    assert_eq!(integrity.indexes[0].len, 39);
+    assert_eq!(integrity.indexes[0].fields, vec![0x1]);
+
+    let relocations = io.compact(&path).unwrap();
+    index.root = relocations[&(index.root as usize)] as crate::util::BlockPosition;
+    assert_eq!(range(&io, &index), vec![2, 22, 32, 3, 13, 23, 33]);
+    assert_eq!(
+        io.check_integrity().unwrap().index_nodes.len(),
+        integrity.index_nodes.len()
+    );
+}
@@ -0,0 +1,152 @@
+use crate::archive::WriteBatch;
+use crate::util::{BlockLength, BlockPosition, Crc32c};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+
+/// A log of [`WriteBatch`]es which have been committed but may
+/// not yet have been applied to the storage file.
+///
+/// Before the [`Backend`] applies a batch, it is appended to the
+/// log and, depending on the configured [`Durability`], flushed to
+/// non-volatile storage. If the process is
+/// interrupted while applying the batch, the log is replayed the
+/// next time the storage file is opened, so a batch is either
+/// applied in its entirety or not at all.
+///
+/// Each record in the log has the following format:
+///
+/// | Size     | Value                                   |
+/// |----------|-----------------------------------------|
+/// | 8        | Length of the rest of the record        |
+/// | 8        | Number of writes                        |
+/// | variable | Writes: a position, a length, and bytes |
+/// | 4        | CRC-32C checksum of the writes          |
+/// | 1        | Commit marker                           |
+///
+/// A record without a commit marker or with a mismatched checksum was
+/// interrupted while being logged, and is discarded along with any
+/// following records.
+///
+/// Batches remain in the log until the storage file has been
+/// flushed, so the log may hold several batches. Replaying them in
+/// order is equivalent to replaying only those not yet applied.
+///
+/// [`Backend`]: crate::backend::Backend
+/// [`Durability`]: crate::backend::Durability
+pub struct WriteAheadLog {
+    file: File,
+}
+
+const COMMIT_MARKER: u8 = 67;
+
+impl WriteAheadLog {
+    /// Opens the log at `path`, creating it if it doesn't exist.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Self { file })
+    }
+
+    /// Appends a [`WriteBatch`] to the log.
+    ///
+    /// The batch is only guaranteed to be stored in non-volatile
+    /// storage once [`sync`] returns.
+    ///
+    /// [`sync`]: WriteAheadLog#method.sync
+    pub fn append(&mut self, batch: &WriteBatch) -> Result<(), Error> {
+        let mut record = Vec::new();
+        record.extend_from_slice(&(batch.writes.len() as u64).to_be_bytes());
+        for (position, bytes) in &batch.writes {
+            record.extend_from_slice(&position.to_be_bytes());
+            record.extend_from_slice(&(bytes.len() as BlockLength).to_be_bytes());
+            record.extend_from_slice(bytes);
+        }
+        record.extend_from_slice(&Crc32c::checksum(&record).to_be_bytes());
+        record.push(COMMIT_MARKER);
+        let mut buf = (record.len() as u64).to_be_bytes().to_vec();
+        buf.append(&mut record);
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&buf)
+    }
+
+    /// Flushes all appended batches to non-volatile storage.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data()
+    }
+
+    /// Returns the size of the log in bytes.
+    pub fn size(&mut self) -> Result<u64, Error> {
+        self.file.seek(SeekFrom::End(0))
+    }
+
+    /// Shortens the log to `size` bytes, discarding the batches
+    /// appended since it had that size.
+    pub fn truncate(&mut self, size: u64) -> Result<(), Error> {
+        self.file.set_len(size)?;
+        self.file.sync_data()
+    }
+
+    /// Reads every complete [`WriteBatch`] in the log, in the
+    /// order they were appended.
+    pub fn recover(&mut self) -> Result<Vec<WriteBatch>, Error> {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&self.file);
+        let mut batches = Vec::new();
+        loop {
+            match Self::read_record(&mut reader) {
+                Ok(Some(batch)) => batches.push(batch),
+                Ok(None) => break,
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(batches)
+    }
+
+    fn read_record(reader: &mut impl Read) -> Result<Option<WriteBatch>, Error> {
+        let mut length_bytes = [0u8; 8];
+        reader.read_exact(&mut length_bytes)?;
+        let length = u64::from_be_bytes(length_bytes);
+        let mut record = Vec::new();
+        reader.take(length).read_to_end(&mut record)?;
+        if record.len() as u64 != length
+            || record.len() < 5
+            || record.last() != Some(&COMMIT_MARKER)
+        {
+            return Ok(None);
+        }
+        let (mut record, checksum) = record[..record.len() - 1].split_at(record.len() - 5);
+        if Crc32c::checksum(record) != u32::from_be_bytes(checksum.try_into().unwrap()) {
+            return Ok(None);
+        }
+        let count = Self::read_u64(&mut record)?;
+        let mut batch = WriteBatch::new(0);
+        for _ in 0..count {
+            let position: BlockPosition = Self::read_u64(&mut record)?;
+            let length: BlockLength = Self::read_u64(&mut record)?;
+            let mut bytes = vec![0u8; length as usize];
+            record.read_exact(&mut bytes)?;
+            batch.writes.push((position, bytes));
+        }
+        Ok(Some(batch))
+    }
+
+    fn read_u64(reader: &mut impl Read) -> Result<u64, Error> {
+        let mut bytes = [0u8; 8];
+        reader.read_exact(&mut bytes)?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Empties the log.
+    ///
+    /// This should only be called after every batch in the log
+    /// has been applied and flushed to the storage file.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        self.file.set_len(0)?;
+        self.file.sync_data()
+    }
+}
@@ -0,0 +1,163 @@
+use super::block_file_io::{block_checksum, BLOCK_HEADER_LENGTH};
+use crate::archive::FileHeader;
+use crate::util::{BlockLength, BlockPosition};
+
+/// A set of writes to the storage file which must be applied
+/// atomically.
+///
+/// A `WriteBatch` is planned by the [`Backend`] when a transaction
+/// is committed, recorded in the [`WriteAheadLog`], then applied by
+/// a [`BlockFileIO`] manager. Each write is a physical write of some
+/// bytes at an offset in the storage file, so applying a batch more
+/// than once has the same effect as applying it once.
+///
+/// [`Backend`]: crate::backend::Backend
+/// [`WriteAheadLog`]: crate::archive::WriteAheadLog
+/// [`BlockFileIO`]: crate::archive::BlockFileIO
+pub struct WriteBatch {
+    pub(super) writes: Vec<(BlockPosition, Vec<u8>)>,
+    end: BlockPosition,
+}
+
+impl WriteBatch {
+    /// Creates an empty [`WriteBatch`].
+    ///
+    /// Accepts the current length of the storage file, after which
+    /// new blocks are appended.
+    pub fn new(end: BlockPosition) -> Self {
+        Self {
+            writes: Vec::new(),
+            end,
+        }
+    }
+
+    /// Plans a new block, appending it to the end of the file.
+    ///
+    /// Returns the position at which the block will be written.
+    pub fn append_block(&mut self, block: Vec<u8>) -> usize {
+        let position = self.end;
+        let buf = Self::encode_block(69, block);
+        self.end += buf.len() as BlockPosition;
+        self.writes.push((position, buf));
+        position as usize + 1
+    }
+
+    /// Plans a new block, placing it in the hole of `hole_size`
+    /// bytes left by removed blocks at `position`.
+    ///
+    /// If the block is smaller than the hole, the rest of the hole
+    /// is marked as a smaller removed block, which must have room
+    /// for a block header. See [`FreeSpaceMap`].
+    ///
+    /// Returns the position at which the block will be written.
+    ///
+    /// [`FreeSpaceMap`]: crate::archive::FreeSpaceMap
+    pub fn place_block(
+        &mut self,
+        block: Vec<u8>,
+        position: BlockPosition,
+        hole_size: BlockPosition,
+    ) -> usize {
+        self.place(69, block, position, hole_size)
+    }
+
+    /// Plans writing an index node, in the space of `hole_size` bytes
+    /// at `position`, which is either a hole left by removed blocks,
+    /// the node's current block, or space reserved with [`reserve`].
+    /// Like [`place_block`], the rest of a larger hole is marked as a
+    /// smaller removed block.
+    ///
+    /// [`reserve`]: WriteBatch#method.reserve
+    /// [`place_block`]: WriteBatch#method.place_block
+    pub fn place_node(&mut self, node: Vec<u8>, position: BlockPosition, hole_size: BlockPosition) {
+        self.place(70, node, position, hole_size);
+    }
+
+    fn place(
+        &mut self,
+        marker: u8,
+        block: Vec<u8>,
+        position: BlockPosition,
+        hole_size: BlockPosition,
+    ) -> usize {
+        let mut buf = Self::encode_block(marker, block);
+        let size = buf.len() as BlockPosition;
+        if hole_size > size {
+            let rest = hole_size - size - BLOCK_HEADER_LENGTH;
+            buf.push(68u8);
+            buf.extend_from_slice(&rest.to_be_bytes());
+            buf.extend_from_slice(&[0u8; 4]);
+        }
+        self.writes.push((position, buf));
+        position as usize + 1
+    }
+
+    /// Plans a new block, appending it to the end of the file
+    /// followed by a removed block of `slack` bytes, which must have
+    /// room for a block header.
+    ///
+    /// The slack lets the block grow in place when it is updated.
+    ///
+    /// Returns the position at which the block will be written.
+    pub fn append_padded_block(&mut self, block: Vec<u8>, slack: BlockPosition) -> usize {
+        let position = self.end;
+        let size = BLOCK_HEADER_LENGTH + block.len() as BlockPosition;
+        self.end += size + slack;
+        let position = self.place_block(block, position, size + slack);
+        // The removed block's data must be written to extend the file
+        if let Some((_, buf)) = self.writes.last_mut() {
+            buf.resize((size + slack) as usize, 0);
+        }
+        position
+    }
+
+    /// Plans extending the file by `size` bytes, which must be filled
+    /// with [`place_node`]. Returns the position of the new space.
+    ///
+    /// [`place_node`]: WriteBatch#method.place_node
+    pub fn reserve(&mut self, size: BlockPosition) -> BlockPosition {
+        let position = self.end;
+        self.end += size;
+        position
+    }
+
+    fn encode_block(marker: u8, mut block: Vec<u8>) -> Vec<u8> {
+        let length_bytes = (block.len() as BlockLength).to_be_bytes();
+        let mut buf = vec![marker];
+        buf.extend_from_slice(&length_bytes);
+        buf.extend_from_slice(&block_checksum(&length_bytes, &block).to_be_bytes());
+        buf.append(&mut block);
+        buf
+    }
+
+    /// Plans marking a block as removed.
+    pub fn remove_block(&mut self, position: usize) {
+        self.writes
+            .push((position as BlockPosition - 1, vec![68u8]));
+    }
+
+    /// Plans writing a new [`FileHeader`] at the beginning of the file.
+    pub fn write_header(&mut self, header: &FileHeader) {
+        self.writes.push((0, header.serialize()));
+    }
+
+    /// Returns the length the storage file will have once the
+    /// batch is applied, after which the next batch appends blocks.
+    pub fn end(&self) -> BlockPosition {
+        self.end
+    }
+
+    /// Plans the writes of another [`WriteBatch`], which was created
+    /// with this batch's [`end`], after this batch's writes.
+    ///
+    /// [`end`]: WriteBatch#method.end
+    pub fn extend(&mut self, other: WriteBatch) {
+        self.writes.extend(other.writes);
+        self.end = other.end;
+    }
+
+    /// Returns `true` if no writes are planned.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+}
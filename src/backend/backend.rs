@@ -1,12 +1,19 @@
 use super::lock::Lock;
-use crate::archive::{ArchiveParser, BlockFileIO, ParseError};
-use crate::backend::{Operation, OperationError, Query, Reference, Request, Response};
+use crate::archive::{
+    ArchiveParser, BlockFileIO, FileHeader, FreeSpaceMap, Index, ParseError, WriteAheadLog,
+    WriteBatch, BLOCK_HEADER_LENGTH, NODE_SIZE,
+};
+use crate::backend::{
+    Change, Durability, Operation, OperationError, Options, Query, Reference, Request, Response,
+};
 use crate::schema::{Document, FieldInstance, Schema};
-use crate::util::{BlockPosition, FieldID, LockType};
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use crate::util::{BlockPosition, DocumentID, FieldID, LockType};
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, File, OpenOptions};
 use std::io;
+use std::path::Path;
 use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
 
 /// The core of the databse's read/write logic.
 ///
@@ -17,38 +24,156 @@ use std::sync::mpsc::{Receiver, Sender};
 /// from the data file. The `Backend` uses [`ArchiveParser`]s to parse
 /// documents in the [`archive`] binary serialization format.
 ///
+/// Unless disabled, commits are first recorded in a
+/// [`WriteAheadLog`] stored alongside the data file, so that an
+/// interrupted commit can be completed when the `Backend` is next
+/// created.
+///
+/// The space left by removed and replaced blocks is reused for new
+/// blocks where they fit (see [`FreeSpaceMap`]), and reclaimed when
+/// the data file is compacted (see [`Backend::compact`]).
+///
 /// [`Database`]: crate::database::Database
 /// [`frontend`]: crate::frontend
 /// [`archive`]: crate::archive
 pub struct Backend {
-    io: BlockFileIO,
-    collections: Vec<Schema>,
-    document_cache: HashMap<usize, Document>,
-    locks: HashMap<usize, Lock>,
+    pub(super) io: BlockFileIO,
+    pub(super) log: Option<WriteAheadLog>,
+    pub(super) collections: Vec<Schema>,
+    pub(super) path: String,
+    pub(super) options: Options,
+    /// The data file's header, which records the next document
+    /// identifier to assign.
+    pub(super) header: FileHeader,
+    /// The current position of each document, by identifier.
+    pub(super) documents: HashMap<DocumentID, usize>,
+    document_cache: HashMap<DocumentID, Document>,
+    locks: HashMap<DocumentID, Lock>,
+    /// The total size of live blocks in the data file.
+    pub(super) live_bytes: u64,
+    /// The total size of removed blocks in the data file.
+    pub(super) dead_bytes: u64,
+    pub(super) free_space: FreeSpaceMap,
+    /// The secondary indexes on the collections, declared in their
+    /// [`Schema`]s.
+    pub(super) indexes: Vec<Index>,
+    /// Changes planned by the commit being planned, which are applied
+    /// once the commit is written.
+    pub(super) pending: Pending,
     reciever: Receiver<Request>,
 }
 
+/// Changes to a [`Backend`]'s map of the data file planned by a
+/// commit.
+#[derive(Default)]
+pub(super) struct Pending {
+    /// Holes left by removed blocks, which are added to the
+    /// [`FreeSpaceMap`].
+    holes: Vec<(BlockPosition, BlockPosition)>,
+    /// Holes taken from the [`FreeSpaceMap`] for new blocks, which
+    /// are returned to it if the plan is discarded.
+    allocations: Vec<(BlockPosition, BlockPosition)>,
+    /// The new position of each created or moved document, or `None`
+    /// for each removed document.
+    documents: Vec<(DocumentID, Option<usize>)>,
+    /// Keys to remove from [`Backend::indexes`], by the index's
+    /// position in the list.
+    pub(super) index_removals: Vec<(usize, Vec<u8>, DocumentID)>,
+    /// Keys to add to [`Backend::indexes`], by the index's position
+    /// in the list.
+    pub(super) index_insertions: Vec<(usize, Vec<u8>, DocumentID)>,
+}
+
+/// The state of a [`Backend`] before a commit is planned, which is
+/// restored if the plan is discarded. See [`Backend::savepoint`].
+struct Savepoint {
+    lengths: [usize; 5],
+    live_bytes: u64,
+    dead_bytes: u64,
+    next_document_id: DocumentID,
+    index_lengths: Vec<u64>,
+}
+
+/// The size of the write-ahead log above which it is emptied, when
+/// commits don't empty it themselves.
+const CHECKPOINT_SIZE: u64 = 4 * 1024 * 1024;
+
+impl Pending {
+    /// Returns the number of each kind of planned change, to undo
+    /// the changes planned after this point with [`Pending::truncate`].
+    fn lengths(&self) -> [usize; 5] {
+        [
+            self.holes.len(),
+            self.allocations.len(),
+            self.documents.len(),
+            self.index_removals.len(),
+            self.index_insertions.len(),
+        ]
+    }
+
+    fn truncate(&mut self, lengths: [usize; 5]) {
+        self.holes.truncate(lengths[0]);
+        self.allocations.truncate(lengths[1]);
+        self.documents.truncate(lengths[2]);
+        self.index_removals.truncate(lengths[3]);
+        self.index_insertions.truncate(lengths[4]);
+    }
+}
+
 impl Backend {
     /// Creates a new [`Backend`] instance.
     ///
     /// Accepts a list of [`Schema`] definitions, a path at which
-    /// the data file is stored, and the recieving end of the
-    /// channel for recieving [`Request`]s.
+    /// the data file is stored, the recieving end of the channel
+    /// for recieving [`Request`]s, and the [`Options`] controlling
+    /// how the data file is read and written.
+    ///
+    /// If the data file doesn't exist, it is created, along with any
+    /// missing parent directories. Any commits left in the write-ahead
+    /// log are replayed, and the data file's header is validated, or
+    /// written if the file is empty (see [`Backend::validate_header`]).
+    /// The data file is then checked for damage (see
+    /// [`Backend::recover`]). Finally, the collections' indexes are
+    /// loaded, or rebuilt if they are out of date (see
+    /// [`Backend::load_indexes`]).
     pub fn new(
         path: String,
         collections: Vec<Schema>,
         reciever: Receiver<Request>,
+        options: Options,
     ) -> Result<Self, io::Error> {
-        Ok(Self {
-            io: BlockFileIO::new(
-                File::open(&path)?,
-                OpenOptions::new().read(true).write(true).open(&path)?,
-            ),
+        if let Some(parent) = Path::new(&path).parent() {
+            create_dir_all(parent)?;
+        }
+        let writer = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let io = BlockFileIO::new(File::open(&path)?, writer);
+        let mut backend = Self {
+            io,
+            log: None,
+            header: FileHeader::new(&collections),
             collections,
+            path,
+            options,
+            documents: HashMap::new(),
             document_cache: HashMap::new(),
             locks: HashMap::new(),
+            live_bytes: 0,
+            dead_bytes: 0,
+            free_space: FreeSpaceMap::new(),
+            indexes: Vec::new(),
+            pending: Pending::default(),
             reciever,
-        })
+        };
+        backend.replay_log()?;
+        backend.validate_header()?;
+        let stored = backend.recover()?;
+        backend.load_indexes(stored)?;
+        Ok(backend)
     }
 
     /// Begins the [`Backend`]'s request execution cycle.
@@ -61,18 +186,88 @@ impl Backend {
     /// [`Database`]: crate::database::Database
     /// [`frontend`]: crate::frontend
     pub fn listen(&mut self) {
-        while let Ok(request) = self.reciever.recv() {
-            if let Operation::Acquire { selection, lock } = request.operation {
-                self.acquire(&selection, lock, request.return_channel);
-            } else {
-                let result = self.execute_operation(request.operation);
-                let send_result = request.return_channel.send(result);
-                if let Err(error) = send_result {
-                    println!("Send error: {}", error);
+        let mut next = None;
+        loop {
+            let request = match next.take() {
+                Some(request) => request,
+                None => match self.reciever.recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                },
+            };
+            match request.operation {
+                Operation::Acquire { selection, lock } => {
+                    self.acquire(&selection, lock, request.return_channel);
+                }
+                Operation::Commit { changes } => {
+                    next = self.group_commit(changes, request.return_channel);
+                }
+                operation => {
+                    let result = self.execute_operation(operation);
+                    Self::respond(&request.return_channel, result);
                 }
             }
         }
     }
+
+    /// Commits a transaction's [`Change`]s together with any other
+    /// commits recieved within the configured group commit delay,
+    /// then responds to each of them.
+    ///
+    /// Commits are gathered until the delay has passed, or until a
+    /// request which isn't a commit, or a commit which changes a
+    /// document changed by an earlier commit in the group, is
+    /// recieved. That request is returned to be executed next.
+    fn group_commit(
+        &mut self,
+        changes: Vec<Change>,
+        return_channel: Sender<Result<Response, OperationError>>,
+    ) -> Option<Request> {
+        let deadline = Instant::now() + Duration::from_millis(self.options.group_commit_delay_ms);
+        let mut changed: HashSet<DocumentID> = Self::changed_documents(&changes).collect();
+        let mut group = vec![changes];
+        let mut senders = vec![return_channel];
+        let mut next = None;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.reciever.recv_timeout(timeout) {
+                Ok(Request {
+                    operation: Operation::Commit { changes },
+                    return_channel,
+                }) if !Self::changed_documents(&changes).any(|id| changed.contains(&id)) => {
+                    changed.extend(Self::changed_documents(&changes));
+                    group.push(changes);
+                    senders.push(return_channel);
+                }
+                Ok(request) => {
+                    next = Some(request);
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        let results = self.commit_group(group);
+        for (sender, result) in senders.iter().zip(results) {
+            Self::respond(sender, result.map(|()| Response::Ok));
+        }
+        next
+    }
+
+    fn changed_documents(changes: &[Change]) -> impl Iterator<Item = DocumentID> + '_ {
+        changes.iter().filter_map(|change| match change {
+            Change::Create { .. } => None,
+            Change::Update { selection, .. } | Change::Delete { selection } => Some(selection.id),
+        })
+    }
+
+    fn respond(
+        sender: &Sender<Result<Response, OperationError>>,
+        result: Result<Response, OperationError>,
+    ) {
+        if let Err(error) = sender.send(result) {
+            println!("Send error: {}", error);
+        }
+    }
 }
 
 /// Execute [`Operation`]s from [`Request`]s.
@@ -90,30 +285,41 @@ mod operations {
             operation: Operation,
         ) -> Result<Response, OperationError> {
             match operation {
-                Operation::FindOne { query } => Ok(Response::Selection(self.find_one(query)?)),
+                Operation::FindOne { query, required } => match self.find_one(query)? {
+                    Some(reference) => Ok(Response::Selection(reference)),
+                    None if required => Err(OperationError::NoMatch),
+                    None => Ok(Response::Empty),
+                },
+                Operation::FindMany { query, required } => {
+                    let references = self.find_many(query)?;
+                    if !references.is_empty() {
+                        Ok(Response::Selections(references))
+                    } else if required {
+                        Err(OperationError::NoMatch)
+                    } else {
+                        Ok(Response::Empty)
+                    }
+                }
                 Operation::Acquire {
                     selection: _,
                     lock: _,
                 } => unreachable!(),
-                Operation::Create { document } => {
-                    let selection = self.create(document)?;
-                    Ok(Response::Selection(selection))
-                }
                 Operation::Read { selection, fields } => {
                     Ok(Response::Document(self.read(selection, fields)?))
                 }
-                Operation::Update { selection, fields } => {
-                    self.update(selection, fields)?;
-                    Ok(Response::Ok)
-                }
-                Operation::Delete { selection } => {
-                    self.delete(selection)?;
+                Operation::Commit { changes } => {
+                    self.commit(changes)?;
                     Ok(Response::Ok)
                 }
                 Operation::Release { selection, lock } => {
                     self.release(selection, lock);
                     Ok(Response::Ok)
                 }
+                Operation::Compact => {
+                    self.compact()?;
+                    Ok(Response::Ok)
+                }
+                Operation::Explain { query } => Ok(Response::Plan(self.plan_query(query)?)),
             }
         }
 
@@ -128,42 +334,380 @@ mod operations {
         ) {
             // TODO optimize order of acquisition
             // TODO optimize queueing system (linked list?)
-            let current = self.locks.get_mut(&selection.position);
+            if let Err(error) = self.resolve(selection) {
+                return_sender.send(Err(error)).unwrap_or(());
+                return;
+            }
+            let current = self.locks.get_mut(&selection.id);
             if let Some(current) = current {
                 current.queue(return_sender, lock);
             } else {
-                self.locks.insert(selection.position, Lock::new(lock));
+                self.locks.insert(selection.id, Lock::new(lock));
                 return_sender.send(Ok(Response::Ok)).unwrap_or(());
             }
         }
 
         fn release(&mut self, selection: Reference, lock: LockType) {
-            let entry = self.locks.get_mut(&selection.position).unwrap();
-            entry.release(&lock);
+            if let Some(entry) = self.locks.get_mut(&selection.id) {
+                if entry.release(&lock) {
+                    self.locks.remove(&selection.id);
+                }
+            }
         }
 
-        fn create(&mut self, document: Document) -> Result<Reference, OperationError> {
-            let bytes = document.serialize();
-            let position = self
-                .io
-                .write_block(bytes)
-                .map_err(OperationError::IOError)?;
-            let selection = Reference {
-                position,
-                schema: document.schema,
+        /// Returns the current position of the document referred to
+        /// by a [`Reference`].
+        ///
+        /// Fails with [`OperationError::StaleReference`] if the
+        /// document has been removed.
+        fn resolve(&self, reference: &Reference) -> Result<usize, OperationError> {
+            self.documents
+                .get(&reference.id)
+                .copied()
+                .ok_or(OperationError::StaleReference)
+        }
+
+        /// Rewrites the data file without its removed blocks.
+        pub(in crate::backend) fn compact(&mut self) -> Result<(), OperationError> {
+            self.checkpoint().map_err(OperationError::from)?;
+            let relocations = self.io.compact(&self.path).map_err(OperationError::from)?;
+            for position in self.documents.values_mut() {
+                if let Some(new_position) = relocations.get(position) {
+                    *position = *new_position;
+                }
+            }
+            for index in &mut self.indexes {
+                if let Some(root) = relocations.get(&(index.root as usize)) {
+                    index.root = *root as BlockPosition;
+                }
+            }
+            self.free_space.clear();
+            println!(
+                "Compaction: removed {} bytes of removed blocks",
+                self.dead_bytes
+            );
+            self.dead_bytes = 0;
+            Ok(())
+        }
+
+        /// Compacts the data file if the fraction of it occupied by
+        /// removed blocks exceeds the configured threshold.
+        fn compact_if_needed(&mut self) {
+            let threshold = match self.options.compaction_threshold {
+                Some(threshold) => threshold,
+                None => return,
+            };
+            let total = self.live_bytes + self.dead_bytes;
+            if total == 0 || (self.dead_bytes as f64 / total as f64) <= threshold {
+                return;
+            }
+            // The commit has already been written, so a failed
+            // compaction is not reported to the client.
+            if let Err(error) = self.compact() {
+                println!("Compaction error: {}", error);
+            }
+        }
+
+        /// Applies a transaction's [`Change`]s atomically.
+        ///
+        /// See [`Backend::commit_group`].
+        fn commit(&mut self, changes: Vec<Change>) -> Result<(), OperationError> {
+            self.commit_group(vec![changes]).pop().unwrap()
+        }
+
+        /// Applies the [`Change`]s of several transactions, each
+        /// atomically, returning the result of each commit in order.
+        ///
+        /// The changes are planned into a single [`WriteBatch`], which
+        /// is recorded in the write-ahead log before being applied to
+        /// the data file, so the commits share one flush. A commit
+        /// which fails to plan doesn't affect the others, but if the
+        /// batch can't be written, every commit fails. The commits
+        /// must not change the same document.
+        pub(in crate::backend) fn commit_group(
+            &mut self,
+            group: Vec<Vec<Change>>,
+        ) -> Vec<Result<(), OperationError>> {
+            self.pending = Pending::default();
+            let end = match self.io.end_position() {
+                Ok(end) => end,
+                Err(error) => return group.iter().map(|_| Err(copy_error(&error))).collect(),
             };
-            Ok(selection)
+            let mut batch = WriteBatch::new(end);
+            let mut results = Vec::with_capacity(group.len());
+            let start = self.savepoint();
+            let next_document_id = self.header.next_document_id;
+            for changes in group {
+                let savepoint = self.savepoint();
+                let mut planned = WriteBatch::new(batch.end());
+                let result = self.plan(&mut planned, changes);
+                if result.is_ok() {
+                    batch.extend(planned);
+                } else {
+                    self.discard(savepoint);
+                }
+                results.push(result);
+            }
+            if batch.is_empty() {
+                return results;
+            }
+            if self.header.next_document_id != next_document_id {
+                batch.write_header(&self.header);
+            }
+            let written = self
+                .plan_index_writes(&mut batch)
+                .and_then(|()| self.write(&batch));
+            if let Err(error) = written {
+                self.discard(start);
+                return results
+                    .into_iter()
+                    .map(|result| result.and(Err(copy_error(&error))))
+                    .collect();
+            }
+            let pending = std::mem::take(&mut self.pending);
+            for (position, size) in pending.holes {
+                self.free_space.insert(position, size);
+            }
+            for (id, position) in pending.documents {
+                self.document_cache.remove(&id);
+                match position {
+                    Some(position) => self.documents.insert(id, position),
+                    None => self.documents.remove(&id),
+                };
+            }
+            self.compact_if_needed();
+            results
+        }
+
+        /// Records the state of the data file's map, so that the
+        /// changes planned after this point can be discarded with
+        /// [`Backend::discard`].
+        fn savepoint(&self) -> Savepoint {
+            Savepoint {
+                lengths: self.pending.lengths(),
+                live_bytes: self.live_bytes,
+                dead_bytes: self.dead_bytes,
+                next_document_id: self.header.next_document_id,
+                index_lengths: self.indexes.iter().map(|index| index.len).collect(),
+            }
+        }
+
+        /// Discards the changes planned since a [`Savepoint`],
+        /// returning the holes they took to the [`FreeSpaceMap`].
+        ///
+        /// Changes to the indexes' nodes are only planned once the
+        /// whole group is, so any that were planned are discarded.
+        fn discard(&mut self, savepoint: Savepoint) {
+            for (position, size) in self.pending.allocations.drain(savepoint.lengths[1]..) {
+                self.free_space.insert(position, size);
+            }
+            self.pending.truncate(savepoint.lengths);
+            self.live_bytes = savepoint.live_bytes;
+            self.dead_bytes = savepoint.dead_bytes;
+            self.header.next_document_id = savepoint.next_document_id;
+            for (index, len) in self.indexes.iter_mut().zip(savepoint.index_lengths) {
+                index.discard_changes();
+                index.len = len;
+            }
         }
 
-        fn find_one(&mut self, query: Query) -> Result<Reference, OperationError> {
+        /// Plans a transaction's [`Change`]s into a [`WriteBatch`].
+        fn plan(
+            &mut self,
+            batch: &mut WriteBatch,
+            changes: Vec<Change>,
+        ) -> Result<(), OperationError> {
+            // Check every reference before planning, so that a stale
+            // reference fails the commit before any block is read
+            for change in &changes {
+                if let Change::Update { selection, .. } | Change::Delete { selection } = change {
+                    self.resolve(selection)?;
+                }
+            }
+            self.check_unique(&changes)?;
+            for change in changes {
+                match change {
+                    Change::Create { document } => self.create(batch, document)?,
+                    Change::Update { selection, fields } => {
+                        self.update(batch, selection, fields)?
+                    }
+                    Change::Delete { selection } => self.delete(batch, selection)?,
+                }
+            }
+            Ok(())
+        }
+
+        /// Writes a [`WriteBatch`] to the data file, recording it in
+        /// the write-ahead log first if it is enabled.
+        ///
+        /// Returns once the batch is as durable as the configured
+        /// [`Durability`] requires. If the batch can't be written to
+        /// the data file, it is removed from the log again, so that it
+        /// isn't replayed when the data file is next opened.
+        pub(in crate::backend) fn write(&mut self, batch: &WriteBatch) -> Result<(), io::Error> {
+            let durability = self.options.durability;
+            let mut log_size = 0;
+            if let Some(log) = &mut self.log {
+                log_size = log.size()?;
+                log.append(batch)?;
+                if durability != Durability::None {
+                    log.sync()?;
+                }
+            }
+            if let Err(error) = self.io.apply(batch) {
+                if let Some(log) = &mut self.log {
+                    log.truncate(log_size)?;
+                }
+                return Err(error);
+            }
+            if let Some(log) = &mut self.log {
+                if durability == Durability::Full || log.size()? >= CHECKPOINT_SIZE {
+                    self.checkpoint()?;
+                }
+            } else if durability != Durability::None {
+                self.io.sync()?;
+            }
+            Ok(())
+        }
+
+        /// Flushes the data file and empties the write-ahead log.
+        ///
+        /// This must be done before the data file is rewritten, as the
+        /// positions of writes in the log would no longer be valid.
+        pub(in crate::backend) fn checkpoint(&mut self) -> Result<(), io::Error> {
+            self.io.sync()?;
+            if let Some(log) = &mut self.log {
+                log.clear()?;
+            }
+            Ok(())
+        }
+
+        /// Plans a new document, assigning it the next identifier.
+        fn create(
+            &mut self,
+            batch: &mut WriteBatch,
+            mut document: Document,
+        ) -> Result<(), OperationError> {
+            let id = self.header.next_document_id;
+            self.header.next_document_id += 1;
+            document.id = Some(id);
+            self.plan_index_changes(id, None, Some(&document))?;
+            let position = self.place(batch, document.serialize(), 0);
+            self.pending.documents.push((id, Some(position)));
+            Ok(())
+        }
+
+        /// Plans a new block followed by `slack` bytes of free space,
+        /// which is either zero or has room for a block header.
+        ///
+        /// The block is placed in a hole in the data file if one fits,
+        /// or appended to the file otherwise. Returns the position of
+        /// the new block.
+        fn place(&mut self, batch: &mut WriteBatch, block: Vec<u8>, slack: BlockPosition) -> usize {
+            let size = BLOCK_HEADER_LENGTH + block.len() as BlockPosition;
+            self.live_bytes += size;
+            match self.free_space.allocate(size + slack) {
+                Some((position, hole_size)) => {
+                    self.pending.allocations.push((position, hole_size));
+                    let position = batch.place_block(block, position, hole_size);
+                    if hole_size > size {
+                        self.pending
+                            .holes
+                            .push((position as BlockPosition - 1 + size, hole_size - size));
+                    }
+                    self.dead_bytes = self.dead_bytes.saturating_sub(size);
+                    position
+                }
+                None if slack > 0 => {
+                    let position = batch.append_padded_block(block, slack);
+                    self.pending
+                        .holes
+                        .push((position as BlockPosition - 1 + size, slack));
+                    self.dead_bytes += slack;
+                    position
+                }
+                None => batch.append_block(block),
+            }
+        }
+
+        /// Plans a block for a new index node, in a hole in the data
+        /// file if one fits, or at the end of the file otherwise, like
+        /// [`Backend::place`].
+        ///
+        /// Returns the position of the block and the size of the space
+        /// it is written in, to pass to [`WriteBatch::place_node`].
+        pub(in crate::backend) fn allocate_node(
+            &mut self,
+            batch: &mut WriteBatch,
+        ) -> (BlockPosition, BlockPosition) {
+            let size = BLOCK_HEADER_LENGTH + NODE_SIZE;
+            self.live_bytes += size;
+            match self.free_space.allocate(size) {
+                Some((position, hole_size)) => {
+                    self.pending.allocations.push((position, hole_size));
+                    if hole_size > size {
+                        self.pending.holes.push((position + size, hole_size - size));
+                    }
+                    self.dead_bytes = self.dead_bytes.saturating_sub(size);
+                    (position, hole_size)
+                }
+                None => (batch.reserve(size), size),
+            }
+        }
+
+        /// Finds the first document matching a [`Query`], or `None`
+        /// if no document matches.
+        fn find_one(&mut self, query: Query) -> Result<Option<Reference>, OperationError> {
+            Ok(self.find(query, Some(1))?.pop())
+        }
+
+        fn find_many(&mut self, query: Query) -> Result<Vec<Reference>, OperationError> {
+            self.find(query, None)
+        }
+
+        /// Finds the documents matching a [`Query`], in the order they
+        /// are stored, up to `limit` documents.
+        ///
+        /// If the query's plan (see [`Backend::plan_query`]) finds the
+        /// documents which may match with the indexes, only those
+        /// documents are read. Otherwise, every document is read.
+        fn find(
+            &mut self,
+            query: Query,
+            limit: Option<usize>,
+        ) -> Result<Vec<Reference>, OperationError> {
+            let plan = self.plan_query(query)?;
             let schema = self
                 .collections
                 .iter()
-                .find(|s| s.id == query.collection)
-                .ok_or(OperationError::UnknownSchemaIdentifier)?;
-            self.io.reset_position().map_err(OperationError::IOError)?;
-            loop {
-                let (position, block) = self.io.next().map_err(OperationError::IOError)?;
+                .find(|s| s.id == plan.collection)
+                .ok_or(OperationError::UnknownSchemaIdentifier)?
+                .clone();
+            let mut candidates = self.candidates(&plan.access)?.map(|ids| {
+                let mut positions: Vec<usize> = ids
+                    .iter()
+                    .filter_map(|id| self.documents.get(id).copied())
+                    .collect();
+                positions.sort_unstable();
+                positions.into_iter()
+            });
+            if candidates.is_none() {
+                self.io.reset_position().map_err(OperationError::from)?;
+            }
+            let mut results = vec![];
+            while limit.is_none_or(|limit| results.len() < limit) {
+                let next = match &mut candidates {
+                    Some(positions) => match positions.next() {
+                        Some(position) => self.io.read_at_position(position as BlockPosition),
+                        None => break,
+                    },
+                    None => self.io.next().map(|(_, block)| block),
+                };
+                let block = match next {
+                    Ok(block) => block,
+                    Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+                    Err(error) => return Err(OperationError::from(error)),
+                };
                 let mut parser = ArchiveParser::new(
                     schema.clone(),
                     block,
@@ -175,116 +719,114 @@ mod operations {
                     Err(ParseError::SchemaMismatch) => {}
                     Err(error) => return Err(OperationError::ParseError(error)),
                     Ok(document) => {
-                        let matches = document.evaluate(&query.condition)?;
+                        let matches = document.evaluate(&plan.condition)?;
                         if matches {
-                            self.document_cache.insert(position, document);
-                            return Ok(Reference {
-                                position,
+                            let id = document.id.unwrap_or_default();
+                            self.document_cache.insert(id, document);
+                            results.push(Reference {
+                                id,
                                 schema: schema.clone(),
                             });
                         }
                     }
                 }
             }
+            Ok(results)
         }
 
-        // fn find_many(&mut self, query: Query) -> Result<ManySelection, OperationError> {
-        //     let schema = self
-        //         .collections
-        //         .iter()
-        //         .find(|s| s.id == query.collection)
-        //         .ok_or(OperationError::UnknownSchemaIdentifier)?;
-        //     self.io.reset_position().map_err(OperationError::IOError)?;
-        //     let mut results = vec![];
-        //     loop {
-        //         let next = self.io.next();
-        //         if let Err(error) = &next {
-        //             if let std::io::ErrorKind::UnexpectedEof = error.kind() {
-        //                 break;
-        //             }
-        //         }
-        //         let (position, block) = next.unwrap();
-        //         let mut parser = ArchiveParser::new(
-        //             schema.clone(),
-        //             block,
-        //             // TODO optimize
-        //             schema.fields.iter().map(|f| f.id).collect(),
-        //         );
-        //         let document_result = parser.read_document();
-        //         match document_result {
-        //             Err(ParseError::SchemaMismatch) => {}
-        //             Err(error) => return Err(OperationError::ParseError(error)),
-        //             Ok(document) => {
-        //                 let matches = document.evaluate(&query.condition)?;
-        //                 self.document_cache.insert(position, document);
-        //                 if matches {
-        //                     results.push(position)
-        //                 }
-        //             }
-        //         }
-        //     }
-        //     Ok(ManySelection {
-        //         schema: schema.clone(),
-        //         positions: results,
-        //     })
-        // }
-
         fn read(
             &mut self,
             selection: Reference,
             fields: Vec<FieldID>,
         ) -> Result<Document, OperationError> {
+            let position = self.resolve(&selection)?;
             let block = self
                 .io
-                .read_at_position(selection.position as BlockPosition)
-                .map_err(OperationError::IOError)?;
+                .read_at_position(position as BlockPosition)
+                .map_err(OperationError::from)?;
             let document = ArchiveParser::new(selection.schema, block, fields)
                 .read_document()
                 .map_err(OperationError::ParseError)?;
             Ok(document)
         }
 
-        // fn read_many(
-        //     &mut self,
-        //     selection: ManySelection,
-        //     fields: Vec<u16>,
-        // ) -> Result<Vec<Document>, OperationError> {
-        //     selection
-        //         .positions
-        //         .iter()
-        //         .map(|p| {
-        //             let block = self
-        //                 .io
-        //                 .read_at_position(*p as u64)
-        //                 .map_err(OperationError::IOError)?;
-        //             let document =
-        //                 ArchiveParser::new(selection.schema.clone(), block, fields.clone())
-        //                     .read_document()
-        //                     .map_err(OperationError::ParseError)?;
-        //             Ok(document)
-        //         })
-        //         .collect()
-        // }
-
         fn update(
             &mut self,
+            batch: &mut WriteBatch,
             selection: Reference,
             fields: Vec<FieldInstance>,
         ) -> Result<(), OperationError> {
-            // TODO optimize
-            self.delete(selection.clone())?;
-            self.create(Document {
-                schema: selection.schema,
+            let position = self.resolve(&selection)?;
+            let document = Document {
+                id: Some(selection.id),
+                schema: selection.schema.clone(),
                 fields,
-            })?;
+            };
+            let old = self.indexed_document(&selection, position)?;
+            self.plan_index_changes(selection.id, old.as_ref(), Some(&document))?;
+            let block = document.serialize();
+            let size = BLOCK_HEADER_LENGTH + block.len() as BlockPosition;
+            let start = position as BlockPosition - 1;
+            let old_size = self.io.block_size(position).map_err(OperationError::from)?;
+
+            // Rewrite the block in place if it fits, using any free
+            // space directly after it
+            let slack = self.free_space.remove(start + old_size).unwrap_or(0);
+            let available = old_size + slack;
+            if size == available || size + BLOCK_HEADER_LENGTH <= available {
+                if slack > 0 {
+                    self.pending.allocations.push((start + old_size, slack));
+                }
+                batch.place_block(block, start, available);
+                if available > size {
+                    self.pending.holes.push((start + size, available - size));
+                }
+                self.live_bytes = (self.live_bytes + size).saturating_sub(old_size);
+                self.dead_bytes = (self.dead_bytes + available - size).saturating_sub(slack);
+                self.pending.documents.push((selection.id, Some(position)));
+                return Ok(());
+            }
+            if slack > 0 {
+                self.free_space.insert(start + old_size, slack);
+            }
+
+            // Otherwise, move the block, leaving some free space after
+            // it to grow into. The free space must keep room for its
+            // own header as the block grows, so it is at least two
+            // headers long
+            self.remove(batch, position, old_size);
+            let position = self.place(batch, block, (size / 4).max(2 * BLOCK_HEADER_LENGTH));
+            self.pending.documents.push((selection.id, Some(position)));
             Ok(())
         }
 
-        fn delete(&mut self, selection: Reference) -> Result<(), OperationError> {
-            self.io
-                .remove_block(selection.position)
-                .map_err(OperationError::IOError)?;
+        fn delete(
+            &mut self,
+            batch: &mut WriteBatch,
+            selection: Reference,
+        ) -> Result<(), OperationError> {
+            let position = self.resolve(&selection)?;
+            let old = self.indexed_document(&selection, position)?;
+            self.plan_index_changes(selection.id, old.as_ref(), None)?;
+            let size = self.io.block_size(position).map_err(OperationError::from)?;
+            self.remove(batch, position, size);
+            self.pending.documents.push((selection.id, None));
             Ok(())
         }
+
+        fn remove(&mut self, batch: &mut WriteBatch, position: usize, size: BlockPosition) {
+            self.live_bytes = self.live_bytes.saturating_sub(size);
+            self.dead_bytes += size;
+            self.pending
+                .holes
+                .push((position as BlockPosition - 1, size));
+            batch.remove_block(position);
+        }
     }
 }
+
+/// Copies an I/O error which failed several commits, so that each
+/// commit can report it.
+fn copy_error(error: &io::Error) -> OperationError {
+    OperationError::from(io::Error::new(error.kind(), error.to_string()))
+}
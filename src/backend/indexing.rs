@@ -0,0 +1,369 @@
+use crate::archive::{
+    ArchiveParser, Index, WriteBatch, BLOCK_HEADER_LENGTH, MAX_INDEX_FIELDS, MAX_KEY_LENGTH,
+    NODE_SIZE,
+};
+use crate::backend::{Backend, Change, OperationError, Reference};
+use crate::schema::{Document, FieldType};
+use crate::util::{BlockPosition, DocumentID, SchemaID};
+use std::collections::HashSet;
+use std::io::{self, ErrorKind};
+use std::ops::Bound;
+
+/// The indexes found in the data file when it is opened.
+pub(super) struct StoredIndexes {
+    /// The indexes whose root nodes were found.
+    pub indexes: Vec<Index>,
+    /// The position and size of every index node.
+    pub nodes: Vec<(BlockPosition, BlockPosition)>,
+    /// Whether any blocks were damaged, which may have included
+    /// index nodes.
+    pub damaged: bool,
+}
+
+impl Backend {
+    /// Loads the secondary indexes declared in the collections'
+    /// [`Schema`]s from their trees in the data file.
+    ///
+    /// If an index has no tree, or the data file was damaged, or has
+    /// a tree for an index which is no longer declared, which may
+    /// have missed changes to the documents, every index is rebuilt
+    /// from the documents.
+    pub(super) fn load_indexes(&mut self, stored: StoredIndexes) -> io::Result<()> {
+        self.indexes = self.define_indexes()?;
+        let mut unmatched = stored.indexes;
+        let mut missing = false;
+        for index in &mut self.indexes {
+            let found = unmatched
+                .iter()
+                .position(|i| i.collection == index.collection && i.fields == index.fields);
+            match found {
+                Some(found) => {
+                    let tree = unmatched.swap_remove(found);
+                    index.root = tree.root;
+                    index.len = tree.len;
+                }
+                None => missing = true,
+            }
+        }
+        if !missing && unmatched.is_empty() && !stored.damaged {
+            return Ok(());
+        }
+        if self.indexes.is_empty() && stored.nodes.is_empty() {
+            return Ok(());
+        }
+        self.rebuild_indexes(stored.nodes)?;
+        println!("Startup: rebuilt {} indexes", self.indexes.len());
+        Ok(())
+    }
+
+    /// Creates an empty [`Index`] for each index declared in the
+    /// collections' [`Schema`]s, and a unique index for each unique
+    /// field which isn't already the only field of an index.
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`] if an index names a
+    /// field which doesn't exist, or whose values can't be indexed,
+    /// or more than [`MAX_INDEX_FIELDS`] fields.
+    fn define_indexes(&self) -> io::Result<Vec<Index>> {
+        let mut indexes = vec![];
+        for schema in &self.collections {
+            for names in &schema.indexes {
+                if names.len() > MAX_INDEX_FIELDS {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "An index of {} has more than {} fields",
+                            schema.name, MAX_INDEX_FIELDS
+                        ),
+                    ));
+                }
+                let mut fields = vec![];
+                for name in names {
+                    let field = schema.fields.iter().find(|f| &f.name == name);
+                    match field.map(|f| (f.id, &f.field_type)) {
+                        Some((
+                            _,
+                            FieldType::Array(_) | FieldType::Object(_) | FieldType::Enum(_),
+                        )) => {
+                            return Err(io::Error::new(
+                                ErrorKind::InvalidInput,
+                                format!("Field {} of {} can't be indexed", name, schema.name),
+                            ))
+                        }
+                        Some((id, _)) => fields.push(id),
+                        None => {
+                            return Err(io::Error::new(
+                                ErrorKind::InvalidInput,
+                                format!("Indexed field {} of {} doesn't exist", name, schema.name),
+                            ))
+                        }
+                    }
+                }
+                indexes.push(Index::new(schema.id, fields));
+            }
+            for field in schema.fields.iter().filter(|f| f.unique) {
+                let existing = indexes
+                    .iter_mut()
+                    .find(|i| i.collection == schema.id && i.fields == [field.id]);
+                match (existing, &field.field_type) {
+                    (_, FieldType::Array(_) | FieldType::Object(_) | FieldType::Enum(_)) => {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Field {} of {} can't be unique", field.name, schema.name),
+                        ))
+                    }
+                    (Some(index), _) => index.unique = true,
+                    (None, _) => {
+                        let mut index = Index::new(schema.id, vec![field.id]);
+                        index.unique = true;
+                        indexes.push(index);
+                    }
+                }
+            }
+        }
+        Ok(indexes)
+    }
+
+    /// Rebuilds every index by reading every document, replacing
+    /// the index nodes at `nodes`.
+    ///
+    /// Fails with [`ErrorKind::InvalidData`] if a document's key in
+    /// an index is longer than [`MAX_KEY_LENGTH`].
+    fn rebuild_indexes(&mut self, nodes: Vec<(BlockPosition, BlockPosition)>) -> io::Result<()> {
+        let mut batch = WriteBatch::new(self.io.end_position()?);
+        for (position, size) in nodes {
+            batch.remove_block(position as usize);
+            self.free_space.insert(position - 1, size);
+            self.live_bytes -= size;
+            self.dead_bytes += size;
+        }
+        let mut entries = vec![vec![]; self.indexes.len()];
+        let documents: Vec<(DocumentID, usize)> =
+            self.documents.iter().map(|(id, p)| (*id, *p)).collect();
+        for (id, position) in documents {
+            let block = self.io.read_at_position(position as BlockPosition)?;
+            let schema_id = match block.get(8..16) {
+                Some(bytes) => SchemaID::from_be_bytes(bytes.try_into().unwrap()),
+                None => continue,
+            };
+            if !self.indexes.iter().any(|i| i.collection == schema_id) {
+                continue;
+            }
+            let Some(schema) = self.collections.iter().find(|s| s.id == schema_id) else {
+                continue;
+            };
+            let fields = schema.fields.iter().map(|f| f.id).collect();
+            let document = ArchiveParser::new(schema.clone(), block, fields)
+                .read_document()
+                .map_err(|error| io::Error::new(ErrorKind::InvalidData, error))?;
+            for (index, entries) in self.indexes.iter().zip(&mut entries) {
+                let Some(key) = index.key(&document) else {
+                    continue;
+                };
+                if key.len() > MAX_KEY_LENGTH {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Document {} has an index key longer than {} bytes",
+                            id, MAX_KEY_LENGTH
+                        ),
+                    ));
+                }
+                entries.push((key, id));
+            }
+        }
+        for (number, mut entries) in entries.into_iter().enumerate() {
+            entries.sort_unstable();
+            let index = &mut self.indexes[number];
+            index.create();
+            for (key, id) in entries {
+                index.insert(&self.io, key, id)?;
+            }
+            let size = BLOCK_HEADER_LENGTH + NODE_SIZE;
+            let positions = (0..index.new_nodes())
+                .map(|_| (batch.reserve(size), size))
+                .collect();
+            self.live_bytes += size * index.new_nodes() as BlockPosition;
+            self.place_index_nodes(&mut batch, number, positions);
+        }
+        self.write(&batch)
+    }
+
+    /// Plans writing the changes to the indexes planned by a group
+    /// of commits, so that they are written along with the documents.
+    pub(super) fn plan_index_writes(&mut self, batch: &mut WriteBatch) -> io::Result<()> {
+        let removals = std::mem::take(&mut self.pending.index_removals);
+        for (number, key, id) in removals {
+            self.indexes[number].remove(&self.io, &key, id)?;
+        }
+        let insertions = std::mem::take(&mut self.pending.index_insertions);
+        for (number, key, id) in insertions {
+            self.indexes[number].insert(&self.io, key, id)?;
+        }
+        for number in 0..self.indexes.len() {
+            let positions = (0..self.indexes[number].new_nodes())
+                .map(|_| self.allocate_node(batch))
+                .collect();
+            self.place_index_nodes(batch, number, positions);
+        }
+        Ok(())
+    }
+
+    /// Plans writing the changed nodes of an index, placing its new
+    /// nodes in the spaces at `positions`, which are the positions
+    /// and sizes of the spaces for their blocks.
+    fn place_index_nodes(
+        &mut self,
+        batch: &mut WriteBatch,
+        number: usize,
+        positions: Vec<(BlockPosition, BlockPosition)>,
+    ) {
+        let data_positions: Vec<BlockPosition> = positions.iter().map(|(p, _)| p + 1).collect();
+        for (position, node) in self.indexes[number].take_changes(&data_positions) {
+            let hole_size = positions
+                .iter()
+                .find(|(p, _)| p + 1 == position)
+                .map_or(BLOCK_HEADER_LENGTH + NODE_SIZE, |(_, size)| *size);
+            batch.place_node(node, position - 1, hole_size);
+        }
+    }
+
+    /// Reads the current version of a selected document if it is in
+    /// an indexed collection, so that its old keys can be removed
+    /// from the indexes.
+    pub(super) fn indexed_document(
+        &mut self,
+        selection: &Reference,
+        position: usize,
+    ) -> Result<Option<Document>, OperationError> {
+        if !self
+            .indexes
+            .iter()
+            .any(|i| i.collection == selection.schema.id)
+        {
+            return Ok(None);
+        }
+        let block = self
+            .io
+            .read_at_position(position as BlockPosition)
+            .map_err(OperationError::from)?;
+        let fields = selection.schema.fields.iter().map(|f| f.id).collect();
+        let document = ArchiveParser::new(selection.schema.clone(), block, fields)
+            .read_document()
+            .map_err(OperationError::ParseError)?;
+        Ok(Some(document))
+    }
+
+    /// Plans the changes to the indexes when a document changes
+    /// from `old` to `new`, where `None` means that the document
+    /// doesn't exist.
+    ///
+    /// Fails if the document's new key in an index is longer than
+    /// [`MAX_KEY_LENGTH`].
+    pub(super) fn plan_index_changes(
+        &mut self,
+        id: DocumentID,
+        old: Option<&Document>,
+        new: Option<&Document>,
+    ) -> Result<(), OperationError> {
+        for (number, index) in self.indexes.iter().enumerate() {
+            let old_key = old.and_then(|d| index.key(d));
+            let new_key = new.and_then(|d| index.key(d));
+            if old_key == new_key {
+                continue;
+            }
+            if let Some(key) = &new_key {
+                if key.len() > MAX_KEY_LENGTH {
+                    return Err(OperationError::IndexKeyTooLong { length: key.len() });
+                }
+            }
+            if let Some(key) = old_key {
+                self.pending.index_removals.push((number, key, id));
+            }
+            if let Some(key) = new_key {
+                self.pending.index_insertions.push((number, key, id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a transaction's [`Change`]s don't give a unique
+    /// field a value which another document has, either already or
+    /// after the changes.
+    ///
+    /// This is checked before the changes are planned, so that a
+    /// violation leaves nothing to undo.
+    pub(super) fn check_unique(&self, changes: &[Change]) -> Result<(), OperationError> {
+        // Updated and deleted documents give up their old values
+        let released: HashSet<DocumentID> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Update { selection, .. } | Change::Delete { selection } => {
+                    Some(selection.id)
+                }
+                Change::Create { .. } => None,
+            })
+            .collect();
+        for (number, index) in self.indexes.iter().enumerate().filter(|(_, i)| i.unique) {
+            let mut claimed = HashSet::new();
+            for change in changes {
+                let (schema, fields) = match change {
+                    Change::Create { document } => (&document.schema, &document.fields),
+                    Change::Update { selection, fields } => (&selection.schema, fields),
+                    Change::Delete { .. } => continue,
+                };
+                if schema.id != index.collection {
+                    continue;
+                }
+                let Some(key) = index.fields_key(fields) else {
+                    continue;
+                };
+                let taken = self
+                    .unique_holders(number, &key)?
+                    .iter()
+                    .any(|holder| !released.contains(holder));
+                if taken || !claimed.insert(key) {
+                    let field = index.fields[0];
+                    return Err(OperationError::UniqueViolation {
+                        field: schema
+                            .fields
+                            .iter()
+                            .find(|f| f.id == field)
+                            .map(|f| f.name.clone())
+                            .unwrap_or_default(),
+                        value: fields
+                            .iter()
+                            .find(|f| f.id == field)
+                            .map(|f| f.value.clone())
+                            .unwrap(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the documents which have a key in a unique index,
+    /// including the changes planned by earlier commits in the group.
+    fn unique_holders(&self, number: usize, key: &[u8]) -> Result<Vec<DocumentID>, OperationError> {
+        let bound = || Bound::Included(key.to_vec());
+        let mut holders: Vec<DocumentID> = self.indexes[number]
+            .range(&self.io, bound(), bound())?
+            .into_iter()
+            .filter(|id| {
+                !self
+                    .pending
+                    .index_removals
+                    .iter()
+                    .any(|(i, k, removed)| *i == number && k == key && removed == id)
+            })
+            .collect();
+        holders.extend(
+            self.pending
+                .index_insertions
+                .iter()
+                .filter(|(i, k, _)| *i == number && k == key)
+                .map(|(_, _, id)| *id),
+        );
+        Ok(holders)
+    }
+}
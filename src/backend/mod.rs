@@ -3,16 +3,24 @@
 //! See [`Backend`].
 #[allow(clippy::module_inception)]
 mod backend;
+mod indexing;
 mod lock;
 mod operation_error;
+mod options;
+mod pattern;
+mod planner;
 mod query;
 mod request;
 mod selection;
+mod startup;
 #[cfg(test)]
 mod tests;
 
 pub use backend::Backend;
 pub use operation_error::OperationError;
-pub use query::{Condition, Expression, Query};
-pub use request::{Operation, Request, Response};
+pub use options::{Durability, Options};
+pub use pattern::Pattern;
+pub use planner::{Access, QueryPlan};
+pub use query::{Condition, Expression, Query, ValueSet};
+pub use request::{Change, Operation, Request, Response};
 pub use selection::Reference;
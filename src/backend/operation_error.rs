@@ -1,5 +1,5 @@
-use crate::archive::ParseError;
-use crate::schema::FieldType;
+use crate::archive::{ParseError, MAX_KEY_LENGTH};
+use crate::schema::{FieldType, FieldValue};
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
@@ -16,6 +16,10 @@ pub enum OperationError {
     UnknownFieldIdentifier,
     ExpressionTypeMismatch { left: FieldType, right: FieldType },
     InvalidExpressionType,
+    StaleReference,
+    NoMatch,
+    UniqueViolation { field: String, value: FieldValue },
+    IndexKeyTooLong { length: usize },
 }
 
 impl Display for OperationError {
@@ -39,12 +43,54 @@ impl Display for OperationError {
             OperationError::InvalidExpressionType => {
                 write!(formatter, "Invalid expression type for operation in query")
             }
+            OperationError::StaleReference => {
+                write!(formatter, "The selected document no longer exists")
+            }
+            OperationError::NoMatch => {
+                write!(formatter, "No document matches the query")
+            }
+            OperationError::UniqueViolation { field, value } => {
+                write!(
+                    formatter,
+                    "Unique field {} already has the value {} in another document",
+                    field, value
+                )
+            }
+            OperationError::IndexKeyTooLong { length } => {
+                write!(
+                    formatter,
+                    "Indexed fields of {} bytes exceed the limit of {} bytes",
+                    length, MAX_KEY_LENGTH
+                )
+            }
         }
     }
 }
 
 impl Error for OperationError {}
 
+impl From<std::io::Error> for OperationError {
+    /// Wraps an error from a [`BlockFileIO`] manager, unwrapping any
+    /// [`ParseError`] it carries, such as a checksum mismatch.
+    ///
+    /// [`BlockFileIO`]: crate::archive::BlockFileIO
+    fn from(error: std::io::Error) -> Self {
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<ParseError>())
+        {
+            let inner = error
+                .into_inner()
+                .unwrap()
+                .downcast::<ParseError>()
+                .unwrap();
+            OperationError::ParseError(*inner)
+        } else {
+            OperationError::IOError(error)
+        }
+    }
+}
+
 impl Display for FieldType {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), std::fmt::Error> {
         match self {
@@ -69,3 +115,28 @@ impl Debug for FieldType {
         write!(formatter, "{}", self)
     }
 }
+
+impl Display for FieldValue {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match self {
+            FieldValue::Int(i) => write!(formatter, "{}", i),
+            FieldValue::UInt(i) => write!(formatter, "{}", i),
+            FieldValue::Long(i) => write!(formatter, "{}", i),
+            FieldValue::ULong(i) => write!(formatter, "{}", i),
+            FieldValue::Float(f) => write!(formatter, "{}", f),
+            FieldValue::Bool(b) => write!(formatter, "{}", b),
+            FieldValue::DateTime(d) => write!(formatter, "{}", d.to_rfc3339()),
+            FieldValue::String(s) => write!(formatter, "{:?}", s),
+            FieldValue::ByteArray(b) => write!(formatter, "{:?}", b),
+            FieldValue::Array(a) => write!(formatter, "array of {}", a.len()),
+            FieldValue::Object(o) => write!(formatter, "object of {}", o.schema.id),
+            FieldValue::Enum(e) => write!(formatter, "case {}", e.case_id),
+        }
+    }
+}
+
+impl Debug for FieldValue {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(formatter, "{}", self)
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+/// Options controlling how a [`Backend`] reads and writes the
+/// data file.
+///
+/// These are read from the database [`Configuration`].
+///
+/// [`Backend`]: crate::backend::Backend
+/// [`Configuration`]: crate::database::Configuration
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Options {
+    /// Whether commits are recorded in a write-ahead log before
+    /// being written to the data file. Defaults to `true`.
+    #[serde(default = "enabled")]
+    pub write_ahead_log: bool,
+    /// The fraction of the data file occupied by removed blocks
+    /// above which the data file is compacted after a commit. If
+    /// `None`, the data file is only compacted on request.
+    #[serde(default)]
+    pub compaction_threshold: Option<f64>,
+    /// When commits are flushed to non-volatile storage. Defaults to
+    /// [`Durability::Full`].
+    #[serde(default)]
+    pub durability: Durability,
+    /// The longest time, in milliseconds, a commit waits for other
+    /// commits to be written and flushed with it. Commits which are
+    /// already waiting are always written together. Defaults to `0`.
+    #[serde(default)]
+    pub group_commit_delay_ms: u64,
+}
+
+/// The guarantee a [`Backend`] makes about a commit once it has
+/// been acknowledged.
+///
+/// [`Backend`]: crate::backend::Backend
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// Every commit is flushed to non-volatile storage before it is
+    /// acknowledged, including its writes to the data file, after
+    /// which the write-ahead log is emptied.
+    #[default]
+    Full,
+    /// Every commit is flushed to the write-ahead log before it is
+    /// acknowledged, but the data file is only flushed, and the log
+    /// emptied, once the log has grown past a limit. An acknowledged
+    /// commit is recovered from the log after a power loss. Without
+    /// a write-ahead log, this is the same as [`Durability::Full`].
+    Group,
+    /// Nothing is flushed when a commit is acknowledged, so recent
+    /// commits may be lost after a power loss, though not after the
+    /// process exits.
+    None,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            write_ahead_log: true,
+            compaction_threshold: None,
+            durability: Durability::Full,
+            group_commit_delay_ms: 0,
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use regex::Regex;
+
+/// A regular expression which strings are matched against by a
+/// [`Condition::Matches`].
+///
+/// Patterns use the syntax of the [`regex`] crate, which includes
+/// `.`, character classes like `[a-z]` and `[^@]`, the escapes `\d`,
+/// `\w` and `\s` and their negations `\D`, `\W` and `\S`, groups, the
+/// alternation `|`, the repetitions `*`, `+`, `?` and `{n,m}`, and the
+/// anchors `^` and `$`. Any other punctuation is matched literally
+/// when escaped with `\`.
+///
+/// Matching takes linear time in the length of the string whatever
+/// the pattern, and patterns which would compile to an excessively
+/// large automaton are rejected.
+///
+/// [`Condition::Matches`]: crate::backend::Condition::Matches
+#[derive(Clone)]
+pub struct Pattern {
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Compiles a pattern, or returns `None` if it is malformed.
+    pub fn new(source: &str) -> Option<Self> {
+        Regex::new(source).ok().map(|regex| Self { regex })
+    }
+
+    /// Returns the source of the pattern.
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    /// Returns whether the pattern matches any part of `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
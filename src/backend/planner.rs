@@ -0,0 +1,551 @@
+use crate::archive::{encode_key, encode_prefix, prefix_end};
+use crate::backend::{Backend, Condition, Expression, OperationError, Query};
+use crate::schema::{FieldType, FieldValue, Schema};
+use crate::util::{DocumentID, FieldID, SchemaID};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::mem::discriminant;
+use std::ops::Bound;
+
+/// A plan for finding the documents which match a [`Query`], made
+/// by [`Backend::plan_query`].
+pub struct QueryPlan {
+    /// The identifier of the queried collection.
+    pub collection: SchemaID,
+    /// How the documents which may match are found.
+    pub access: Access,
+    /// The query's condition, which each document found is checked
+    /// against, with its branches reordered so that as few as
+    /// possible are evaluated.
+    pub condition: Condition,
+}
+
+/// A way of finding the documents which may match a query.
+pub enum Access {
+    /// Read every document in the data file.
+    Scan { estimate: usize },
+    /// Look up a range of keys in an index.
+    Index(IndexLookup),
+    /// The documents found by either access.
+    Union(Box<Access>, Box<Access>),
+    /// The documents found by both accesses.
+    Intersection(Box<Access>, Box<Access>),
+}
+
+/// A lookup of one or more ranges of keys in an index.
+pub struct IndexLookup {
+    /// The position of the index in the backend's list of indexes.
+    index: usize,
+    /// The identifiers of the fields the index is keyed on.
+    pub fields: Vec<FieldID>,
+    /// The comparisons which every document in the ranges satisfies.
+    pub conditions: Vec<Condition>,
+    ranges: Vec<KeyRange>,
+    /// The number of documents in the ranges.
+    pub estimate: usize,
+}
+
+/// The lower and upper bounds of a range of keys.
+type KeyRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+impl Access {
+    /// Returns the estimated number of documents found.
+    pub fn estimate(&self) -> usize {
+        match self {
+            Access::Scan { estimate } => *estimate,
+            Access::Index(lookup) => lookup.estimate,
+            Access::Union(left, right) => left.estimate() + right.estimate(),
+            Access::Intersection(left, right) => left.estimate().min(right.estimate()),
+        }
+    }
+}
+
+/// The cost of reading a document found with an index, relative to
+/// reading a document during a scan, which reads them in the order
+/// they are stored.
+const INDEX_READ_COST: usize = 4;
+
+/// The assumed fraction of documents for which an equality holds,
+/// when it can't be looked up in an index.
+const EQUAL_SELECTIVITY: f64 = 0.1;
+
+/// The assumed fraction of documents for which any other condition
+/// holds, when it can't be looked up in an index.
+const DEFAULT_SELECTIVITY: f64 = 1.0 / 3.0;
+
+/// The most ranges of keys an index lookup is planned with, when
+/// fields are compared with several values.
+const MAX_LOOKUP_RANGES: usize = 256;
+
+impl Backend {
+    /// Plans how to find the documents matching a [`Query`].
+    ///
+    /// The documents which may match are found with the indexes
+    /// (see [`Backend::plan_access`]) if that is estimated to be
+    /// faster than reading every document. The condition's `and`s
+    /// and `or`s are reordered by their estimated selectivity, so
+    /// that each document is ruled in or out after evaluating as few
+    /// of their branches as possible.
+    pub(super) fn plan_query(&self, query: Query) -> Result<QueryPlan, OperationError> {
+        let schema = self
+            .collections
+            .iter()
+            .find(|s| s.id == query.collection)
+            .ok_or(OperationError::UnknownSchemaIdentifier)?;
+        let total = self.documents.len();
+        let access = match self.plan_access(schema, &query.condition)? {
+            Some(access) if access.estimate() * INDEX_READ_COST <= total => access,
+            _ => Access::Scan { estimate: total },
+        };
+        Ok(QueryPlan {
+            collection: query.collection,
+            access,
+            condition: self.reorder(schema, query.condition)?,
+        })
+    }
+
+    /// Plans how to find the documents in a collection which may
+    /// match a [`Condition`] using the indexes, or returns `None` if
+    /// the indexes can't narrow them down.
+    ///
+    /// The comparisons of fields with values of the fields' types
+    /// which must all hold are looked up in the index they narrow
+    /// down the most (see [`Backend::index_lookup`]). Conditions
+    /// joined with `or` narrow down the documents if both of them
+    /// can be looked up.
+    pub(super) fn plan_access(
+        &self,
+        schema: &Schema,
+        condition: &Condition,
+    ) -> Result<Option<Access>, OperationError> {
+        Ok(match condition {
+            Condition::Or(left, right) => {
+                match (
+                    self.plan_access(schema, left)?,
+                    self.plan_access(schema, right)?,
+                ) {
+                    (Some(left), Some(right)) => {
+                        Some(Access::Union(Box::new(left), Box::new(right)))
+                    }
+                    _ => None,
+                }
+            }
+            Condition::And(..)
+            | Condition::Equal(..)
+            | Condition::GreaterThan(..)
+            | Condition::GreaterOrEqual(..)
+            | Condition::LessThan(..)
+            | Condition::LessOrEqual(..)
+            | Condition::Prefix(..)
+            | Condition::OneOf(..) => {
+                let mut conjuncts = vec![];
+                Self::conjuncts(condition, &mut conjuncts);
+                let comparisons: Vec<Comparison> = conjuncts
+                    .iter()
+                    .flat_map(|c| Self::comparisons(schema, c))
+                    .collect();
+                let mut access = self.index_lookup(schema, &comparisons)?.map(Access::Index);
+                for conjunct in conjuncts {
+                    if !matches!(conjunct, Condition::Or(..)) {
+                        continue;
+                    }
+                    if let Some(other) = self.plan_access(schema, conjunct)? {
+                        access = Some(match access {
+                            Some(access) => Access::Intersection(Box::new(access), Box::new(other)),
+                            None => other,
+                        });
+                    }
+                }
+                access
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns the identifiers of the documents found by an
+    /// [`Access`], or `None` if every document must be read.
+    ///
+    /// The documents found may not all match the query, so each must
+    /// still be checked against its condition.
+    pub(super) fn candidates(
+        &self,
+        access: &Access,
+    ) -> Result<Option<BTreeSet<DocumentID>>, OperationError> {
+        Ok(match access {
+            Access::Scan { .. } => None,
+            Access::Index(lookup) => {
+                let mut ids = BTreeSet::new();
+                for (lower, upper) in &lookup.ranges {
+                    ids.extend(self.indexes[lookup.index].range(
+                        &self.io,
+                        lower.clone(),
+                        upper.clone(),
+                    )?);
+                }
+                Some(ids)
+            }
+            Access::Union(left, right) => match (self.candidates(left)?, self.candidates(right)?) {
+                (Some(left), Some(right)) => Some(&left | &right),
+                _ => None,
+            },
+            Access::Intersection(left, right) => {
+                match (self.candidates(left)?, self.candidates(right)?) {
+                    (Some(left), Some(right)) => Some(&left & &right),
+                    (candidates, None) | (None, candidates) => candidates,
+                }
+            }
+        })
+    }
+
+    /// Collects the conditions joined with `and` which must all hold
+    /// for a condition to hold.
+    fn conjuncts<'a>(condition: &'a Condition, conjuncts: &mut Vec<&'a Condition>) {
+        match condition {
+            Condition::And(left, right) => {
+                Self::conjuncts(left, conjuncts);
+                Self::conjuncts(right, conjuncts);
+            }
+            condition => conjuncts.push(condition),
+        }
+    }
+
+    /// Plans a lookup of the documents which may satisfy every
+    /// comparison in the index they narrow down the most, or returns
+    /// `None` if no index can be used.
+    ///
+    /// An index is used with equalities on a prefix of its fields,
+    /// followed by at most a lower and an upper bound on the next
+    /// field, so that the documents are found with a range of keys
+    /// for each combination of the values the fields are compared
+    /// with. The index with the longest equality prefix is chosen,
+    /// then the one with the most bounds.
+    fn index_lookup(
+        &self,
+        schema: &Schema,
+        comparisons: &[Comparison],
+    ) -> Result<Option<IndexLookup>, OperationError> {
+        let find = |field: FieldID, ordering: Ordering| {
+            comparisons
+                .iter()
+                .find(|c| c.field == field && c.ordering == ordering)
+        };
+        let mut best = None;
+        let mut best_score = (0, 0);
+        for (number, index) in self.indexes.iter().enumerate() {
+            if index.collection != schema.id {
+                continue;
+            }
+            let mut equalities = vec![];
+            let mut ranges = 1;
+            for field in &index.fields {
+                match find(*field, Ordering::Equal) {
+                    Some(comparison) if ranges * comparison.keys.len() <= MAX_LOOKUP_RANGES => {
+                        ranges *= comparison.keys.len();
+                        equalities.push(comparison);
+                    }
+                    _ => break,
+                }
+            }
+            let next = index.fields.get(equalities.len());
+            let lower = next.and_then(|f| find(*f, Ordering::Greater));
+            let upper = next.and_then(|f| find(*f, Ordering::Less));
+            let score = (
+                equalities.len(),
+                lower.is_some() as usize + upper.is_some() as usize,
+            );
+            if score > best_score {
+                best_score = score;
+                best = Some((number, equalities, lower, upper));
+            }
+        }
+        let Some((number, equalities, lower, upper)) = best else {
+            return Ok(None);
+        };
+        let index = &self.indexes[number];
+        // Every combination of the values the prefix is compared with
+        let mut prefixes = vec![vec![]];
+        for comparison in &equalities {
+            prefixes = prefixes
+                .iter()
+                .flat_map(|prefix| {
+                    comparison
+                        .keys
+                        .iter()
+                        .map(move |key| [prefix.as_slice(), key].concat())
+                })
+                .collect();
+        }
+        let ranges: Vec<_> = prefixes
+            .iter()
+            .map(|prefix| Self::key_range(prefix, lower, upper))
+            .collect();
+        let mut estimate = 0;
+        for (lower, upper) in &ranges {
+            estimate += index.range(&self.io, lower.clone(), upper.clone())?.len();
+        }
+        let mut conditions: Vec<&Condition> = equalities
+            .into_iter()
+            .chain(lower)
+            .chain(upper)
+            .map(|c| c.condition)
+            .collect();
+        // A prefix is both bounds
+        conditions.dedup_by(|a, b| std::ptr::eq(*a, *b));
+        let conditions = conditions.into_iter().cloned().collect();
+        Ok(Some(IndexLookup {
+            index: number,
+            fields: index.fields.clone(),
+            conditions,
+            ranges,
+            estimate,
+        }))
+    }
+
+    /// Returns the range of keys beginning with `prefix` which are
+    /// within the bounds on the next field.
+    fn key_range(
+        prefix: &[u8],
+        lower: Option<&Comparison>,
+        upper: Option<&Comparison>,
+    ) -> KeyRange {
+        let mut upper_bound = match upper {
+            Some(comparison) if comparison.inclusive => {
+                let key = [prefix, &comparison.keys[0]].concat();
+                prefix_end(&key).map_or(Bound::Unbounded, Bound::Excluded)
+            }
+            Some(comparison) => Bound::Excluded([prefix, &comparison.keys[0]].concat()),
+            None => prefix_end(prefix).map_or(Bound::Unbounded, Bound::Excluded),
+        };
+        let lower_bound = match lower {
+            Some(comparison) if comparison.inclusive => {
+                Bound::Included([prefix, &comparison.keys[0]].concat())
+            }
+            Some(comparison) => {
+                let key = [prefix, &comparison.keys[0]].concat();
+                match prefix_end(&key) {
+                    Some(end) => Bound::Included(end),
+                    // Nothing is greater than the greatest possible key
+                    None => {
+                        upper_bound = Bound::Excluded(key.clone());
+                        Bound::Excluded(key)
+                    }
+                }
+            }
+            None => Bound::Included(prefix.to_vec()),
+        };
+        (lower_bound, upper_bound)
+    }
+
+    /// Returns the comparisons of a field with values a condition
+    /// makes, if the values are of the field's type.
+    ///
+    /// A string field starting with a value is compared as being at
+    /// least the value, and less than the first key after every
+    /// string starting with it.
+    fn comparisons<'a>(schema: &Schema, condition: &'a Condition) -> Vec<Comparison<'a>> {
+        let field_type = |field: &FieldID| {
+            schema
+                .fields
+                .iter()
+                .find(|f| f.id == *field)
+                .map(|f| &f.field_type)
+        };
+        match condition {
+            Condition::Prefix(Expression::Field(field), Expression::Value(value)) => {
+                let (Some(FieldType::String), FieldValue::String(prefix)) =
+                    (field_type(field), value)
+                else {
+                    return vec![];
+                };
+                let mut key = vec![];
+                encode_prefix(prefix.as_bytes(), &mut key);
+                let end = prefix_end(&key);
+                let lower = Comparison {
+                    field: *field,
+                    ordering: Ordering::Greater,
+                    inclusive: true,
+                    keys: vec![key],
+                    condition,
+                };
+                match end {
+                    Some(end) => vec![
+                        lower,
+                        Comparison {
+                            field: *field,
+                            ordering: Ordering::Less,
+                            inclusive: false,
+                            keys: vec![end],
+                            condition,
+                        },
+                    ],
+                    None => vec![lower],
+                }
+            }
+            Condition::OneOf(Expression::Field(field), values) => {
+                let Some(field_type) = field_type(field) else {
+                    return vec![];
+                };
+                let mut keys = vec![];
+                for value in values.values() {
+                    let mut key = vec![];
+                    if discriminant(field_type) != discriminant(&value.simple_type())
+                        || !encode_key(value, &mut key)
+                    {
+                        return vec![];
+                    }
+                    keys.push(key);
+                }
+                keys.sort();
+                keys.dedup();
+                vec![Comparison {
+                    field: *field,
+                    ordering: Ordering::Equal,
+                    inclusive: true,
+                    keys,
+                    condition,
+                }]
+            }
+            condition => Self::comparison(schema, condition).into_iter().collect(),
+        }
+    }
+
+    /// Returns the comparison of a field with a value a condition
+    /// makes, if the value is of the field's type.
+    fn comparison<'a>(schema: &Schema, condition: &'a Condition) -> Option<Comparison<'a>> {
+        let (ordering, inclusive, left, right) = match condition {
+            Condition::Equal(left, right) => (Ordering::Equal, true, left, right),
+            Condition::GreaterThan(left, right) => (Ordering::Greater, false, left, right),
+            Condition::GreaterOrEqual(left, right) => (Ordering::Greater, true, left, right),
+            Condition::LessThan(left, right) => (Ordering::Less, false, left, right),
+            Condition::LessOrEqual(left, right) => (Ordering::Less, true, left, right),
+            _ => return None,
+        };
+        let (ordering, field, value) = match (left, right) {
+            (Expression::Field(field), Expression::Value(value)) => (ordering, field, value),
+            (Expression::Value(value), Expression::Field(field)) => {
+                (ordering.reverse(), field, value)
+            }
+            _ => return None,
+        };
+        let field_type = &schema.fields.iter().find(|f| f.id == *field)?.field_type;
+        if discriminant(field_type) != discriminant(&value.simple_type()) {
+            return None;
+        }
+        let mut key = vec![];
+        encode_key(value, &mut key).then_some(Comparison {
+            field: *field,
+            ordering,
+            inclusive,
+            keys: vec![key],
+            condition,
+        })
+    }
+
+    /// Estimates the fraction of a collection's documents for which
+    /// a condition holds.
+    ///
+    /// Comparisons which can be looked up in an index are estimated
+    /// from the number of documents the lookup finds. Other
+    /// conditions are assumed to hold for a fixed fraction of
+    /// documents.
+    fn selectivity(&self, schema: &Schema, condition: &Condition) -> Result<f64, OperationError> {
+        Ok(match condition {
+            Condition::And(left, right) => {
+                self.selectivity(schema, left)? * self.selectivity(schema, right)?
+            }
+            Condition::Or(left, right) => {
+                let (left, right) = (
+                    self.selectivity(schema, left)?,
+                    self.selectivity(schema, right)?,
+                );
+                left + right - left * right
+            }
+            Condition::Not(condition) => 1.0 - self.selectivity(schema, condition)?,
+            condition => {
+                let lookup = self.index_lookup(schema, &Self::comparisons(schema, condition))?;
+                match (lookup, condition) {
+                    (Some(lookup), _) => {
+                        let size = self.indexes[lookup.index].len.max(1);
+                        lookup.estimate as f64 / size as f64
+                    }
+                    (None, Condition::Equal(..) | Condition::EqualIgnoreCase(..)) => {
+                        EQUAL_SELECTIVITY
+                    }
+                    (None, Condition::NotEqual(..)) => 1.0 - EQUAL_SELECTIVITY,
+                    (None, Condition::OneOf(_, values)) => {
+                        1.0 - (1.0 - EQUAL_SELECTIVITY).powi(values.values().len() as i32)
+                    }
+                    (None, _) => DEFAULT_SELECTIVITY,
+                }
+            }
+        })
+    }
+
+    /// Reorders the branches of a condition's `and`s so that those
+    /// least likely to hold are evaluated first, and of its `or`s so
+    /// that those most likely to hold are, so that evaluation stops
+    /// as early as possible.
+    fn reorder(&self, schema: &Schema, condition: Condition) -> Result<Condition, OperationError> {
+        let and = match condition {
+            Condition::And(..) => true,
+            Condition::Or(..) => false,
+            Condition::Not(condition) => {
+                return Ok(Condition::Not(Box::new(self.reorder(schema, *condition)?)))
+            }
+            condition => return Ok(condition),
+        };
+        let mut branches = vec![];
+        Self::branches(condition, and, &mut branches);
+        let mut branches: Vec<(f64, Condition)> = branches
+            .into_iter()
+            .map(|branch| {
+                let branch = self.reorder(schema, branch)?;
+                Ok((self.selectivity(schema, &branch)?, branch))
+            })
+            .collect::<Result<_, OperationError>>()?;
+        if and {
+            branches.sort_by(|a, b| a.0.total_cmp(&b.0));
+        } else {
+            branches.sort_by(|a, b| b.0.total_cmp(&a.0));
+        }
+        let mut branches = branches.into_iter().map(|(_, branch)| branch);
+        let first = branches.next().unwrap();
+        Ok(branches.fold(first, |left, right| {
+            if and {
+                Condition::And(Box::new(left), Box::new(right))
+            } else {
+                Condition::Or(Box::new(left), Box::new(right))
+            }
+        }))
+    }
+
+    /// Collects the branches of a chain of `and`s, or of `or`s.
+    fn branches(condition: Condition, and: bool, branches: &mut Vec<Condition>) {
+        match condition {
+            Condition::And(left, right) if and => {
+                Self::branches(*left, and, branches);
+                Self::branches(*right, and, branches);
+            }
+            Condition::Or(left, right) if !and => {
+                Self::branches(*left, and, branches);
+                Self::branches(*right, and, branches);
+            }
+            condition => branches.push(condition),
+        }
+    }
+}
+
+/// A comparison of a field with a value, or for an equality, any of
+/// several values, which can be looked up in an index.
+struct Comparison<'a> {
+    field: FieldID,
+    /// How the field's value compares to the value.
+    ordering: Ordering,
+    /// Whether the field's value may also be equal to the value.
+    inclusive: bool,
+    /// The encoded value, or for an equality, the encoded values the
+    /// field may be equal to.
+    keys: Vec<Vec<u8>>,
+    /// The condition making the comparison.
+    condition: &'a Condition,
+}
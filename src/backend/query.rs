@@ -1,6 +1,11 @@
-use crate::backend::OperationError;
-use crate::schema::{Document, FieldType, FieldValue};
-use crate::util::{FieldID, SchemaID};
+use crate::archive::encode_key;
+use crate::backend::{OperationError, Pattern};
+use crate::schema::{Document, FieldType, FieldValue, Schema};
+use crate::util::{CaseID, FieldID, SchemaID};
+use chrono::Duration;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::mem::discriminant;
 
 /// A query in a select statement.
 pub struct Query {
@@ -13,11 +18,37 @@ pub struct Query {
 
 /// A boolean condition which a [`Document`] either matches
 /// or does not match.
+#[derive(Clone)]
 pub enum Condition {
     Equal(Expression, Expression),
-    // NotEqual(Expression, Expression),
+    NotEqual(Expression, Expression),
     GreaterThan(Expression, Expression),
+    GreaterOrEqual(Expression, Expression),
     LessThan(Expression, Expression),
+    LessOrEqual(Expression, Expression),
+    /// Whether two strings are equal when case is ignored.
+    EqualIgnoreCase(Expression, Expression),
+    /// Whether a string starts with another.
+    Prefix(Expression, Expression),
+    /// Whether a string ends with another.
+    Suffix(Expression, Expression),
+    /// Whether a string contains another.
+    Contains(Expression, Expression),
+    /// Whether a [`Pattern`] matches any part of a string.
+    Matches(Expression, Pattern),
+    /// Whether a value is equal to any value in a [`ValueSet`].
+    OneOf(Expression, ValueSet),
+    /// Whether an array has an element equal to a value.
+    In(Expression, Expression),
+    /// Whether a condition holds for any element of an array, which
+    /// [`Expression::Placeholder`] refers to.
+    Any(Expression, Box<Condition>),
+    /// Whether a condition holds for every element of an array.
+    All(Expression, Box<Condition>),
+    /// Whether an enum is of a case, and if there is a condition,
+    /// whether it holds for the case's associated value, which
+    /// [`Expression::Placeholder`] refers to.
+    Case(Expression, CaseID, Option<Box<Condition>>),
     Or(Box<Condition>, Box<Condition>),
     And(Box<Condition>, Box<Condition>),
     Not(Box<Condition>),
@@ -28,10 +59,115 @@ pub enum Condition {
 ///
 /// This may be either a literal value specified in the
 /// condition expression, or a reference to a field on
-/// the document, which evaluates to that field's value.
+/// the document, which evaluates to that field's value,
+/// or to the document's identifier. A field's value may be
+/// followed into the sub-documents of object fields, and the
+/// elements of array fields.
+#[derive(Clone)]
 pub enum Expression {
     Value(FieldValue),
     Field(FieldID),
+    DocumentID,
+    /// A field of the object another expression evaluates to.
+    Member(Box<Expression>, FieldID),
+    /// An element of the array another expression evaluates to.
+    Element(Box<Expression>, usize),
+    /// The number of elements of the array another expression
+    /// evaluates to, as a `ULong`.
+    Length(Box<Expression>),
+    /// The array element a [`Condition::Any`] or [`Condition::All`]
+    /// is testing.
+    Placeholder,
+    /// The date another expression evaluates to, moved by a duration.
+    Offset(Box<Expression>, Duration),
+}
+
+/// The values a [`Condition::OneOf`] compares a value with.
+///
+/// Large sets are looked up by the values' index keys (see
+/// [`encode_key`]), which are equal only for equal values of the same
+/// type, rather than compared with each value in turn.
+#[derive(Clone)]
+pub struct ValueSet {
+    values: Vec<FieldValue>,
+    keys: Option<HashSet<Vec<u8>>>,
+}
+
+/// The number of values above which a [`ValueSet`] is hashed.
+const HASHED_SET_SIZE: usize = 8;
+
+impl ValueSet {
+    /// Creates a set of values, which should all be of the same type.
+    pub fn new(values: Vec<FieldValue>) -> Self {
+        let keys = if values.len() > HASHED_SET_SIZE {
+            values
+                .iter()
+                .map(|value| {
+                    let mut key = vec![];
+                    encode_key(value, &mut key).then_some(key)
+                })
+                .collect()
+        } else {
+            None
+        };
+        Self { values, keys }
+    }
+
+    /// Returns the values in the set, in the order they were given.
+    pub fn values(&self) -> &[FieldValue] {
+        &self.values
+    }
+
+    /// Returns whether the set has a value equal to `value`.
+    fn contains(&self, value: &FieldValue) -> Result<bool, OperationError> {
+        if let (Some(keys), Some(first)) = (&self.keys, self.values.first()) {
+            if discriminant(value) != discriminant(first) {
+                return Err(OperationError::ExpressionTypeMismatch {
+                    left: value.simple_type(),
+                    right: first.simple_type(),
+                });
+            }
+            let mut key = vec![];
+            if encode_key(value, &mut key) {
+                return Ok(keys.contains(&key));
+            }
+        }
+        for candidate in &self.values {
+            if equal(value, candidate)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Expression {
+    /// Returns the type of the values this expression evaluates to
+    /// on documents of a [`Schema`], where `_` refers to an array
+    /// element of type `element`, or `None` if it doesn't refer to a
+    /// field of the schema.
+    pub fn value_type(&self, schema: &Schema, element: Option<&FieldType>) -> Option<FieldType> {
+        match self {
+            Expression::Value(value) => Some(value.simple_type()),
+            Expression::Field(id) => schema
+                .fields
+                .iter()
+                .find(|f| f.id == *id)
+                .map(|f| f.field_type.clone()),
+            Expression::DocumentID => Some(FieldType::ULong),
+            Expression::Member(object, id) => match object.value_type(schema, element)? {
+                FieldType::Object(schema) => Expression::Field(*id).value_type(&schema, None),
+                _ => None,
+            },
+            Expression::Element(array, _) => match array.value_type(schema, element)? {
+                FieldType::Array(element) => Some(*element),
+                _ => None,
+            },
+            Expression::Length(_) => Some(FieldType::ULong),
+            Expression::Placeholder => element.cloned(),
+            Expression::Offset(date, _) => date.value_type(schema, element),
+        }
+    }
 }
 
 macro_rules! eval_match_arm {
@@ -51,50 +187,44 @@ impl Document {
     /// Evaluates whether this [`Document`] matches a
     /// [`Condition`].
     pub fn evaluate(&self, condition: &Condition) -> Result<bool, OperationError> {
+        self.evaluate_in(condition, None)
+    }
+
+    /// Evaluates whether this [`Document`] matches a [`Condition`],
+    /// where `_` refers to `element`.
+    fn evaluate_in(
+        &self,
+        condition: &Condition,
+        element: Option<&FieldValue>,
+    ) -> Result<bool, OperationError> {
         match condition {
             Condition::Equal(left, right) => {
-                let left_value = self.eval_expr(left)?;
-                let right_value = self.eval_expr(right)?;
-                let r = right_value;
-                match left_value {
-                    FieldValue::Int(l) => eval_match_arm!(Int, l, r, ==),
-                    FieldValue::UInt(l) => eval_match_arm!(UInt, l, r, ==),
-                    FieldValue::Long(l) => eval_match_arm!(Long, l, r, ==),
-                    FieldValue::ULong(l) => eval_match_arm!(ULong, l, r, ==),
-                    FieldValue::Float(l) => eval_match_arm!(Float, l, r, ==),
-                    FieldValue::Bool(l) => eval_match_arm!(Bool, l, r, ==),
-                    FieldValue::DateTime(l) => eval_match_arm!(DateTime, l, r, ==),
-                    FieldValue::String(l) => eval_match_arm!(String, l, r, ==),
-                    FieldValue::ByteArray(l) => eval_match_arm!(ByteArray, l, r, ==),
-                    FieldValue::Array(_) => Err(OperationError::InvalidExpressionType),
-                    FieldValue::Object(_) => Err(OperationError::InvalidExpressionType),
-                    FieldValue::Enum(_) => Err(OperationError::InvalidExpressionType),
-                }
+                let (Some(left_value), Some(right_value)) = (
+                    self.eval_expr(left, element)?,
+                    self.eval_expr(right, element)?,
+                ) else {
+                    return Ok(false);
+                };
+                equal(left_value.as_ref(), right_value.as_ref())
+            }
+            Condition::NotEqual(left, right) => {
+                let (Some(left_value), Some(right_value)) = (
+                    self.eval_expr(left, element)?,
+                    self.eval_expr(right, element)?,
+                ) else {
+                    return Ok(false);
+                };
+                equal(left_value.as_ref(), right_value.as_ref()).map(|equal| !equal)
             }
-            // Condition::NotEqual(left, right) => {
-            //     let left_value = self.eval_expr(left)?;
-            //     let right_value = self.eval_expr(right)?;
-            //     let r = right_value;
-            //     match left_value {
-            //         FieldValue::Int(l) => eval_match_arm!(Int, l, r, !=),
-            //         FieldValue::UInt(l) => eval_match_arm!(UInt, l, r, !=),
-            //         FieldValue::Long(l) => eval_match_arm!(Long, l, r, !=),
-            //         FieldValue::ULong(l) => eval_match_arm!(ULong, l, r, !=),
-            //         FieldValue::Float(l) => eval_match_arm!(Float, l, r, !=),
-            //         FieldValue::Bool(l) => eval_match_arm!(Bool, l, r, !=),
-            //         FieldValue::DateTime(l) => eval_match_arm!(DateTime, l, r, !=),
-            //         FieldValue::String(l) => eval_match_arm!(String, l, r, !=),
-            //         FieldValue::ByteArray(l) => eval_match_arm!(ByteArray, l, r, !=),
-            //         FieldValue::Array(_) => Err(OperationError::InvalidExpressionType),
-            //         FieldValue::Object(_) => Err(OperationError::InvalidExpressionType),
-            //         FieldValue::Enum(_) => Err(OperationError::InvalidExpressionType),
-            //     }
-            // }
             Condition::GreaterThan(left, right) => {
-                let left_value = self.eval_expr(left)?;
-                let right_value = self.eval_expr(right)?;
-                let r = right_value;
-                match left_value {
+                let (Some(left_value), Some(right_value)) = (
+                    self.eval_expr(left, element)?,
+                    self.eval_expr(right, element)?,
+                ) else {
+                    return Ok(false);
+                };
+                let r = right_value.as_ref();
+                match left_value.as_ref() {
                     FieldValue::Int(l) => eval_match_arm!(Int, l, r, >),
                     FieldValue::UInt(l) => eval_match_arm!(UInt, l, r, >),
                     FieldValue::Long(l) => eval_match_arm!(Long, l, r, >),
@@ -109,11 +239,38 @@ impl Document {
                     FieldValue::Enum(_) => Err(OperationError::InvalidExpressionType),
                 }
             }
+            Condition::GreaterOrEqual(left, right) => {
+                let (Some(left_value), Some(right_value)) = (
+                    self.eval_expr(left, element)?,
+                    self.eval_expr(right, element)?,
+                ) else {
+                    return Ok(false);
+                };
+                let r = right_value.as_ref();
+                match left_value.as_ref() {
+                    FieldValue::Int(l) => eval_match_arm!(Int, l, r, >=),
+                    FieldValue::UInt(l) => eval_match_arm!(UInt, l, r, >=),
+                    FieldValue::Long(l) => eval_match_arm!(Long, l, r, >=),
+                    FieldValue::ULong(l) => eval_match_arm!(ULong, l, r, >=),
+                    FieldValue::Float(l) => eval_match_arm!(Float, l, r, >=),
+                    FieldValue::Bool(_) => Err(OperationError::InvalidExpressionType),
+                    FieldValue::DateTime(l) => eval_match_arm!(DateTime, l, r, >=),
+                    FieldValue::String(l) => eval_match_arm!(String, l, r, >=),
+                    FieldValue::ByteArray(_) => Err(OperationError::InvalidExpressionType),
+                    FieldValue::Array(_) => Err(OperationError::InvalidExpressionType),
+                    FieldValue::Object(_) => Err(OperationError::InvalidExpressionType),
+                    FieldValue::Enum(_) => Err(OperationError::InvalidExpressionType),
+                }
+            }
             Condition::LessThan(left, right) => {
-                let left_value = self.eval_expr(left)?;
-                let right_value = self.eval_expr(right)?;
-                let r = right_value;
-                match left_value {
+                let (Some(left_value), Some(right_value)) = (
+                    self.eval_expr(left, element)?,
+                    self.eval_expr(right, element)?,
+                ) else {
+                    return Ok(false);
+                };
+                let r = right_value.as_ref();
+                match left_value.as_ref() {
                     FieldValue::Int(l) => eval_match_arm!(Int, l, r, <),
                     FieldValue::UInt(l) => eval_match_arm!(UInt, l, r, <),
                     FieldValue::Long(l) => eval_match_arm!(Long, l, r, <),
@@ -128,22 +285,243 @@ impl Document {
                     FieldValue::Enum(_) => Err(OperationError::InvalidExpressionType),
                 }
             }
-            Condition::Or(left, right) => Ok(self.evaluate(left)? || self.evaluate(right)?),
-            Condition::And(left, right) => Ok(self.evaluate(left)? && self.evaluate(right)?),
-            Condition::Not(condition) => Ok(!self.evaluate(condition)?),
+            Condition::LessOrEqual(left, right) => {
+                let (Some(left_value), Some(right_value)) = (
+                    self.eval_expr(left, element)?,
+                    self.eval_expr(right, element)?,
+                ) else {
+                    return Ok(false);
+                };
+                let r = right_value.as_ref();
+                match left_value.as_ref() {
+                    FieldValue::Int(l) => eval_match_arm!(Int, l, r, <=),
+                    FieldValue::UInt(l) => eval_match_arm!(UInt, l, r, <=),
+                    FieldValue::Long(l) => eval_match_arm!(Long, l, r, <=),
+                    FieldValue::ULong(l) => eval_match_arm!(ULong, l, r, <=),
+                    FieldValue::Float(l) => eval_match_arm!(Float, l, r, <=),
+                    FieldValue::Bool(_) => Err(OperationError::InvalidExpressionType),
+                    FieldValue::DateTime(l) => eval_match_arm!(DateTime, l, r, <=),
+                    FieldValue::String(l) => eval_match_arm!(String, l, r, <=),
+                    FieldValue::ByteArray(_) => Err(OperationError::InvalidExpressionType),
+                    FieldValue::Array(_) => Err(OperationError::InvalidExpressionType),
+                    FieldValue::Object(_) => Err(OperationError::InvalidExpressionType),
+                    FieldValue::Enum(_) => Err(OperationError::InvalidExpressionType),
+                }
+            }
+            Condition::EqualIgnoreCase(left, right) => {
+                self.eval_strings(left, right, element, |l, r| {
+                    l.chars()
+                        .flat_map(char::to_lowercase)
+                        .eq(r.chars().flat_map(char::to_lowercase))
+                })
+            }
+            Condition::Prefix(left, right) => {
+                self.eval_strings(left, right, element, |l, r| l.starts_with(r))
+            }
+            Condition::Suffix(left, right) => {
+                self.eval_strings(left, right, element, |l, r| l.ends_with(r))
+            }
+            Condition::Contains(left, right) => {
+                self.eval_strings(left, right, element, |l, r| l.contains(r))
+            }
+            Condition::Matches(expr, pattern) => match self.eval_expr(expr, element)?.as_deref() {
+                Some(FieldValue::String(s)) => Ok(pattern.is_match(s)),
+                Some(value) => Err(OperationError::ExpressionTypeMismatch {
+                    left: value.simple_type(),
+                    right: FieldType::String,
+                }),
+                None => Ok(false),
+            },
+            Condition::OneOf(value, values) => match self.eval_expr(value, element)? {
+                Some(value) => values.contains(&value),
+                None => Ok(false),
+            },
+            Condition::In(value, array) => {
+                let (Some(value), Some(array)) = (
+                    self.eval_expr(value, element)?,
+                    self.eval_expr(array, element)?,
+                ) else {
+                    return Ok(false);
+                };
+                let FieldValue::Array(elements) = array.as_ref() else {
+                    return Err(OperationError::InvalidExpressionType);
+                };
+                for item in elements {
+                    if equal(item, &value)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Condition::Any(array, condition) => {
+                let Some(array) = self.eval_expr(array, element)? else {
+                    return Ok(false);
+                };
+                let FieldValue::Array(elements) = array.as_ref() else {
+                    return Err(OperationError::InvalidExpressionType);
+                };
+                for item in elements {
+                    if self.evaluate_in(condition, Some(item))? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Condition::All(array, condition) => {
+                let Some(array) = self.eval_expr(array, element)? else {
+                    return Ok(false);
+                };
+                let FieldValue::Array(elements) = array.as_ref() else {
+                    return Err(OperationError::InvalidExpressionType);
+                };
+                for item in elements {
+                    if !self.evaluate_in(condition, Some(item))? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            Condition::Case(value, case_id, condition) => {
+                let Some(value) = self.eval_expr(value, element)? else {
+                    return Ok(false);
+                };
+                let FieldValue::Enum(value) = value.as_ref() else {
+                    return Err(OperationError::InvalidExpressionType);
+                };
+                match (condition, &value.associated_value) {
+                    _ if value.case_id != *case_id => Ok(false),
+                    (None, _) => Ok(true),
+                    (Some(condition), Some(associated)) => {
+                        self.evaluate_in(condition, Some(associated))
+                    }
+                    (Some(_), None) => Ok(false),
+                }
+            }
+            Condition::Or(left, right) => {
+                Ok(self.evaluate_in(left, element)? || self.evaluate_in(right, element)?)
+            }
+            Condition::And(left, right) => {
+                Ok(self.evaluate_in(left, element)? && self.evaluate_in(right, element)?)
+            }
+            Condition::Not(condition) => Ok(!self.evaluate_in(condition, element)?),
+        }
+    }
+
+    /// Evaluates a comparison of two expressions which must both be
+    /// strings.
+    fn eval_strings(
+        &self,
+        left: &Expression,
+        right: &Expression,
+        element: Option<&FieldValue>,
+        compare: impl Fn(&str, &str) -> bool,
+    ) -> Result<bool, OperationError> {
+        let (Some(left_value), Some(right_value)) = (
+            self.eval_expr(left, element)?,
+            self.eval_expr(right, element)?,
+        ) else {
+            return Ok(false);
+        };
+        match (left_value.as_ref(), right_value.as_ref()) {
+            (FieldValue::String(l), FieldValue::String(r)) => Ok(compare(l, r)),
+            (l, r) => Err(OperationError::ExpressionTypeMismatch {
+                left: l.simple_type(),
+                right: r.simple_type(),
+            }),
         }
     }
 
-    fn eval_expr<'a>(&'a self, expr: &'a Expression) -> Result<&'a FieldValue, OperationError> {
+    /// Evaluates an [`Expression`] on this [`Document`], where `_`
+    /// refers to `element`, or returns `None` if it refers to a member
+    /// or element which doesn't exist, so that no comparison with it
+    /// holds.
+    fn eval_expr<'a>(
+        &'a self,
+        expr: &'a Expression,
+        element: Option<&'a FieldValue>,
+    ) -> Result<Option<Cow<'a, FieldValue>>, OperationError> {
         match expr {
-            Expression::Value(value) => Ok(value),
+            Expression::Value(value) => Ok(Some(Cow::Borrowed(value))),
             Expression::Field(field_id) => {
                 if let Some(field_instance) = self.fields.iter().find(|x| x.id == *field_id) {
-                    Ok(&field_instance.value)
+                    Ok(Some(Cow::Borrowed(&field_instance.value)))
                 } else {
                     Err(OperationError::UnknownFieldIdentifier)
                 }
             }
+            Expression::DocumentID => self
+                .id
+                .map(|id| Some(Cow::Owned(FieldValue::ULong(id))))
+                .ok_or(OperationError::InvalidExpressionType),
+            Expression::Member(object, field_id) => {
+                let Some(object) = self.eval_expr(object, element)? else {
+                    return Ok(None);
+                };
+                Ok(match object {
+                    Cow::Borrowed(FieldValue::Object(document)) => document
+                        .fields
+                        .iter()
+                        .find(|f| f.id == *field_id)
+                        .map(|f| Cow::Borrowed(&f.value)),
+                    Cow::Owned(FieldValue::Object(document)) => document
+                        .fields
+                        .into_iter()
+                        .find(|f| f.id == *field_id)
+                        .map(|f| Cow::Owned(f.value)),
+                    _ => return Err(OperationError::InvalidExpressionType),
+                })
+            }
+            Expression::Placeholder => element
+                .map(|value| Some(Cow::Borrowed(value)))
+                .ok_or(OperationError::InvalidExpressionType),
+            Expression::Length(array) => match self.eval_expr(array, element)?.as_deref() {
+                Some(FieldValue::Array(values)) => {
+                    Ok(Some(Cow::Owned(FieldValue::ULong(values.len() as u64))))
+                }
+                Some(_) => Err(OperationError::InvalidExpressionType),
+                None => Ok(None),
+            },
+            // A date moved out of the range of dates has no value
+            Expression::Offset(date, duration) => match self.eval_expr(date, element)?.as_deref() {
+                Some(FieldValue::DateTime(date)) => Ok(date
+                    .checked_add_signed(*duration)
+                    .map(|date| Cow::Owned(FieldValue::DateTime(date)))),
+                Some(_) => Err(OperationError::InvalidExpressionType),
+                None => Ok(None),
+            },
+            Expression::Element(array, index) => {
+                let Some(array) = self.eval_expr(array, element)? else {
+                    return Ok(None);
+                };
+                Ok(match array {
+                    Cow::Borrowed(FieldValue::Array(values)) => {
+                        values.get(*index).map(Cow::Borrowed)
+                    }
+                    Cow::Owned(FieldValue::Array(mut values)) if *index < values.len() => {
+                        Some(Cow::Owned(values.swap_remove(*index)))
+                    }
+                    Cow::Owned(FieldValue::Array(_)) => None,
+                    _ => return Err(OperationError::InvalidExpressionType),
+                })
+            }
         }
     }
 }
+
+/// Evaluates whether two values are equal.
+fn equal(left: &FieldValue, right: &FieldValue) -> Result<bool, OperationError> {
+    match left {
+        FieldValue::Int(l) => eval_match_arm!(Int, l, right, ==),
+        FieldValue::UInt(l) => eval_match_arm!(UInt, l, right, ==),
+        FieldValue::Long(l) => eval_match_arm!(Long, l, right, ==),
+        FieldValue::ULong(l) => eval_match_arm!(ULong, l, right, ==),
+        FieldValue::Float(l) => eval_match_arm!(Float, l, right, ==),
+        FieldValue::Bool(l) => eval_match_arm!(Bool, l, right, ==),
+        FieldValue::DateTime(l) => eval_match_arm!(DateTime, l, right, ==),
+        FieldValue::String(l) => eval_match_arm!(String, l, right, ==),
+        FieldValue::ByteArray(l) => eval_match_arm!(ByteArray, l, right, ==),
+        FieldValue::Array(_) => Err(OperationError::InvalidExpressionType),
+        FieldValue::Object(_) => Err(OperationError::InvalidExpressionType),
+        FieldValue::Enum(_) => Err(OperationError::InvalidExpressionType),
+    }
+}
@@ -1,4 +1,4 @@
-use crate::backend::{OperationError, Query, Reference};
+use crate::backend::{OperationError, Query, QueryPlan, Reference};
 use crate::schema::{Document, FieldInstance};
 use crate::util::{FieldID, LockType};
 use std::sync::mpsc::Sender;
@@ -19,8 +19,16 @@ pub struct Request {
 pub enum Operation {
     /// Find one [`Document`] in a collection.
     ///
-    /// See [`Query`]. Returns a [`Response::Selection`].
-    FindOne { query: Query },
+    /// See [`Query`]. Returns a [`Response::Selection`], or a
+    /// [`Response::Empty`] if no document matches. If `required`,
+    /// fails with [`OperationError::NoMatch`] instead.
+    FindOne { query: Query, required: bool },
+    /// Find every [`Document`] in a collection matching a query.
+    ///
+    /// See [`Query`]. Returns a [`Response::Selections`], or a
+    /// [`Response::Empty`] if no document matches. If `required`,
+    /// fails with [`OperationError::NoMatch`] instead.
+    FindMany { query: Query, required: bool },
     /// Wait to acquire a lock on a [`Selection`]. Takes the
     /// selection to wait for the lock on. Returns a
     /// [`Response::Ok`].
@@ -28,10 +36,6 @@ pub enum Operation {
         selection: Reference,
         lock: LockType,
     },
-    /// Create a [`Document`] on a collection.
-    ///
-    /// Returns a [`Response::Selection`].
-    Create { document: Document },
     /// Read some fields of the [`Document`] referred to by
     /// `selection`.
     ///
@@ -41,29 +45,47 @@ pub enum Operation {
         selection: Reference,
         fields: Vec<FieldID>,
     },
-    /// Update the [`Document`] referred to by `selection`,
-    /// replacing its existin fields with new [`FieldInstance`]s.
+    /// Atomically apply a transaction's [`Change`]s.
+    ///
+    /// Returns a [`Response::Ok`] once the changes are written.
+    Commit { changes: Vec<Change> },
+    /// Release the lock on [`Selection`].
+    Release {
+        selection: Reference,
+        lock: LockType,
+    },
+    /// Rewrite the data file without its removed blocks.
+    ///
+    /// Returns a [`Response::Ok`] once the data file is rewritten.
+    Compact,
+    /// Plan how to find the documents matching a query, without
+    /// finding them.
     ///
-    /// Returns a [`Response::Ok`].
+    /// See [`QueryPlan`]. Returns a [`Response::Plan`].
+    Explain { query: Query },
+}
+
+/// A change to a [`Document`], applied by an [`Operation::Commit`].
+pub enum Change {
+    /// Create a new [`Document`] on a collection.
+    Create { document: Document },
+    /// Replace the fields of the [`Document`] referred to by
+    /// `selection` with new [`FieldInstance`]s.
     Update {
         selection: Reference,
         fields: Vec<FieldInstance>,
     },
     /// Delete the [`Document`] referred to by `selection`.
-    ///
-    /// Returns a [`Response::Ok`].
     Delete { selection: Reference },
-    /// Release the lock on [`Selection`].
-    Release {
-        selection: Reference,
-        lock: LockType,
-    },
 }
 
 /// A response to a [`Request`].
 pub enum Response {
     Selection(Reference),
+    Selections(Vec<Reference>),
     Document(Document),
+    Empty,
+    Plan(QueryPlan),
     Ok,
 }
 
@@ -77,6 +99,20 @@ impl Response {
         }
     }
 
+    /// Returns Some(Vec<Reference>) if this [`Response`] is a
+    /// [`Response::Selections`], or None otherwise.
+    pub fn get_selections(self) -> Option<Vec<Reference>> {
+        match self {
+            Response::Selections(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if this [`Response`] is a [`Response::Empty`].
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Response::Empty)
+    }
+
     /// Returns Some(Document) if this [`Response`] is a
     /// [`Response::Document`], or None otherwise.
     pub fn get_document(self) -> Option<Document> {
@@ -86,6 +122,15 @@ impl Response {
         }
     }
 
+    /// Returns Some(QueryPlan) if this [`Response`] is a
+    /// [`Response::Plan`], or None otherwise.
+    pub fn get_plan(self) -> Option<QueryPlan> {
+        match self {
+            Response::Plan(p) => Some(p),
+            _ => None,
+        }
+    }
+
     pub fn get_ok(self) -> Option<()> {
         match self {
             Response::Ok => Some(()),
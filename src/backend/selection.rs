@@ -1,4 +1,5 @@
 use crate::schema::Schema;
+use crate::util::DocumentID;
 
 /// A pointer to a [`Document`] in the storage file.
 ///
@@ -14,18 +15,9 @@ pub struct Reference {
     ///
     /// [`Document`]: crate::schema::Document
     pub schema: Schema,
-    /// The offset in the storage file at which the
-    /// [`Document`] is stored.
+    /// The identifier of the [`Document`], which is unchanged
+    /// when the document is updated or moved.
     ///
     /// [`Document`]: crate::schema::Document
-    pub(super) position: usize,
+    pub id: DocumentID,
 }
-
-// A pointer to a list of [`Document`][crate::schema::Document]s.
-//
-// See [`Selection`].
-// #[derive(Clone)]
-// pub struct ManySelection {
-//     pub schema: Schema,
-//     pub(super) positions: Vec<usize>,
-// }
@@ -0,0 +1,176 @@
+use crate::archive::{FileHeader, FormatError, WriteAheadLog, WriteBatch, HEADER_LENGTH};
+use crate::backend::indexing::StoredIndexes;
+use crate::backend::Backend;
+use crate::util::BlockPosition;
+use std::fs::OpenOptions;
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+
+impl Backend {
+    /// Replays any commits left in the write-ahead log at
+    /// `path` with a `.wal` extension.
+    ///
+    /// The log is replayed even if it has since been disabled, so
+    /// that a committed transaction is never lost.
+    pub(super) fn replay_log(&mut self) -> io::Result<()> {
+        let write_ahead_log = self.options.write_ahead_log;
+        let log_path = format!("{}.wal", self.path);
+        if !write_ahead_log && !Path::new(&log_path).exists() {
+            return Ok(());
+        }
+        let mut log = WriteAheadLog::open(&log_path)?;
+        for batch in log.recover()? {
+            self.io.apply(&batch)?;
+        }
+        self.io.sync()?;
+        log.clear()?;
+        if write_ahead_log {
+            self.log = Some(log);
+        }
+        Ok(())
+    }
+
+    /// Validates the data file's [`FileHeader`], or writes a new
+    /// header if the file is empty.
+    ///
+    /// A file written before files had a header, which begins with
+    /// its first block, is migrated (see [`Backend::migrate`]).
+    ///
+    /// If the schema definitions have changed since the file was last
+    /// opened, the header's fingerprint is updated.
+    pub(super) fn validate_header(&mut self) -> io::Result<()> {
+        let bytes = self.io.read_header()?;
+        if bytes.is_empty() {
+            self.header = FileHeader::new(&self.collections);
+            let mut batch = WriteBatch::new(HEADER_LENGTH);
+            batch.write_header(&self.header);
+            return self.write(&batch);
+        }
+        if matches!(bytes[0], 68 | 69) {
+            return self.migrate();
+        }
+        self.header = FileHeader::parse(&bytes).map_err(Self::format_error)?;
+        let fingerprint = FileHeader::fingerprint(&self.collections);
+        if self.header.schema_fingerprint != fingerprint {
+            println!(
+                "Startup: schema definitions have changed since the data file was last opened"
+            );
+            self.header.schema_fingerprint = fingerprint;
+            let mut batch = WriteBatch::new(self.io.end_position()?);
+            batch.write_header(&self.header);
+            self.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites a data file written before files had a header in
+    /// the current format.
+    ///
+    /// Documents had no identifiers then, so each document is
+    /// assigned one in the order they are stored.
+    pub(super) fn migrate(&mut self) -> io::Result<()> {
+        self.header = FileHeader::new(&self.collections);
+        self.io.migrate(&self.path, &mut self.header)?;
+        println!(
+            "Startup: migrated {} documents to format version {}",
+            self.header.next_document_id - 1,
+            self.header.version
+        );
+        Ok(())
+    }
+
+    fn format_error(error: FormatError) -> io::Error {
+        io::Error::new(ErrorKind::InvalidData, error)
+    }
+
+    /// Checks the data file for damaged blocks, such as a block torn
+    /// by a power loss during a write.
+    ///
+    /// Blocks whose checksums do not match their data are moved to a
+    /// quarantine file alongside the data file, so that no data is
+    /// discarded, and marked as removed, so that reading the other
+    /// documents isn't interrupted. Everything from the first
+    /// malformed block to the end of the file is also quarantined, and
+    /// the data file is truncated. Each entry in the quarantine file
+    /// is the offset of the damaged data, its length, then the data
+    /// itself.
+    ///
+    /// The check also finds the removed blocks in the file, which are
+    /// recorded in the [`FreeSpaceMap`] to be reused, and counted to
+    /// decide when to compact the file, the position of each
+    /// document, and the index nodes, which are returned to be
+    /// loaded (see [`Backend::load_indexes`]).
+    ///
+    /// [`FreeSpaceMap`]: crate::archive::FreeSpaceMap
+    pub(super) fn recover(&mut self) -> io::Result<StoredIndexes> {
+        let integrity = self.io.check_integrity()?;
+        let stored = StoredIndexes {
+            indexes: integrity.indexes,
+            nodes: integrity.index_nodes,
+            damaged: !integrity.corrupted.is_empty() || integrity.damaged_tail.is_some(),
+        };
+        self.live_bytes = integrity.live_bytes;
+        self.dead_bytes = integrity.holes.iter().map(|(_, size)| size).sum();
+        self.free_space.clear();
+        for (position, size) in integrity.holes {
+            self.free_space.insert(position, size);
+        }
+        self.documents = integrity
+            .documents
+            .into_iter()
+            .map(|(id, position)| (id, position as usize))
+            .collect();
+        // The header is written with each commit which creates
+        // documents, but a newer identifier may have been replayed
+        if let Some(max) = self.documents.keys().max() {
+            self.header.next_document_id = self.header.next_document_id.max(max.saturating_add(1));
+        }
+        let mut batch = WriteBatch::new(self.io.end_position()?);
+        for position in integrity.corrupted {
+            let size = self.io.block_size(position as usize)?;
+            let block = self.io.read_range(position - 1, size)?;
+            self.quarantine(position - 1, block)?;
+            batch.remove_block(position as usize);
+            self.free_space.insert(position - 1, size);
+            self.live_bytes -= size;
+            self.dead_bytes += size;
+            println!(
+                "Recovery: checksum mismatch in block at offset {}, moved the block to {}.quarantine",
+                position - 1,
+                self.path
+            );
+        }
+        if !batch.is_empty() {
+            self.write(&batch)?;
+        }
+        let position = match integrity.damaged_tail {
+            Some(position) => position,
+            None => return Ok(stored),
+        };
+        let removed = self.io.read_from(position)?;
+        let length = removed.len();
+        self.quarantine(position, removed)?;
+        self.io.truncate(position)?;
+        self.io.sync()?;
+        println!(
+            "Recovery: moved {} damaged bytes at offset {} to {}.quarantine",
+            length, position, self.path
+        );
+        Ok(stored)
+    }
+
+    /// Appends damaged data from `position` in the data file to the
+    /// quarantine file, flushing it before the data is removed from
+    /// the data file.
+    fn quarantine(&self, position: BlockPosition, mut data: Vec<u8>) -> io::Result<()> {
+        let mut quarantine = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(format!("{}.quarantine", self.path))?;
+        let mut buf = position.to_be_bytes().to_vec();
+        buf.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        buf.append(&mut data);
+        quarantine.write_all(&buf)?;
+        quarantine.sync_data()
+    }
+}
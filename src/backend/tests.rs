@@ -1,5 +1,6 @@
 #[allow(unused_imports)]
 use super::*;
+use crate::test_support::{test_document, test_field, test_schema};
 // use crate::schema::*;
 
 // struct Cleanup;
@@ -281,3 +282,1827 @@ use super::*;
 //         })
 //         .expect("Read error");
 // }
+
+struct TestFile(String);
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.0);
+        _ = std::fs::remove_file(format!("{}.wal", self.0));
+        _ = std::fs::remove_file(format!("{}.quarantine", self.0));
+        _ = std::fs::remove_file(format!("{}.compact", self.0));
+    }
+}
+
+impl TestFile {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir()
+            .join(format!("swift-db-backend-{}-{}", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        _ = std::fs::remove_file(format!("{}.wal", path));
+        _ = std::fs::remove_file(format!("{}.quarantine", path));
+        std::fs::File::create(&path).expect("File creation failed");
+        Self(path)
+    }
+}
+
+fn open_with_schemas(file: &TestFile, collections: Vec<crate::schema::Schema>) -> Backend {
+    let (_, rx) = std::sync::mpsc::channel();
+    Backend::new(file.0.clone(), collections, rx, super::Options::default())
+        .expect("Backend construction failed")
+}
+
+/// Finds the first document of a collection whose first field is
+/// `value`.
+fn find_equal(
+    backend: &mut Backend,
+    collection: crate::util::SchemaID,
+    value: crate::schema::FieldValue,
+) -> Result<Response, OperationError> {
+    backend.execute_operation(Operation::FindOne {
+        required: true,
+        query: Query {
+            collection,
+            condition: Condition::Equal(Expression::Field(0x1), Expression::Value(value)),
+        },
+    })
+}
+
+fn counter_schema() -> crate::schema::Schema {
+    test_schema(
+        "counters",
+        0x30,
+        vec![test_field("count", 0x1, crate::schema::FieldType::UInt)],
+    )
+}
+
+fn counter(count: u32) -> crate::schema::Document {
+    test_document(
+        counter_schema(),
+        vec![crate::schema::FieldValue::UInt(count)],
+    )
+}
+
+fn open_backend(file: &TestFile) -> Backend {
+    open_with_schemas(file, vec![counter_schema()])
+}
+
+fn open_backend_with(file: &TestFile, options: super::Options) -> Backend {
+    let (_, rx) = std::sync::mpsc::channel();
+    Backend::new(file.0.clone(), vec![counter_schema()], rx, options)
+        .expect("Backend construction failed")
+}
+
+fn read_counter(backend: &mut Backend, selection: Reference) -> Result<u32, OperationError> {
+    let document = backend
+        .execute_operation(Operation::Read {
+            selection,
+            fields: vec![0x1],
+        })?
+        .get_document()
+        .unwrap();
+    match document.fields[0].value {
+        crate::schema::FieldValue::UInt(count) => Ok(count),
+        _ => panic!("Unexpected field type"),
+    }
+}
+
+fn find_counter(backend: &mut Backend, count: u32) -> Result<Response, OperationError> {
+    find_equal(backend, 0x30, crate::schema::FieldValue::UInt(count))
+}
+
+#[test]
+fn commit_changes() {
+    let file = TestFile::new("commit");
+    let mut backend = open_backend(&file);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![
+                Change::Create {
+                    document: counter(1),
+                },
+                Change::Create {
+                    document: counter(2),
+                },
+            ],
+        })
+        .expect("Commit failed");
+    let selection = find_counter(&mut backend, 1)
+        .expect("Find failed")
+        .get_selection()
+        .unwrap();
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Update {
+                selection,
+                fields: counter(3).fields,
+            }],
+        })
+        .expect("Commit failed");
+    assert!(find_counter(&mut backend, 1).is_err());
+    assert!(find_counter(&mut backend, 2).is_ok());
+    assert!(find_counter(&mut backend, 3).is_ok());
+    assert_eq!(
+        std::fs::metadata(format!("{}.wal", file.0)).unwrap().len(),
+        0
+    );
+}
+
+#[test]
+fn replay_write_ahead_log() {
+    let file = TestFile::new("replay");
+    let mut log =
+        crate::archive::WriteAheadLog::open(&format!("{}.wal", file.0)).expect("Log open failed");
+    let mut batch = crate::archive::WriteBatch::new(crate::archive::HEADER_LENGTH);
+    batch.write_header(&crate::archive::FileHeader::new(&[counter_schema()]));
+    batch.append_block(counter(7).serialize());
+    log.append(&batch).expect("Log append failed");
+    drop(log);
+    let mut backend = open_backend(&file);
+    assert!(find_counter(&mut backend, 7).is_ok());
+}
+
+fn find_by_id(backend: &mut Backend, id: u64) -> Result<Response, OperationError> {
+    backend.execute_operation(Operation::FindOne {
+        required: true,
+        query: Query {
+            collection: 0x30,
+            condition: Condition::Equal(
+                Expression::DocumentID,
+                Expression::Value(crate::schema::FieldValue::ULong(id)),
+            ),
+        },
+    })
+}
+
+#[test]
+fn find_by_document_id() {
+    let file = TestFile::new("document-id");
+    let mut backend = open_backend(&file);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![
+                Change::Create {
+                    document: counter(1),
+                },
+                Change::Create {
+                    document: counter(2),
+                },
+            ],
+        })
+        .expect("Commit failed");
+    let selection = find_counter(&mut backend, 2)
+        .expect("Find failed")
+        .get_selection()
+        .unwrap();
+    assert_eq!(selection.id, 2);
+    let selection = find_by_id(&mut backend, 1)
+        .expect("Find failed")
+        .get_selection()
+        .unwrap();
+    assert_eq!(read_counter(&mut backend, selection).unwrap(), 1);
+
+    // Identifiers are not reused after a restart
+    drop(backend);
+    let mut backend = open_backend(&file);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(3),
+            }],
+        })
+        .expect("Commit failed");
+    let selection = find_by_id(&mut backend, 3)
+        .expect("Find failed")
+        .get_selection()
+        .unwrap();
+    assert_eq!(read_counter(&mut backend, selection).unwrap(), 3);
+}
+
+#[test]
+fn migrate_headerless_file() {
+    let file = TestFile::new("migrate");
+    // Before files had a header, blocks had no checksum, and
+    // documents no identifier
+    let block = |marker: u8, data: Vec<u8>| {
+        let mut block = vec![marker];
+        block.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        block.extend_from_slice(&data);
+        block
+    };
+    let bytes = [
+        block(69, counter(4).serialize()[8..].to_vec()),
+        block(68, counter(7).serialize()[8..].to_vec()),
+        block(69, counter(5).serialize()[8..].to_vec()),
+    ]
+    .concat();
+    std::fs::write(&file.0, bytes).unwrap();
+    let mut backend = open_backend(&file);
+    assert_eq!(backend.header.next_document_id, 3);
+    let selection = find_by_id(&mut backend, 2)
+        .expect("Find failed")
+        .get_selection()
+        .unwrap();
+    assert_eq!(read_counter(&mut backend, selection).unwrap(), 5);
+
+    // A migrated file is not migrated again
+    drop(backend);
+    let mut backend = open_backend(&file);
+    assert_eq!(backend.header.next_document_id, 3);
+    let selection = find_by_id(&mut backend, 1)
+        .expect("Find failed")
+        .get_selection()
+        .unwrap();
+    assert_eq!(read_counter(&mut backend, selection).unwrap(), 4);
+}
+
+#[test]
+fn discard_failed_write_from_log() {
+    let file = TestFile::new("failed-write");
+    let mut backend = open_backend(&file);
+    let log_path = format!("{}.wal", file.0);
+    let log_size = std::fs::metadata(&log_path).unwrap().len();
+    // A position the data file can't be written at
+    let mut batch = crate::archive::WriteBatch::new(1 << 63);
+    batch.append_block(counter(1).serialize());
+    assert!(backend.write(&batch).is_err());
+    assert_eq!(std::fs::metadata(&log_path).unwrap().len(), log_size);
+
+    // The batch isn't replayed when the file is reopened
+    drop(backend);
+    open_backend(&file);
+}
+
+#[test]
+fn group_durability() {
+    let file = TestFile::new("group");
+    let options = super::Options {
+        durability: super::Durability::Group,
+        ..Default::default()
+    };
+    let mut backend = open_backend_with(&file, options.clone());
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(1),
+            }],
+        })
+        .expect("Commit failed");
+    let log_path = format!("{}.wal", file.0);
+    assert!(std::fs::metadata(&log_path).unwrap().len() > 0);
+
+    // Writes to the data file which were never flushed are recovered
+    // from the log
+    drop(backend);
+    let data = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&file.0)
+        .unwrap();
+    data.set_len(crate::archive::HEADER_LENGTH).unwrap();
+    let mut backend = open_backend_with(&file, options);
+    assert!(find_counter(&mut backend, 1).is_ok());
+    assert_eq!(std::fs::metadata(&log_path).unwrap().len(), 0);
+}
+
+fn send(
+    sender: &std::sync::mpsc::Sender<super::Request>,
+    operation: Operation,
+) -> std::sync::mpsc::Receiver<Result<Response, OperationError>> {
+    let (return_channel, reciever) = std::sync::mpsc::channel();
+    sender
+        .send(super::Request {
+            operation,
+            return_channel,
+        })
+        .unwrap();
+    reciever
+}
+
+#[test]
+fn group_commits() {
+    let file = TestFile::new("group-commit");
+    let (sender, reciever) = std::sync::mpsc::channel();
+    let mut backend = Backend::new(
+        file.0.clone(),
+        vec![counter_schema()],
+        reciever,
+        super::Options::default(),
+    )
+    .expect("Backend construction failed");
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(1),
+            }],
+        })
+        .expect("Commit failed");
+    let selection = find_counter(&mut backend, 1)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+
+    // Queued commits are written together, except for a commit which
+    // changes a document changed earlier in the group
+    let responses = [
+        Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(2),
+            }],
+        },
+        Operation::Commit {
+            changes: vec![Change::Update {
+                selection: selection.clone(),
+                fields: counter(3).fields,
+            }],
+        },
+        Operation::Commit {
+            changes: vec![Change::Update {
+                selection: selection.clone(),
+                fields: counter(40000).fields,
+            }],
+        },
+        Operation::Read {
+            selection,
+            fields: vec![0x1],
+        },
+    ]
+    .into_iter()
+    .map(|operation| send(&sender, operation))
+    .collect::<Vec<_>>();
+    drop(sender);
+    backend.listen();
+    for response in &responses[..3] {
+        assert!(matches!(response.recv().unwrap(), Ok(Response::Ok)));
+    }
+    let document = responses[3]
+        .recv()
+        .unwrap()
+        .unwrap()
+        .get_document()
+        .unwrap();
+    assert!(matches!(
+        document.fields[0].value,
+        crate::schema::FieldValue::UInt(40000)
+    ));
+    assert!(find_counter(&mut backend, 2).is_ok());
+    assert!(find_counter(&mut backend, 3).is_err());
+}
+
+#[test]
+fn discard_failed_commit() {
+    use std::io::{Seek, SeekFrom, Write};
+    let file = TestFile::new("discard");
+    let mut backend = open_with_schemas(&file, vec![indexed_counter_schema()]);
+    let changes = (1..=2)
+        .map(|n| Change::Create {
+            document: counter(n),
+        })
+        .collect();
+    backend
+        .execute_operation(Operation::Commit { changes })
+        .expect("Commit failed");
+    let first = find_counter(&mut backend, 1)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+    let second = find_counter(&mut backend, 2)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+    let hole = backend.documents[&first.id];
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Delete { selection: first }],
+        })
+        .expect("Commit failed");
+
+    // Damage the second document, so that updating it fails after the
+    // new document was placed in the hole
+    let mut data = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&file.0)
+        .unwrap();
+    let position = backend.documents[&second.id] as u64;
+    data.seek(SeekFrom::Start(position + 20)).unwrap();
+    data.write_all(&[0xff]).unwrap();
+    let state = |backend: &Backend| {
+        (
+            backend.live_bytes,
+            backend.dead_bytes,
+            backend.header.next_document_id,
+        )
+    };
+    let before = state(&backend);
+    assert!(backend
+        .execute_operation(Operation::Commit {
+            changes: vec![
+                Change::Create {
+                    document: counter(3),
+                },
+                Change::Update {
+                    selection: second,
+                    fields: counter(4).fields,
+                },
+            ],
+        })
+        .is_err());
+    assert_eq!(state(&backend), before);
+
+    // The hole is still free
+    let id = backend.header.next_document_id;
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(3),
+            }],
+        })
+        .expect("Commit failed");
+    assert_eq!(backend.documents[&id], hole);
+}
+
+#[test]
+fn find_many_documents() {
+    let file = TestFile::new("find-many");
+    let mut backend = open_backend(&file);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: [5, 12, 8, 20]
+                .into_iter()
+                .map(|count| Change::Create {
+                    document: counter(count),
+                })
+                .collect(),
+        })
+        .expect("Commit failed");
+    let find = |backend: &mut Backend, count, required| {
+        backend.execute_operation(Operation::FindMany {
+            required,
+            query: Query {
+                collection: 0x30,
+                condition: Condition::GreaterThan(
+                    Expression::Field(0x1),
+                    Expression::Value(crate::schema::FieldValue::UInt(count)),
+                ),
+            },
+        })
+    };
+    let selections = find(&mut backend, 6, false)
+        .expect("Find failed")
+        .get_selections()
+        .unwrap();
+    let counts: Vec<u32> = selections
+        .into_iter()
+        .map(|selection| read_counter(&mut backend, selection).unwrap())
+        .collect();
+    assert_eq!(counts, vec![12, 8, 20]);
+    assert!(find(&mut backend, 20, false).unwrap().is_empty());
+    assert!(matches!(
+        find(&mut backend, 20, true),
+        Err(OperationError::NoMatch)
+    ));
+}
+
+#[test]
+fn find_no_match() {
+    let file = TestFile::new("no-match");
+    let mut backend = open_backend(&file);
+    let find = |backend: &mut Backend, required| {
+        backend.execute_operation(Operation::FindOne {
+            required,
+            query: Query {
+                collection: 0x30,
+                condition: Condition::Equal(
+                    Expression::Field(0x1),
+                    Expression::Value(crate::schema::FieldValue::UInt(1)),
+                ),
+            },
+        })
+    };
+    assert!(find(&mut backend, false).unwrap().is_empty());
+    assert!(matches!(
+        find(&mut backend, true),
+        Err(OperationError::NoMatch)
+    ));
+}
+
+#[test]
+fn quarantine_torn_block() {
+    let file = TestFile::new("torn");
+    let mut backend = open_backend(&file);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(1),
+            }],
+        })
+        .expect("Commit failed");
+    drop(backend);
+    let length = std::fs::metadata(&file.0).unwrap().len();
+    let mut data = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&file.0)
+        .unwrap();
+    std::io::Write::write_all(&mut data, &[69u8, 0, 0, 0, 0, 0, 0, 1, 0, 7, 7]).unwrap();
+    let mut backend = open_backend(&file);
+    assert_eq!(std::fs::metadata(&file.0).unwrap().len(), length);
+    let quarantine = std::fs::read(format!("{}.quarantine", file.0)).unwrap();
+    assert_eq!(quarantine.len(), 16 + 11);
+    assert!(find_counter(&mut backend, 1).is_ok());
+}
+
+#[test]
+fn quarantine_corrupted_block() {
+    use std::io::{Seek, SeekFrom, Write};
+    let file = TestFile::new("corrupted");
+    let mut backend = open_backend(&file);
+    let changes = (1..=3)
+        .map(|n| Change::Create {
+            document: counter(n),
+        })
+        .collect();
+    backend
+        .execute_operation(Operation::Commit { changes })
+        .expect("Commit failed");
+    let second = find_counter(&mut backend, 2)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+    let position = backend.documents[&second.id] as u64;
+    drop(backend);
+    let length = std::fs::metadata(&file.0).unwrap().len();
+    let mut data = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&file.0)
+        .unwrap();
+    data.seek(SeekFrom::Start(position + 20)).unwrap();
+    data.write_all(&[0xff]).unwrap();
+
+    // The other documents can still be found by reading every block
+    let mut backend = open_backend(&file);
+    assert!(find_counter(&mut backend, 1).is_ok());
+    assert!(find_counter(&mut backend, 3).is_ok());
+    assert!(matches!(
+        read_counter(&mut backend, second),
+        Err(OperationError::StaleReference)
+    ));
+    let block_size = (length - crate::archive::HEADER_LENGTH) / 3;
+    let quarantine = std::fs::read(format!("{}.quarantine", file.0)).unwrap();
+    assert_eq!(quarantine.len() as u64, 16 + block_size);
+    assert_eq!(quarantine[..8], (position - 1).to_be_bytes());
+
+    // The block's space is reused
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(4),
+            }],
+        })
+        .expect("Commit failed");
+    assert_eq!(std::fs::metadata(&file.0).unwrap().len(), length);
+    drop(backend);
+    let mut backend = open_backend(&file);
+    assert!(backend.io.check_integrity().unwrap().corrupted.is_empty());
+    assert!(find_counter(&mut backend, 4).is_ok());
+}
+
+#[test]
+fn reject_foreign_data_file() {
+    let file = TestFile::new("foreign");
+    std::fs::write(&file.0, b"not a data file").unwrap();
+    let (_, rx) = std::sync::mpsc::channel();
+    let error = Backend::new(
+        file.0.clone(),
+        vec![counter_schema()],
+        rx,
+        super::Options::default(),
+    )
+    .err()
+    .expect("Backend construction succeeded");
+    assert!(matches!(
+        crate::database::LifecycleError::from_backend(error),
+        crate::database::LifecycleError::DataFileFormatError(
+            crate::archive::FormatError::InvalidMagic
+        )
+    ));
+}
+
+#[test]
+fn create_missing_data_file() {
+    let directory =
+        std::env::temp_dir().join(format!("swift-db-backend-new-{}", std::process::id()));
+    _ = std::fs::remove_dir_all(&directory);
+    let path = directory.join("nested").join("data.sdb");
+    let (_, rx) = std::sync::mpsc::channel();
+    let backend = Backend::new(
+        path.to_string_lossy().into_owned(),
+        vec![counter_schema()],
+        rx,
+        super::Options::default(),
+    );
+    assert!(backend.is_ok());
+    let bytes = std::fs::read(&path).unwrap();
+    assert!(crate::archive::FileHeader::parse(&bytes).is_ok());
+    _ = std::fs::remove_dir_all(&directory);
+}
+
+#[test]
+fn compact_data_file() {
+    let file = TestFile::new("compact");
+    let mut backend = open_backend(&file);
+    let changes = (1..=3)
+        .map(|n| Change::Create {
+            document: counter(n),
+        })
+        .collect();
+    backend
+        .execute_operation(Operation::Commit { changes })
+        .expect("Commit failed");
+    let first = find_counter(&mut backend, 1)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+    let third = find_counter(&mut backend, 3)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Delete {
+                selection: first.clone(),
+            }],
+        })
+        .expect("Commit failed");
+    let length = std::fs::metadata(&file.0).unwrap().len();
+    backend
+        .execute_operation(Operation::Compact)
+        .expect("Compaction failed");
+    assert!(std::fs::metadata(&file.0).unwrap().len() < length);
+    assert_eq!(read_counter(&mut backend, third.clone()).unwrap(), 3);
+    assert!(matches!(
+        read_counter(&mut backend, first),
+        Err(OperationError::StaleReference)
+    ));
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Update {
+                selection: third,
+                fields: counter(4).fields,
+            }],
+        })
+        .expect("Commit failed");
+    drop(backend);
+    let mut backend = open_backend(&file);
+    assert!(find_counter(&mut backend, 2).is_ok());
+    assert!(find_counter(&mut backend, 3).is_err());
+    assert!(find_counter(&mut backend, 4).is_ok());
+}
+
+#[test]
+fn compact_above_threshold() {
+    let file = TestFile::new("compact-threshold");
+    let options = super::Options {
+        compaction_threshold: Some(0.4),
+        ..Default::default()
+    };
+    let mut backend = open_backend_with(&file, options);
+    let changes = (1..=3)
+        .map(|n| Change::Create {
+            document: counter(n),
+        })
+        .collect();
+    backend
+        .execute_operation(Operation::Commit { changes })
+        .expect("Commit failed");
+    let length = std::fs::metadata(&file.0).unwrap().len();
+    for n in 1..=2 {
+        assert_eq!(std::fs::metadata(&file.0).unwrap().len(), length);
+        let selection = find_counter(&mut backend, n)
+            .unwrap()
+            .get_selection()
+            .unwrap();
+        backend
+            .execute_operation(Operation::Commit {
+                changes: vec![Change::Delete { selection }],
+            })
+            .expect("Commit failed");
+    }
+    assert!(std::fs::metadata(&file.0).unwrap().len() < length);
+    assert!(find_counter(&mut backend, 3).is_ok());
+}
+
+#[test]
+fn reuse_removed_space() {
+    let file = TestFile::new("reuse");
+    let mut backend = open_backend(&file);
+    let changes = (1..=2)
+        .map(|n| Change::Create {
+            document: counter(n),
+        })
+        .collect();
+    backend
+        .execute_operation(Operation::Commit { changes })
+        .expect("Commit failed");
+    let length = std::fs::metadata(&file.0).unwrap().len();
+    let first = find_counter(&mut backend, 1)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Delete {
+                selection: first.clone(),
+            }],
+        })
+        .expect("Commit failed");
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(3),
+            }],
+        })
+        .expect("Commit failed");
+    assert_eq!(std::fs::metadata(&file.0).unwrap().len(), length);
+    assert!(matches!(
+        read_counter(&mut backend, first),
+        Err(OperationError::StaleReference)
+    ));
+    drop(backend);
+    let mut backend = open_backend(&file);
+    assert!(find_counter(&mut backend, 2).is_ok());
+    assert!(find_counter(&mut backend, 3).is_ok());
+}
+
+fn note_schema() -> crate::schema::Schema {
+    test_schema(
+        "notes",
+        0x31,
+        vec![test_field("text", 0x1, crate::schema::FieldType::String)],
+    )
+}
+
+fn note(text: &str) -> crate::schema::Document {
+    test_document(
+        note_schema(),
+        vec![crate::schema::FieldValue::String(text.to_string())],
+    )
+}
+
+fn find_note(backend: &mut Backend, text: &str) -> Result<Reference, OperationError> {
+    Ok(find_equal(
+        backend,
+        0x31,
+        crate::schema::FieldValue::String(text.to_string()),
+    )?
+    .get_selection()
+    .unwrap())
+}
+
+fn read_note(backend: &mut Backend, selection: Reference) -> Result<String, OperationError> {
+    let document = backend
+        .execute_operation(Operation::Read {
+            selection,
+            fields: vec![0x1],
+        })?
+        .get_document()
+        .unwrap();
+    match &document.fields[0].value {
+        crate::schema::FieldValue::String(text) => Ok(text.clone()),
+        _ => panic!("Unexpected field type"),
+    }
+}
+
+fn update_note(backend: &mut Backend, selection: Reference, text: &str) {
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Update {
+                selection,
+                fields: note(text).fields,
+            }],
+        })
+        .expect("Commit failed");
+}
+
+#[test]
+fn update_in_place() {
+    let file = TestFile::new("in-place");
+    let mut backend = open_with_schemas(&file, vec![note_schema()]);
+    let changes = ["first", "second"]
+        .iter()
+        .map(|text| Change::Create {
+            document: note(text),
+        })
+        .collect();
+    backend
+        .execute_operation(Operation::Commit { changes })
+        .expect("Commit failed");
+    let length = std::fs::metadata(&file.0).unwrap().len();
+    let first = find_note(&mut backend, "first").unwrap();
+    update_note(&mut backend, first.clone(), "FIRST");
+    assert_eq!(std::fs::metadata(&file.0).unwrap().len(), length);
+    let position = backend.documents[&first.id];
+    assert_eq!(find_note(&mut backend, "FIRST").unwrap().id, first.id);
+    assert_eq!(backend.documents[&first.id], position);
+    assert_eq!(read_note(&mut backend, first).unwrap(), "FIRST");
+}
+
+#[test]
+fn move_outgrown_block() {
+    let file = TestFile::new("move");
+    let mut backend = open_with_schemas(&file, vec![note_schema()]);
+    let changes = ["first", "second"]
+        .iter()
+        .map(|text| Change::Create {
+            document: note(text),
+        })
+        .collect();
+    backend
+        .execute_operation(Operation::Commit { changes })
+        .expect("Commit failed");
+    let first = find_note(&mut backend, "first").unwrap();
+    let position = backend.documents[&first.id];
+
+    // Outgrow the block, moving it with some slack
+    update_note(&mut backend, first.clone(), "the first note, but longer");
+    assert_eq!(
+        read_note(&mut backend, first.clone()).unwrap(),
+        "the first note, but longer"
+    );
+    let moved = backend.documents[&first.id];
+    assert_ne!(moved, position);
+
+    // Grow into the slack, then outgrow it
+    update_note(&mut backend, first.clone(), "the first note, but longer!");
+    assert_eq!(backend.documents[&first.id], moved);
+    let text = "the first note, but much, much, much longer";
+    update_note(&mut backend, first.clone(), text);
+    assert_eq!(read_note(&mut backend, first.clone()).unwrap(), text);
+    let integrity = backend.io.check_integrity().unwrap();
+    assert_eq!(integrity.documents.len(), 2);
+    assert!(integrity.corrupted.is_empty());
+    assert_eq!(integrity.damaged_tail, None);
+
+    // The moved document keeps its identifier across a restart
+    drop(backend);
+    let mut backend = open_with_schemas(&file, vec![note_schema()]);
+    assert_eq!(find_note(&mut backend, text).unwrap().id, first.id);
+    assert_eq!(read_note(&mut backend, first).unwrap(), text);
+    assert!(find_note(&mut backend, "second").is_ok());
+}
+
+#[test]
+fn compact_moved_block() {
+    let file = TestFile::new("compact-moved");
+    let mut backend = open_with_schemas(&file, vec![note_schema()]);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: note("short"),
+            }],
+        })
+        .expect("Commit failed");
+    let selection = find_note(&mut backend, "short").unwrap();
+    update_note(&mut backend, selection.clone(), "a much longer note");
+    backend
+        .execute_operation(Operation::Compact)
+        .expect("Compaction failed");
+    assert_eq!(
+        read_note(&mut backend, selection).unwrap(),
+        "a much longer note"
+    );
+    assert!(backend.io.check_integrity().unwrap().holes.is_empty());
+}
+
+fn indexed_counter_schema() -> crate::schema::Schema {
+    crate::schema::Schema {
+        indexes: vec![vec!["count".to_string()]],
+        ..counter_schema()
+    }
+}
+
+/// Finds the counts of the documents found by looking up a
+/// condition on the count in the index.
+fn find_indexed(backend: &mut Backend, condition: Condition) -> Vec<u32> {
+    let schema = indexed_counter_schema();
+    let access = backend
+        .plan_access(&schema, &condition)
+        .unwrap()
+        .expect("Index not used");
+    let ids = backend.candidates(&access).unwrap().unwrap();
+    let mut counts: Vec<u32> = ids
+        .into_iter()
+        .map(|id| {
+            let selection = Reference {
+                schema: schema.clone(),
+                id,
+            };
+            read_counter(backend, selection).unwrap()
+        })
+        .collect();
+    counts.sort_unstable();
+    counts
+}
+
+fn count_is(count: u32) -> Condition {
+    Condition::Equal(
+        Expression::Field(0x1),
+        Expression::Value(crate::schema::FieldValue::UInt(count)),
+    )
+}
+
+fn count_above(count: u32) -> Condition {
+    Condition::GreaterThan(
+        Expression::Field(0x1),
+        Expression::Value(crate::schema::FieldValue::UInt(count)),
+    )
+}
+
+#[test]
+fn maintain_index() {
+    let file = TestFile::new("index");
+    let mut backend = open_with_schemas(&file, vec![indexed_counter_schema()]);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: [15, 5, 20, 10]
+                .into_iter()
+                .map(|count| Change::Create {
+                    document: counter(count),
+                })
+                .collect(),
+        })
+        .expect("Commit failed");
+    assert_eq!(find_indexed(&mut backend, count_above(8)), vec![10, 15, 20]);
+    let below = Condition::GreaterThan(
+        Expression::Value(crate::schema::FieldValue::UInt(15)),
+        Expression::Field(0x1),
+    );
+    assert_eq!(find_indexed(&mut backend, below), vec![5, 10]);
+
+    let ten = find_counter(&mut backend, 10)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+    let five = find_counter(&mut backend, 5)
+        .unwrap()
+        .get_selection()
+        .unwrap();
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![
+                Change::Update {
+                    selection: ten,
+                    fields: counter(30).fields,
+                },
+                Change::Delete { selection: five },
+            ],
+        })
+        .expect("Commit failed");
+    assert!(find_indexed(&mut backend, count_is(10)).is_empty());
+    assert_eq!(find_indexed(&mut backend, count_is(30)), vec![30]);
+    let either = Condition::Or(
+        Box::new(count_is(15)),
+        Box::new(Condition::LessThan(
+            Expression::Field(0x1),
+            Expression::Value(crate::schema::FieldValue::UInt(10)),
+        )),
+    );
+    assert_eq!(find_indexed(&mut backend, either), vec![15]);
+}
+
+#[test]
+fn store_index_in_data_file() {
+    let file = TestFile::new("index-store");
+    let mut backend = open_with_schemas(&file, vec![indexed_counter_schema()]);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(4),
+            }],
+        })
+        .expect("Commit failed");
+    let stored = (backend.indexes[0].root, backend.indexes[0].len);
+    assert_eq!(stored.1, 1);
+    drop(backend);
+    let mut backend = open_with_schemas(&file, vec![indexed_counter_schema()]);
+    assert_eq!((backend.indexes[0].root, backend.indexes[0].len), stored);
+    assert_eq!(find_indexed(&mut backend, count_above(0)), vec![4]);
+    drop(backend);
+
+    // Opening the file without the index discards it, as commits
+    // made without it don't update it
+    let mut backend = open_backend(&file);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: counter(8),
+            }],
+        })
+        .expect("Commit failed");
+    drop(backend);
+    let mut backend = open_with_schemas(&file, vec![indexed_counter_schema()]);
+    assert_eq!(find_indexed(&mut backend, count_above(0)), vec![4, 8]);
+}
+
+#[test]
+fn split_index_nodes() {
+    let file = TestFile::new("index-split");
+    let mut backend = open_with_schemas(&file, vec![user_schema()]);
+    // Each node only holds a few keys this long
+    let email = |n: usize| format!("{:03}{}", n, "@".repeat(900));
+    backend
+        .execute_operation(Operation::Commit {
+            changes: (0..100)
+                .map(|n| Change::Create {
+                    document: user(&email(n)),
+                })
+                .collect(),
+        })
+        .expect("Commit failed");
+    let removed = (0..100)
+        .step_by(2)
+        .map(|n| Change::Delete {
+            selection: find_user(&mut backend, &email(n)),
+        })
+        .collect();
+    backend
+        .execute_operation(Operation::Commit { changes: removed })
+        .expect("Commit failed");
+    drop(backend);
+
+    let mut backend = open_with_schemas(&file, vec![user_schema()]);
+    assert_eq!(backend.indexes[0].len, 50);
+    backend
+        .execute_operation(Operation::Compact)
+        .expect("Compaction failed");
+    let all = backend.indexes[0]
+        .range(
+            &backend.io,
+            std::ops::Bound::Unbounded,
+            std::ops::Bound::Unbounded,
+        )
+        .unwrap();
+    let expected: Vec<crate::util::DocumentID> =
+        (0..100).skip(1).step_by(2).map(|n| n + 1).collect();
+    assert_eq!(all, expected);
+    for n in 0..100 {
+        let found = find_equal(
+            &mut backend,
+            0x32,
+            crate::schema::FieldValue::String(email(n)),
+        );
+        assert_eq!(found.is_ok(), n % 2 == 1);
+    }
+    assert!(matches!(
+        backend.execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: user(&email(1)),
+            }],
+        }),
+        Err(OperationError::UniqueViolation { .. })
+    ));
+    assert!(matches!(
+        backend.execute_operation(Operation::Commit {
+            changes: vec![Change::Create {
+                document: user(&"@".repeat(2000)),
+            }],
+        }),
+        Err(OperationError::IndexKeyTooLong { .. })
+    ));
+}
+
+fn user_schema() -> crate::schema::Schema {
+    let mut email = test_field("email", 0x1, crate::schema::FieldType::String);
+    email.unique = true;
+    test_schema("users", 0x32, vec![email])
+}
+
+fn user(email: &str) -> crate::schema::Document {
+    test_document(
+        user_schema(),
+        vec![crate::schema::FieldValue::String(email.to_string())],
+    )
+}
+
+fn find_user(backend: &mut Backend, email: &str) -> Reference {
+    find_equal(
+        backend,
+        0x32,
+        crate::schema::FieldValue::String(email.to_string()),
+    )
+    .expect("Find failed")
+    .get_selection()
+    .unwrap()
+}
+
+#[test]
+fn enforce_unique_field() {
+    let file = TestFile::new("unique");
+    let mut backend = open_with_schemas(&file, vec![user_schema()]);
+    let commit =
+        |backend: &mut Backend, changes| backend.execute_operation(Operation::Commit { changes });
+    commit(
+        &mut backend,
+        vec![
+            Change::Create {
+                document: user("a@example.com"),
+            },
+            Change::Create {
+                document: user("b@example.com"),
+            },
+        ],
+    )
+    .expect("Commit failed");
+    let Err(error) = commit(
+        &mut backend,
+        vec![Change::Create {
+            document: user("a@example.com"),
+        }],
+    ) else {
+        panic!("Duplicate value committed");
+    };
+    assert_eq!(
+        error.to_string(),
+        "Unique field email already has the value \"a@example.com\" in another document"
+    );
+    let duplicates = vec![
+        Change::Create {
+            document: user("c@example.com"),
+        },
+        Change::Create {
+            document: user("c@example.com"),
+        },
+    ];
+    assert!(matches!(
+        commit(&mut backend, duplicates),
+        Err(OperationError::UniqueViolation { .. })
+    ));
+
+    // Swapping values within a transaction doesn't violate uniqueness
+    let a = find_user(&mut backend, "a@example.com");
+    let b = find_user(&mut backend, "b@example.com");
+    commit(
+        &mut backend,
+        vec![
+            Change::Update {
+                selection: a.clone(),
+                fields: user("b@example.com").fields,
+            },
+            Change::Update {
+                selection: b.clone(),
+                fields: user("a@example.com").fields,
+            },
+        ],
+    )
+    .expect("Commit failed");
+    assert_eq!(find_user(&mut backend, "a@example.com").id, b.id);
+    commit(
+        &mut backend,
+        vec![
+            Change::Delete { selection: a },
+            Change::Create {
+                document: user("b@example.com"),
+            },
+        ],
+    )
+    .expect("Commit failed");
+}
+
+fn event_schema() -> crate::schema::Schema {
+    crate::schema::Schema {
+        indexes: vec![vec!["tenant".to_string(), "created".to_string()]],
+        ..test_schema(
+            "events",
+            0x33,
+            vec![
+                test_field("tenant", 0x1, crate::schema::FieldType::String),
+                test_field("created", 0x2, crate::schema::FieldType::Long),
+            ],
+        )
+    }
+}
+
+fn event(tenant: &str, created: i64) -> crate::schema::Document {
+    test_document(
+        event_schema(),
+        vec![
+            crate::schema::FieldValue::String(tenant.to_string()),
+            crate::schema::FieldValue::Long(created),
+        ],
+    )
+}
+
+#[test]
+fn use_compound_index() {
+    let file = TestFile::new("compound-index");
+    let mut backend = open_with_schemas(&file, vec![event_schema()]);
+    let events = [
+        ("a", 3),
+        ("b", 7),
+        ("a", 9),
+        ("a", -4),
+        ("ab", 8),
+        ("a", 12),
+    ];
+    backend
+        .execute_operation(Operation::Commit {
+            changes: events
+                .iter()
+                .map(|(tenant, created)| Change::Create {
+                    document: event(tenant, *created),
+                })
+                .collect(),
+        })
+        .expect("Commit failed");
+    let tenant = |name: &str| {
+        Box::new(Condition::Equal(
+            Expression::Field(0x1),
+            Expression::Value(crate::schema::FieldValue::String(name.to_string())),
+        ))
+    };
+    let created = |after: bool, time| {
+        let value = Expression::Value(crate::schema::FieldValue::Long(time));
+        Box::new(if after {
+            Condition::GreaterThan(Expression::Field(0x2), value)
+        } else {
+            Condition::LessThan(Expression::Field(0x2), value)
+        })
+    };
+    let find = |condition: &Condition| {
+        let access = backend.plan_access(&event_schema(), condition).unwrap()?;
+        Some(
+            backend
+                .candidates(&access)
+                .unwrap()?
+                .into_iter()
+                .collect::<Vec<_>>(),
+        )
+    };
+    assert_eq!(find(&tenant("a")), Some(vec![1, 3, 4, 6]));
+    assert_eq!(
+        find(&Condition::And(tenant("a"), created(true, 3))),
+        Some(vec![3, 6])
+    );
+    assert_eq!(
+        find(&Condition::And(
+            created(false, 10),
+            Box::new(Condition::And(created(true, -5), tenant("a")))
+        )),
+        Some(vec![1, 3, 4])
+    );
+    assert_eq!(find(&created(true, 3)), None);
+
+    let selections = backend
+        .execute_operation(Operation::FindMany {
+            required: true,
+            query: Query {
+                collection: 0x33,
+                condition: Condition::And(tenant("a"), created(true, 3)),
+            },
+        })
+        .expect("Find failed")
+        .get_selections()
+        .unwrap();
+    let ids: Vec<u64> = selections.iter().map(|s| s.id).collect();
+    assert_eq!(ids, vec![3, 6]);
+}
+
+#[test]
+fn plan_queries() {
+    let file = TestFile::new("plan");
+    let mut backend = open_with_schemas(&file, vec![event_schema()]);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: (0..40)
+                .map(|i| Change::Create {
+                    document: event(&format!("tenant-{}", i % 10), i),
+                })
+                .collect(),
+        })
+        .expect("Commit failed");
+    let tenant = Condition::Equal(
+        Expression::Field(0x1),
+        Expression::Value(crate::schema::FieldValue::String("tenant-3".to_string())),
+    );
+    let created = |time| {
+        Condition::GreaterThan(
+            Expression::Field(0x2),
+            Expression::Value(crate::schema::FieldValue::Long(time)),
+        )
+    };
+    let plan = |backend: &Backend, condition| {
+        backend
+            .plan_query(Query {
+                collection: 0x33,
+                condition,
+            })
+            .expect("Planning failed")
+    };
+
+    // The equality is evaluated first, as it holds for fewer documents
+    let selective = plan(
+        &backend,
+        Condition::And(Box::new(created(20)), Box::new(tenant.clone())),
+    );
+    assert!(matches!(selective.access, Access::Index(ref lookup) if lookup.estimate == 2));
+    assert!(matches!(
+        selective.condition,
+        Condition::And(ref left, _) if matches!(**left, Condition::Equal(..))
+    ));
+
+    // Reading most documents through the index costs more than a scan
+    let unselective = plan(
+        &backend,
+        Condition::Or(Box::new(tenant), Box::new(created(-1))),
+    );
+    assert!(matches!(unselective.access, Access::Scan { estimate: 40 }));
+}
+
+#[test]
+fn evaluate_field_paths() {
+    use crate::schema::{FieldType, FieldValue};
+    let point_schema = test_schema(
+        "point",
+        0x35,
+        vec![
+            test_field("x", 0x1, FieldType::Int),
+            test_field("y", 0x2, FieldType::Int),
+        ],
+    );
+    // Points without a y
+    let point = |x| {
+        FieldValue::Object(Box::new(test_document(
+            point_schema.clone(),
+            vec![FieldValue::Int(x)],
+        )))
+    };
+    let schema = test_schema(
+        "shapes",
+        0x34,
+        vec![
+            test_field(
+                "origin",
+                0x1,
+                FieldType::Object(Box::new(point_schema.clone())),
+            ),
+            test_field(
+                "points",
+                0x2,
+                FieldType::Array(Box::new(FieldType::Object(Box::new(point_schema.clone())))),
+            ),
+        ],
+    );
+    let shape = test_document(
+        schema,
+        vec![point(3), FieldValue::Array(vec![point(5), point(-2)])],
+    );
+    let value = |x| Expression::Value(FieldValue::Int(x));
+    let member = |object, id| Expression::Member(Box::new(object), id);
+    let element = |index| Expression::Element(Box::new(Expression::Field(0x2)), index);
+    let holds = |condition| shape.evaluate(&condition).expect("Evaluation failed");
+
+    assert!(holds(Condition::Equal(
+        member(Expression::Field(0x1), 0x1),
+        value(3)
+    )));
+    assert!(holds(Condition::LessThan(
+        member(element(1), 0x1),
+        value(0)
+    )));
+    assert!(holds(Condition::GreaterThan(
+        member(element(0), 0x1),
+        value(4)
+    )));
+    // Members and elements which don't exist match nothing
+    assert!(!holds(Condition::Equal(member(element(0), 0x2), value(0))));
+    assert!(!holds(Condition::Equal(member(element(2), 0x1), value(5))));
+    assert!(holds(Condition::Not(Box::new(Condition::Equal(
+        member(element(2), 0x1),
+        value(5)
+    )))));
+}
+
+#[test]
+fn look_up_inclusive_bounds() {
+    let file = TestFile::new("inclusive-bounds");
+    let mut backend = open_with_schemas(&file, vec![event_schema()]);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: [3, 7, 9, 12]
+                .iter()
+                .map(|created| Change::Create {
+                    document: event("a", *created),
+                })
+                .collect(),
+        })
+        .expect("Commit failed");
+    let value = |time| Expression::Value(crate::schema::FieldValue::Long(time));
+    let tenant = Box::new(Condition::Equal(
+        Expression::Field(0x1),
+        Expression::Value(crate::schema::FieldValue::String("a".to_string())),
+    ));
+    let find = |condition: Condition| {
+        let condition = Condition::And(tenant.clone(), Box::new(condition));
+        let access = backend.plan_access(&event_schema(), &condition).unwrap()?;
+        Some(
+            backend
+                .candidates(&access)
+                .unwrap()?
+                .into_iter()
+                .collect::<Vec<_>>(),
+        )
+    };
+    assert_eq!(
+        find(Condition::GreaterOrEqual(Expression::Field(0x2), value(7))),
+        Some(vec![2, 3, 4])
+    );
+    assert_eq!(
+        find(Condition::LessOrEqual(Expression::Field(0x2), value(9))),
+        Some(vec![1, 2, 3])
+    );
+    // The comparison is reversed when the value is on the left
+    assert_eq!(
+        find(Condition::GreaterOrEqual(value(7), Expression::Field(0x2))),
+        Some(vec![1, 2])
+    );
+    assert_eq!(
+        find(Condition::NotEqual(Expression::Field(0x2), value(7))),
+        Some(vec![1, 2, 3, 4])
+    );
+
+    let document = event("a", 7);
+    let holds = |condition| document.evaluate(&condition).expect("Evaluation failed");
+    assert!(holds(Condition::GreaterOrEqual(
+        Expression::Field(0x2),
+        value(7)
+    )));
+    assert!(holds(Condition::LessOrEqual(
+        Expression::Field(0x2),
+        value(7)
+    )));
+    assert!(!holds(Condition::LessOrEqual(
+        Expression::Field(0x2),
+        value(6)
+    )));
+    assert!(holds(Condition::NotEqual(Expression::Field(0x2), value(6))));
+    assert!(document
+        .evaluate(&Condition::NotEqual(Expression::Field(0x1), value(0)))
+        .is_err());
+}
+
+#[test]
+fn match_patterns() {
+    use super::Pattern;
+    let matches = |pattern: &str, text: &str| {
+        Pattern::new(pattern)
+            .expect("Invalid pattern")
+            .is_match(text)
+    };
+    assert!(matches("^[^@]+@example\\.com$", "jo@example.com"));
+    assert!(!matches("^[^@]+@example\\.com$", "jo@example.org"));
+    assert!(!matches("^[^@]+@example\\.com$", "@example.com"));
+    assert!(matches("o+b", "foobar"));
+    assert!(!matches("^o+b", "foobar"));
+    assert!(matches("^(ab|cd)*e?$", "abcdab"));
+    assert!(!matches("^(ab|cd)*$", "abc"));
+    assert!(matches("\\d\\d-\\w+\\s", "rev 12-a_b "));
+    assert!(matches("^[a-c\\d-]+$", "b-3a"));
+    assert!(!matches("^\\D$", "7"));
+    assert!(matches("^d.a$", "día"));
+    assert!(matches("", "anything"));
+    assert!(matches("^a{2,3}$", "aaa"));
+    assert!(!matches("^a{2,3}$", "aaaa"));
+    // Nested repetitions match in linear time
+    assert!(!matches("^(a*)*b$", &"a".repeat(10_000)));
+    // And deeply nested groups are rejected rather than overflowing
+    // the stack
+    let nested = format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000));
+    assert!(Pattern::new(&nested).is_none());
+    for invalid in ["(ab", "ab)", "*a", "[a-", "[z-a]", "a\\"] {
+        assert!(
+            Pattern::new(invalid).is_none(),
+            "{} should be invalid",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn evaluate_text_conditions() {
+    use crate::schema::FieldValue;
+    let document = event("Swift-DB", 1);
+    let value = |s: &str| Expression::Value(FieldValue::String(s.to_string()));
+    let tenant = || Expression::Field(0x1);
+    let holds = |condition| document.evaluate(&condition).expect("Evaluation failed");
+    assert!(holds(Condition::Prefix(tenant(), value("Swift"))));
+    assert!(!holds(Condition::Prefix(tenant(), value("swift"))));
+    assert!(holds(Condition::Suffix(tenant(), value("-DB"))));
+    assert!(holds(Condition::Contains(tenant(), value("ft-D"))));
+    assert!(!holds(Condition::Contains(tenant(), value("db"))));
+    assert!(holds(Condition::EqualIgnoreCase(
+        tenant(),
+        value("swift-db")
+    )));
+    assert!(!holds(Condition::EqualIgnoreCase(tenant(), value("swift"))));
+    assert!(holds(Condition::Matches(
+        tenant(),
+        super::Pattern::new("^[A-Z]\\w+-").unwrap()
+    )));
+    assert!(document
+        .evaluate(&Condition::Prefix(Expression::Field(0x2), value("1")))
+        .is_err());
+}
+
+#[test]
+fn look_up_prefixes() {
+    let file = TestFile::new("prefix-index");
+    let mut backend = open_with_schemas(&file, vec![event_schema()]);
+    backend
+        .execute_operation(Operation::Commit {
+            changes: ["a", "ab", "b", "a\0", "", "ba"]
+                .iter()
+                .map(|tenant| Change::Create {
+                    document: event(tenant, 0),
+                })
+                .collect(),
+        })
+        .expect("Commit failed");
+    let find = |prefix: &str| {
+        let condition = Condition::Prefix(
+            Expression::Field(0x1),
+            Expression::Value(crate::schema::FieldValue::String(prefix.to_string())),
+        );
+        let access = backend.plan_access(&event_schema(), &condition).unwrap()?;
+        Some(
+            backend
+                .candidates(&access)
+                .unwrap()?
+                .into_iter()
+                .collect::<Vec<_>>(),
+        )
+    };
+    assert_eq!(find("a"), Some(vec![1, 2, 4]));
+    assert_eq!(find("b"), Some(vec![3, 6]));
+    assert_eq!(find("a\0"), Some(vec![4]));
+    assert_eq!(find("c"), Some(vec![]));
+    assert_eq!(find(""), Some(vec![1, 2, 3, 4, 5, 6]));
+}
+
+#[test]
+fn evaluate_array_conditions() {
+    use crate::schema::{FieldType, FieldValue};
+    let item_schema = test_schema(
+        "item",
+        0x37,
+        vec![test_field("price", 0x1, FieldType::UInt)],
+    );
+    let item = |price| {
+        FieldValue::Object(Box::new(test_document(
+            item_schema.clone(),
+            vec![FieldValue::UInt(price)],
+        )))
+    };
+    let schema = test_schema(
+        "orders",
+        0x36,
+        vec![
+            test_field("tags", 0x1, FieldType::Array(Box::new(FieldType::String))),
+            test_field(
+                "items",
+                0x2,
+                FieldType::Array(Box::new(FieldType::Object(Box::new(item_schema.clone())))),
+            ),
+            test_field("empty", 0x3, FieldType::Array(Box::new(FieldType::Int))),
+        ],
+    );
+    let tag = |s: &str| FieldValue::String(s.to_string());
+    let order = test_document(
+        schema,
+        vec![
+            FieldValue::Array(vec![tag("swift"), tag("db")]),
+            FieldValue::Array(vec![item(40), item(120)]),
+            FieldValue::Array(vec![]),
+        ],
+    );
+    let tags = || Expression::Field(0x1);
+    let items = || Expression::Field(0x2);
+    let price_above = |price| {
+        Box::new(Condition::GreaterThan(
+            Expression::Member(Box::new(Expression::Placeholder), 0x1),
+            Expression::Value(FieldValue::UInt(price)),
+        ))
+    };
+    let holds = |condition| order.evaluate(&condition).expect("Evaluation failed");
+
+    assert!(holds(Condition::In(
+        Expression::Value(tag("swift")),
+        tags()
+    )));
+    assert!(!holds(Condition::In(
+        Expression::Value(tag("rust")),
+        tags()
+    )));
+    assert!(holds(Condition::Any(items(), price_above(100))));
+    assert!(!holds(Condition::All(items(), price_above(100))));
+    assert!(holds(Condition::All(items(), price_above(30))));
+    assert!(holds(Condition::Any(
+        tags(),
+        Box::new(Condition::Prefix(
+            Expression::Placeholder,
+            Expression::Value(tag("sw"))
+        ))
+    )));
+    // `all` holds for an empty array, and `any` doesn't
+    let empty = || Expression::Field(0x3);
+    let never = || {
+        Box::new(Condition::Equal(
+            Expression::Placeholder,
+            Expression::Value(FieldValue::Int(0)),
+        ))
+    };
+    assert!(holds(Condition::All(empty(), never())));
+    assert!(!holds(Condition::Any(empty(), never())));
+    assert!(holds(Condition::Equal(
+        Expression::Length(Box::new(tags())),
+        Expression::Value(FieldValue::ULong(2))
+    )));
+    assert!(order
+        .evaluate(&Condition::In(
+            Expression::Value(FieldValue::Int(1)),
+            tags()
+        ))
+        .is_err());
+}
+
+#[test]
+fn evaluate_case_conditions() {
+    use crate::schema::{Document, EnumCase, EnumValue, FieldType, FieldValue};
+    let case = |name: &str, id, associated_value| EnumCase {
+        name: name.to_string(),
+        id,
+        associated_value,
+    };
+    let schema = test_schema(
+        "payments",
+        0x38,
+        vec![test_field(
+            "method",
+            0x1,
+            FieldType::Enum(vec![
+                case("cash", 0x1, None),
+                case("card", 0x2, Some(FieldType::UInt)),
+            ]),
+        )],
+    );
+    let payment = |case_id, associated_value| {
+        test_document(
+            schema.clone(),
+            vec![FieldValue::Enum(Box::new(EnumValue {
+                case_id,
+                associated_value,
+            }))],
+        )
+    };
+    let cash = payment(0x1, None);
+    let card = payment(0x2, Some(FieldValue::UInt(150)));
+    let above = |amount| {
+        Some(Box::new(Condition::GreaterThan(
+            Expression::Placeholder,
+            Expression::Value(FieldValue::UInt(amount)),
+        )))
+    };
+    let holds =
+        |document: &Document, condition| document.evaluate(&condition).expect("Evaluation failed");
+    assert!(holds(
+        &cash,
+        Condition::Case(Expression::Field(0x1), 0x1, None)
+    ));
+    assert!(!holds(
+        &cash,
+        Condition::Case(Expression::Field(0x1), 0x2, None)
+    ));
+    assert!(!holds(
+        &cash,
+        Condition::Case(Expression::Field(0x1), 0x2, above(0))
+    ));
+    assert!(holds(
+        &card,
+        Condition::Case(Expression::Field(0x1), 0x2, None)
+    ));
+    assert!(holds(
+        &card,
+        Condition::Case(Expression::Field(0x1), 0x2, above(100))
+    ));
+    assert!(!holds(
+        &card,
+        Condition::Case(Expression::Field(0x1), 0x2, above(200))
+    ));
+}
+
+#[test]
+fn look_up_value_sets() {
+    use super::ValueSet;
+    use crate::schema::FieldValue;
+    let file = TestFile::new("value-sets");
+    let mut backend = open_with_schemas(&file, vec![event_schema()]);
+    let events = [("a", 3), ("b", 7), ("c", 9), ("a", 12), ("d", 1), ("b", 2)];
+    backend
+        .execute_operation(Operation::Commit {
+            changes: events
+                .iter()
+                .map(|(tenant, created)| Change::Create {
+                    document: event(tenant, *created),
+                })
+                .collect(),
+        })
+        .expect("Commit failed");
+    let tenants = |names: &[&str]| {
+        Condition::OneOf(
+            Expression::Field(0x1),
+            ValueSet::new(
+                names
+                    .iter()
+                    .map(|name| FieldValue::String(name.to_string()))
+                    .collect(),
+            ),
+        )
+    };
+    let find = |condition: &Condition| {
+        let access = backend.plan_access(&event_schema(), condition).unwrap()?;
+        Some(
+            backend
+                .candidates(&access)
+                .unwrap()?
+                .into_iter()
+                .collect::<Vec<_>>(),
+        )
+    };
+    assert_eq!(find(&tenants(&["a", "b", "a"])), Some(vec![1, 2, 4, 6]));
+    let after = Condition::GreaterThan(
+        Expression::Field(0x2),
+        Expression::Value(FieldValue::Long(2)),
+    );
+    let condition = Condition::And(Box::new(tenants(&["b", "c", "e"])), Box::new(after));
+    assert_eq!(find(&condition), Some(vec![2, 3]));
+    let Some(Access::Index(lookup)) = backend.plan_access(&event_schema(), &condition).unwrap()
+    else {
+        panic!("Expected an index lookup");
+    };
+    assert_eq!(lookup.estimate, 2);
+    assert_eq!(lookup.conditions.len(), 2);
+
+    // Large sets are hashed, and must still compare values of one type
+    let many: Vec<String> = (0..100).map(|i| format!("tenant-{}", i)).collect();
+    let many: Vec<&str> = many.iter().map(String::as_str).chain(["c"]).collect();
+    let document = event("c", 0);
+    assert!(document.evaluate(&tenants(&many)).unwrap());
+    assert!(!document.evaluate(&tenants(&many[..100])).unwrap());
+    assert!(document.evaluate(&tenants(&["b", "c"])).unwrap());
+    for size in [2, 20] {
+        let longs = ValueSet::new((0..size).map(FieldValue::Long).collect());
+        assert!(document
+            .evaluate(&Condition::OneOf(Expression::Field(0x1), longs))
+            .is_err());
+    }
+}
+
+#[test]
+fn evaluate_date_offsets() {
+    use crate::schema::{FieldType, FieldValue};
+    use chrono::{DateTime, Duration};
+    let date = |s: &str| FieldValue::DateTime(DateTime::parse_from_rfc3339(s).unwrap().into());
+    let schema = test_schema(
+        "trials",
+        0x39,
+        vec![
+            test_field("started", 0x1, FieldType::DateTime),
+            test_field("ended", 0x2, FieldType::DateTime),
+        ],
+    );
+    let trial = test_document(
+        schema,
+        vec![date("2026-01-01T00:00:00Z"), date("2026-01-20T00:00:00Z")],
+    );
+    let started_plus =
+        |days| Expression::Offset(Box::new(Expression::Field(0x1)), Duration::days(days));
+    let holds = |condition| trial.evaluate(&condition).expect("Evaluation failed");
+    assert!(holds(Condition::LessThan(
+        Expression::Field(0x2),
+        started_plus(30)
+    )));
+    assert!(!holds(Condition::LessThan(
+        Expression::Field(0x2),
+        started_plus(14)
+    )));
+    assert!(holds(Condition::Equal(
+        Expression::Field(0x2),
+        started_plus(19)
+    )));
+    assert!(holds(Condition::GreaterThan(
+        Expression::Field(0x1),
+        Expression::Offset(Box::new(Expression::Field(0x2)), -Duration::weeks(3))
+    )));
+    // A date moved out of range matches nothing
+    assert!(!holds(Condition::LessThan(
+        Expression::Field(0x1),
+        Expression::Offset(Box::new(Expression::Field(0x1)), Duration::max_value())
+    )));
+}
@@ -1,3 +1,4 @@
+use crate::backend::Options;
 use crate::database::Database;
 use crate::database::LifecycleError;
 use crate::schema::Schema;
@@ -8,6 +9,8 @@ use std::fs::File;
 pub struct Configuration {
     schemas: Vec<Schema>,
     filename: String,
+    #[serde(flatten)]
+    options: Options,
 }
 
 impl Configuration {
@@ -18,7 +21,7 @@ impl Configuration {
     }
 
     pub fn make_database(self) -> Result<Database, LifecycleError> {
-        let database = Database::new(self.filename, self.schemas)?;
+        let database = Database::new(self.filename, self.schemas, self.options)?;
         Ok(database)
     }
 }
@@ -1,4 +1,4 @@
-use crate::backend::{Backend, Request};
+use crate::backend::{Backend, Options, Request};
 use crate::database::LifecycleError;
 use crate::frontend::Connection;
 use crate::schema::Schema;
@@ -27,11 +27,15 @@ impl Database {
     /// Loads configuration and creates a [`Backend`], along with
     /// an MPSC channel for communication between frontends and
     /// the backend.
-    pub fn new(path: String, collections: Vec<Schema>) -> Result<Self, LifecycleError> {
+    pub fn new(
+        path: String,
+        collections: Vec<Schema>,
+        options: Options,
+    ) -> Result<Self, LifecycleError> {
         let (sender, reciever) = channel();
         let db = Self {
-            backend: Backend::new(path, collections.clone(), reciever)
-                .map_err(LifecycleError::BackendError)?,
+            backend: Backend::new(path, collections.clone(), reciever, options)
+                .map_err(LifecycleError::from_backend)?,
             sender,
             collections,
         };
@@ -1,3 +1,4 @@
+use crate::archive::FormatError;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 
@@ -8,8 +9,10 @@ use std::fmt::{Display, Formatter};
 ///
 /// [`Database`]: crate::database::Database
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum LifecycleError {
     BackendError(std::io::Error),
+    DataFileFormatError(FormatError),
     NetworkError(std::io::Error),
     ConfigurationFileError(std::io::Error),
     ConfigurationError(serde_json::Error),
@@ -21,6 +24,9 @@ impl Display for LifecycleError {
             LifecycleError::BackendError(e) => {
                 write!(formatter, "Backend construction error: {}", e)
             }
+            LifecycleError::DataFileFormatError(e) => {
+                write!(formatter, "Data file format error: {}", e)
+            }
             LifecycleError::NetworkError(e) => write!(formatter, "Network error: {}", e),
             LifecycleError::ConfigurationFileError(e) => {
                 write!(formatter, "Configuration file error: {}", e)
@@ -33,3 +39,25 @@ impl Display for LifecycleError {
 }
 
 impl Error for LifecycleError {}
+
+impl LifecycleError {
+    /// Wraps an error constructing the [`Backend`], unwrapping any
+    /// [`FormatError`] it carries.
+    ///
+    /// [`Backend`]: crate::backend::Backend
+    pub fn from_backend(error: std::io::Error) -> Self {
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<FormatError>())
+        {
+            let inner = error
+                .into_inner()
+                .unwrap()
+                .downcast::<FormatError>()
+                .unwrap();
+            LifecycleError::DataFileFormatError(*inner)
+        } else {
+            LifecycleError::BackendError(error)
+        }
+    }
+}
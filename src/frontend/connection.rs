@@ -89,7 +89,7 @@ impl Connection {
 /// [`execute_statement`]: crate::schema::Document#method.execute_statement
 mod execute_statement {
     use super::*;
-    use crate::{backend::Reference, schema::Document};
+    use crate::schema::Document;
 
     impl Connection {
         /// Executes a language [`Statement`].
@@ -107,7 +107,9 @@ mod execute_statement {
                     transaction,
                     lock,
                     query,
-                } => self.select(identifier, transaction, lock, query),
+                    many,
+                    required,
+                } => self.select(identifier, transaction, lock, query, many, required),
                 Statement::Create {
                     identifier,
                     transaction,
@@ -119,6 +121,8 @@ mod execute_statement {
                     document,
                 } => self.update_all(selection, document),
                 Statement::Delete { selection } => self.delete(selection),
+                Statement::Compact => self.compact(),
+                Statement::Explain { query } => self.explain(query),
             }
         }
 
@@ -150,17 +154,19 @@ mod execute_statement {
             let transaction = &mut self.transactions[transaction_index];
             let mut return_channels = Vec::with_capacity(transaction.selections.len());
             for selection in &transaction.selections {
-                let (return_channel, return_reciever) = channel();
-                self.sender
-                    .send(Request {
-                        operation: Operation::Acquire {
-                            selection: selection.reference.clone(),
-                            lock: selection.lock.clone(),
-                        },
-                        return_channel,
-                    })
-                    .or(Err(FrontendError::SendError))?;
-                return_channels.push(return_reciever);
+                for reference in selection.references() {
+                    let (return_channel, return_reciever) = channel();
+                    self.sender
+                        .send(Request {
+                            operation: Operation::Acquire {
+                                selection: reference.clone(),
+                                lock: selection.lock.clone(),
+                            },
+                            return_channel,
+                        })
+                        .or(Err(FrontendError::SendError))?;
+                    return_channels.push(return_reciever);
+                }
             }
             for reciever in return_channels {
                 reciever
@@ -172,54 +178,53 @@ mod execute_statement {
             }
             transaction.acquire()?;
             for selection in &mut transaction.selections {
-                let all_fields = selection
-                    .reference
-                    .schema
-                    .fields
-                    .iter()
-                    .map(|f| f.id)
-                    .collect();
-                let document = Connection::request_operation(
-                    &self.sender,
-                    Operation::Read {
-                        selection: selection.reference.clone(),
-                        fields: all_fields,
-                    },
-                )?
-                .get_document()
-                .ok_or(FrontendError::RecieveError)?;
-                selection.cache(document);
+                let all_fields: Vec<_> = selection.schema.fields.iter().map(|f| f.id).collect();
+                let documents = selection
+                    .references()
+                    .map(|reference| {
+                        Connection::request_operation(
+                            &self.sender,
+                            Operation::Read {
+                                selection: reference.clone(),
+                                fields: all_fields.clone(),
+                            },
+                        )?
+                        .get_document()
+                        .ok_or(FrontendError::RecieveError)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                selection.cache(documents);
             }
             Ok(Response::Acquired)
         }
 
         fn commit(&mut self, identifier: String) -> Result<Response, FrontendError> {
             let transaction = &self.transactions[self.get_transaction_index(&identifier)?];
-            for selection in &transaction.selections {
-                if let Some(new) = selection.new_cached() {
-                    if let Some(document) = new {
-                        self.request(Operation::Update {
-                            selection: selection.reference.clone(),
-                            fields: document.fields.clone(),
-                        })?;
-                    } else {
-                        self.request(Operation::Delete {
-                            selection: selection.reference.clone(),
-                        })?;
-                    }
-                }
-            }
+            transaction.guard_action()?;
+            let changes = transaction
+                .selections
+                .iter()
+                .flat_map(|s| s.changes())
+                .collect();
+            self.request(Operation::Commit { changes })?
+                .get_ok()
+                .ok_or(FrontendError::RecieveError)?;
             self.close(identifier)?;
             Ok(Response::Committed)
         }
 
         fn close(&mut self, transaction: String) -> Result<Response, FrontendError> {
             let index = self.get_transaction_index(&transaction)?;
-            for selection in &self.transactions[index].selections {
-                self.request(Operation::Release {
-                    selection: selection.reference.clone(),
-                    lock: selection.lock.clone(),
-                })?;
+            // Locks are only held once the transaction is acquired
+            if self.transactions[index].guard_action().is_ok() {
+                for selection in &self.transactions[index].selections {
+                    for reference in selection.references() {
+                        self.request(Operation::Release {
+                            selection: reference.clone(),
+                            lock: selection.lock.clone(),
+                        })?;
+                    }
+                }
             }
             self.transactions.remove(index);
             // TODO optimize
@@ -240,18 +245,43 @@ mod execute_statement {
             transaction_identifier: String,
             lock: LockType,
             query: Query,
+            many: bool,
+            required: bool,
         ) -> Result<Response, FrontendError> {
             let transaction_index = self.get_transaction_index(&transaction_identifier)?;
             self.transactions[transaction_index].guard_selection()?;
             if self.selection_map.contains_key(&identifier) {
                 return Err(FrontendError::SelectionRedeclaration(identifier));
             }
-            let reference = self
-                .request(Operation::FindOne { query })?
-                .get_selection()
-                .ok_or(FrontendError::RecieveError)?;
-            let selection = Selection::new(reference, lock);
+            let schema = self
+                .collections
+                .iter()
+                .find(|s| s.id == query.collection)
+                .ok_or(FrontendError::RecieveError)?
+                .clone();
+            let operation = if many {
+                Operation::FindMany { query, required }
+            } else {
+                Operation::FindOne { query, required }
+            };
+            let response = self.request(operation)?;
+            let references = if response.is_empty() {
+                Vec::new()
+            } else if many {
+                response
+                    .get_selections()
+                    .ok_or(FrontendError::RecieveError)?
+            } else {
+                vec![response
+                    .get_selection()
+                    .ok_or(FrontendError::RecieveError)?]
+            };
+            let empty = references.is_empty();
+            let selection = Selection::new(schema, references, lock, many);
             self.create_selection(transaction_index, selection, identifier)?;
+            if empty {
+                return Ok(Response::Empty);
+            }
             Ok(Response::Selected)
         }
 
@@ -266,11 +296,7 @@ mod execute_statement {
             if self.selection_map.contains_key(&identifier) {
                 return Err(FrontendError::SelectionRedeclaration(identifier));
             }
-            let reference = self
-                .request(Operation::Create { document })?
-                .get_selection()
-                .ok_or(FrontendError::RecieveError)?;
-            let selection = Selection::new(reference, LockType::Write);
+            let selection = Selection::created(document);
             self.create_selection(transaction_index, selection, identifier)?;
             Ok(Response::Selected)
         }
@@ -283,11 +309,16 @@ mod execute_statement {
             let transaction_index = self.get_transaction_index(&location.0)?;
             self.transactions[transaction_index].guard_action()?;
             let selection = &self.transactions[transaction_index].selections[location.1];
-            let document = selection
-                .cached()
-                .ok_or(FrontendError::UnknownSelection(identifier))?
-                .clone();
-            Ok(Response::Document(document))
+            let documents = selection.cached();
+            if selection.many {
+                return Ok(Response::Documents(
+                    documents.into_iter().cloned().collect(),
+                ));
+            }
+            match documents.first() {
+                Some(document) => Ok(Response::Document((*document).clone())),
+                None => Ok(Response::Empty),
+            }
         }
 
         fn update_all(
@@ -318,6 +349,27 @@ mod execute_statement {
             Ok(Response::Deleted)
         }
 
+        fn compact(&mut self) -> Result<Response, FrontendError> {
+            self.request(Operation::Compact)?
+                .get_ok()
+                .ok_or(FrontendError::RecieveError)?;
+            Ok(Response::Compacted)
+        }
+
+        fn explain(&mut self, query: Query) -> Result<Response, FrontendError> {
+            let schema = self
+                .collections
+                .iter()
+                .find(|s| s.id == query.collection)
+                .ok_or(FrontendError::RecieveError)?
+                .clone();
+            let plan = self
+                .request(Operation::Explain { query })?
+                .get_plan()
+                .ok_or(FrontendError::RecieveError)?;
+            Ok(Response::Plan { plan, schema })
+        }
+
         fn request(&self, operation: Operation) -> Result<BackendResponse, FrontendError> {
             Connection::request_operation(&self.sender, operation)
         }
@@ -370,8 +422,8 @@ mod execute_statement {
             Ok(index)
         }
 
-        pub fn get_selection_map(&self) -> Result<HashMap<String, &Reference>, FrontendError> {
-            let entries: Result<HashMap<String, &Reference>, FrontendError> = self
+        pub fn get_selection_map(&self) -> Result<HashMap<String, &Schema>, FrontendError> {
+            let entries: Result<HashMap<String, &Schema>, FrontendError> = self
                 .selection_map
                 .iter()
                 .map(|(key, (transaction_id, index))| {
@@ -379,7 +431,7 @@ mod execute_statement {
                         key.clone(),
                         &self.transactions[self.get_transaction_index(transaction_id)?].selections
                             [*index]
-                            .reference,
+                            .schema,
                     ))
                 })
                 .collect();
@@ -1,50 +1,124 @@
-use crate::backend::Reference;
-use crate::schema::Document;
+use crate::backend::{Change, Reference};
+use crate::schema::{Document, Schema};
 use crate::util::LockType;
 
 pub struct Selection {
-    pub reference: Reference,
+    pub schema: Schema,
     pub lock: LockType,
+    /// Whether this selection was made with `selects`, and so
+    /// refers to any number of documents.
+    pub many: bool,
+    documents: Vec<SelectedDocument>,
+}
+
+/// A document in a [`Selection`], along with its state in the
+/// transaction.
+struct SelectedDocument {
+    /// The backend reference to the selected document, or `None`
+    /// if the document was created in this transaction and has
+    /// not yet been committed.
+    reference: Option<Reference>,
     cached: Option<Document>,
     new_cached: Option<Document>,
 }
 
 impl Selection {
-    pub fn new(reference: Reference, lock: LockType) -> Self {
+    /// Creates a selection for the documents matched by a `select`
+    /// or `selects` query, which may be none.
+    pub fn new(schema: Schema, references: Vec<Reference>, lock: LockType, many: bool) -> Self {
         Self {
-            reference,
+            schema,
             lock,
-            cached: None,
-            new_cached: None,
+            many,
+            documents: references.into_iter().map(SelectedDocument::new).collect(),
         }
     }
 
-    pub fn cached(&self) -> Option<&Document> {
-        self.new_cached.as_ref().or(self.cached.as_ref())
+    /// Creates a selection for a document which will be created
+    /// when the transaction is committed.
+    pub fn created(document: Document) -> Self {
+        Self {
+            schema: document.schema.clone(),
+            lock: LockType::Write,
+            many: false,
+            documents: vec![SelectedDocument {
+                reference: None,
+                cached: None,
+                new_cached: Some(document),
+            }],
+        }
     }
 
-    pub fn new_cached(&self) -> Option<Option<&Document>> {
-        if self.cached.is_some() {
-            if let Some(ref new) = self.new_cached {
-                Some(Some(new))
-            } else {
-                None
-            }
-        } else {
-            Some(None)
-        }
+    /// Returns the backend references to the selected documents
+    /// which have already been committed.
+    pub fn references(&self) -> impl Iterator<Item = &Reference> {
+        self.documents.iter().filter_map(|d| d.reference.as_ref())
+    }
+
+    /// Returns the current state of each selected document which
+    /// hasn't been deleted.
+    pub fn cached(&self) -> Vec<&Document> {
+        self.documents.iter().filter_map(|d| d.cached()).collect()
     }
 
-    pub fn cache(&mut self, document: Document) {
-        self.cached = Some(document);
+    /// Returns the [`Change`]s to commit for this selection, one
+    /// for each modified document.
+    pub fn changes(&self) -> impl Iterator<Item = Change> + '_ {
+        self.documents.iter().filter_map(|d| d.change())
+    }
+
+    /// Caches the documents read after acquiring this selection's
+    /// locks, in the order of [`Selection::references`].
+    pub fn cache(&mut self, documents: Vec<Document>) {
+        let selected = self.documents.iter_mut().filter(|d| d.reference.is_some());
+        for (selected, document) in selected.zip(documents) {
+            selected.cached = Some(document);
+        }
     }
 
     pub fn update_cache(&mut self, document: Document) {
-        self.new_cached = Some(document);
+        for selected in &mut self.documents {
+            selected.new_cached = Some(document.clone());
+        }
     }
 
     pub fn delete_cache(&mut self) {
-        self.cached = None;
-        self.new_cached = None;
+        for selected in &mut self.documents {
+            selected.cached = None;
+            selected.new_cached = None;
+        }
+    }
+}
+
+impl SelectedDocument {
+    fn new(reference: Reference) -> Self {
+        Self {
+            reference: Some(reference),
+            cached: None,
+            new_cached: None,
+        }
+    }
+
+    fn cached(&self) -> Option<&Document> {
+        self.new_cached.as_ref().or(self.cached.as_ref())
+    }
+
+    /// Returns the [`Change`] to commit for this document, or
+    /// `None` if it was not modified.
+    fn change(&self) -> Option<Change> {
+        match (&self.reference, &self.cached, &self.new_cached) {
+            (Some(reference), Some(_), Some(new)) => Some(Change::Update {
+                selection: reference.clone(),
+                fields: new.fields.clone(),
+            }),
+            (Some(_), Some(_), None) => None,
+            (Some(reference), None, _) => Some(Change::Delete {
+                selection: reference.clone(),
+            }),
+            (None, _, Some(new)) => Some(Change::Create {
+                document: new.clone(),
+            }),
+            (None, _, None) => None,
+        }
     }
 }
@@ -1,10 +1,12 @@
 use super::expression::Expression;
-use crate::backend::{Condition, Expression as ValueExpression, Query, Reference};
+use crate::backend::{Condition, Expression as ValueExpression, Pattern, Query, ValueSet};
 use crate::language::{ParseError, Statement};
-use crate::schema::{Document, FieldValue, Schema};
+use crate::schema::{Document, FieldType, FieldValue, Schema};
 use crate::util::LockType;
+use chrono::{DateTime, Duration, Utc};
 use std::collections::HashMap;
 use std::io::Read;
+use std::time::SystemTime;
 
 /// Builds a [`Statement`] from a parsed expression.
 ///
@@ -14,7 +16,7 @@ use std::io::Read;
 pub fn build_statement(
     expression: &[Expression],
     collections: &[Schema],
-    selections: HashMap<String, &Reference>,
+    selections: HashMap<String, &Schema>,
     reader: impl Read,
 ) -> Result<Statement, ParseError> {
     let keyword = expression
@@ -26,11 +28,14 @@ pub fn build_statement(
         "acquire" => build_acquire(expression),
         "commit" => build_commit(expression),
         "close" => build_close(expression),
-        "select" => build_select(expression, collections),
+        "select" => build_select(expression, collections, false),
+        "selects" => build_select(expression, collections, true),
         "create" => build_create(expression, collections, reader),
         "readall" => build_read_all(expression),
         "updateall" => build_update_all(expression, selections, reader),
         "delete" => build_delete(expression),
+        "compact" => build_compact(expression),
+        "explain" => build_explain(expression, collections),
         _ => Err(ParseError::UnexpectedToken),
     }
 }
@@ -71,13 +76,44 @@ fn build_close(expression: &[Expression]) -> Result<Statement, ParseError> {
     })
 }
 
-fn build_select(
+fn build_compact(expression: &[Expression]) -> Result<Statement, ParseError> {
+    if expression.len() != 1 {
+        return Err(ParseError::ArgumentCount);
+    }
+    Ok(Statement::Compact)
+}
+
+fn build_explain(
     expression: &[Expression],
     collections: &[Schema],
 ) -> Result<Statement, ParseError> {
-    if expression.len() != 6 {
+    if expression.len() != 2 {
         return Err(ParseError::ArgumentCount);
     }
+    let select = expression[1].get_expression()?;
+    let many = match select.first().map(|e| e.get_identifier()).transpose()? {
+        Some(keyword) if keyword == "select" => false,
+        Some(keyword) if keyword == "selects" => true,
+        Some(_) => return Err(ParseError::UnexpectedToken),
+        None => return Err(ParseError::ArgumentCount),
+    };
+    match build_select(select, collections, many)? {
+        Statement::Select { query, .. } => Ok(Statement::Explain { query }),
+        _ => unreachable!(),
+    }
+}
+
+fn build_select(
+    expression: &[Expression],
+    collections: &[Schema],
+    many: bool,
+) -> Result<Statement, ParseError> {
+    let required = match expression.len() {
+        6 => false,
+        7 if expression[6].get_identifier()? == "required" => true,
+        7 => return Err(ParseError::UnexpectedToken),
+        _ => return Err(ParseError::ArgumentCount),
+    };
     let identifier = expression[1].get_identifier()?;
     let transaction = expression[2].get_identifier()?;
     let lock_type_name = expression[3].get_identifier()?;
@@ -99,7 +135,7 @@ fn build_select(
         .iter()
         .find(|s| &s.name == collection_name)
         .ok_or_else(|| ParseError::UnknownIdentifier(collection_name.clone()))?;
-    let condition = build_condition(expression[5].get_expression()?, collection)?;
+    let condition = build_condition(expression[5].get_expression()?, Scope::new(collection))?;
     Ok(Statement::Select {
         identifier: identifier.clone(),
         transaction: transaction.clone(),
@@ -108,6 +144,8 @@ fn build_select(
             collection: collection.id,
             condition,
         },
+        many,
+        required,
     })
 }
 
@@ -142,112 +180,394 @@ fn build_create(
     Ok(statement)
 }
 
-fn build_condition(expression: &[Expression], schema: &Schema) -> Result<Condition, ParseError> {
+/// What the expressions in a condition may refer to: the fields of
+/// the queried collection, and inside an `any` or `all`, the array
+/// element `_`, or inside a `case`, the associated value `_`.
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    schema: &'a Schema,
+    element: Option<&'a FieldType>,
+}
+
+impl<'a> Scope<'a> {
+    fn new(schema: &'a Schema) -> Self {
+        Self {
+            schema,
+            element: None,
+        }
+    }
+}
+
+fn build_condition(expression: &[Expression], scope: Scope) -> Result<Condition, ParseError> {
     if expression.is_empty() {
         return Err(ParseError::ArgumentCount);
     }
     // TODO implement true/false
-    // TODO implement not equal
-    match expression[0].get_operator()? {
-        '=' => {
-            let values = get_binary_expressions(expression, schema)?;
+    // Text conditions are named rather than written as operators
+    let operator = match &expression[0] {
+        Expression::Identifier(name) => name.as_str(),
+        operator => operator.get_operator()?,
+    };
+    match operator {
+        "=" => {
+            let values = get_binary_expressions(expression, scope)?;
             Ok(Condition::Equal(values.0, values.1))
         }
-        '<' => {
-            let values = get_binary_expressions(expression, schema)?;
+        "!=" => {
+            let values = get_binary_expressions(expression, scope)?;
+            Ok(Condition::NotEqual(values.0, values.1))
+        }
+        "<" => {
+            let values = get_binary_expressions(expression, scope)?;
             Ok(Condition::LessThan(values.0, values.1))
         }
-        '>' => {
-            let values = get_binary_expressions(expression, schema)?;
+        "<=" => {
+            let values = get_binary_expressions(expression, scope)?;
+            Ok(Condition::LessOrEqual(values.0, values.1))
+        }
+        ">" => {
+            let values = get_binary_expressions(expression, scope)?;
             Ok(Condition::GreaterThan(values.0, values.1))
         }
-        '|' => {
-            let values = get_binary_conditions(expression, schema)?;
-            Ok(Condition::Or(Box::new(values.0), Box::new(values.1)))
+        ">=" => {
+            let values = get_binary_expressions(expression, scope)?;
+            Ok(Condition::GreaterOrEqual(values.0, values.1))
+        }
+        "ieq" => {
+            let values = get_binary_expressions(expression, scope)?;
+            Ok(Condition::EqualIgnoreCase(values.0, values.1))
+        }
+        "prefix" => {
+            let values = get_binary_expressions(expression, scope)?;
+            Ok(Condition::Prefix(values.0, values.1))
+        }
+        "suffix" => {
+            let values = get_binary_expressions(expression, scope)?;
+            Ok(Condition::Suffix(values.0, values.1))
         }
-        '&' => {
-            let values = get_binary_conditions(expression, schema)?;
-            Ok(Condition::And(Box::new(values.0), Box::new(values.1)))
+        "contains" => {
+            let values = get_binary_expressions(expression, scope)?;
+            Ok(Condition::Contains(values.0, values.1))
         }
-        '!' => {
+        "matches" => {
+            if expression.len() != 3 {
+                return Err(ParseError::ArgumentCount);
+            }
+            let value = build_value_expression(&expression[1], scope)?;
+            let source = expression[2].get_literal()?;
+            let pattern =
+                Pattern::new(source).ok_or_else(|| ParseError::InvalidPattern(source.clone()))?;
+            Ok(Condition::Matches(value, pattern))
+        }
+        "in" => {
+            if expression.len() != 3 {
+                return Err(ParseError::ArgumentCount);
+            }
+            let array = build_value_expression(&expression[2], scope)?;
+            let element = array_element_type(&array, scope)?;
+            let value = match &expression[1] {
+                Expression::Numeric(number) => parse_numeric(number, &element)
+                    .map(ValueExpression::Value)
+                    .ok_or_else(|| ParseError::NumericType {
+                        numeric: number.clone(),
+                        field_type: element,
+                    })?,
+                value => build_value_expression(value, scope)?,
+            };
+            Ok(Condition::In(value, array))
+        }
+        "any" | "all" => {
+            if expression.len() != 3 {
+                return Err(ParseError::ArgumentCount);
+            }
+            let array = build_value_expression(&expression[1], scope)?;
+            let element = array_element_type(&array, scope)?;
+            let element_scope = Scope {
+                schema: scope.schema,
+                element: Some(&element),
+            };
+            let condition = Box::new(build_condition(
+                expression[2].get_expression()?,
+                element_scope,
+            )?);
+            if operator == "any" {
+                Ok(Condition::Any(array, condition))
+            } else {
+                Ok(Condition::All(array, condition))
+            }
+        }
+        "case" => {
+            if expression.len() != 3 && expression.len() != 4 {
+                return Err(ParseError::ArgumentCount);
+            }
+            let value = build_value_expression(&expression[1], scope)?;
+            let cases = match value.value_type(scope.schema, scope.element) {
+                Some(FieldType::Enum(cases)) => cases,
+                Some(field_type) => return Err(ParseError::NotAnEnum(field_type)),
+                None => return Err(ParseError::UnexpectedToken),
+            };
+            let name = expression[2].get_identifier()?;
+            let case = cases
+                .iter()
+                .find(|c| &c.name == name)
+                .ok_or_else(|| ParseError::UnknownIdentifier(name.clone()))?;
+            let condition = match (expression.get(3), &case.associated_value) {
+                (None, _) => None,
+                (Some(condition), Some(associated)) => {
+                    let associated_scope = Scope {
+                        schema: scope.schema,
+                        element: Some(associated),
+                    };
+                    Some(Box::new(build_condition(
+                        condition.get_expression()?,
+                        associated_scope,
+                    )?))
+                }
+                (Some(_), None) => return Err(ParseError::NoAssociatedValue(name.clone())),
+            };
+            Ok(Condition::Case(value, case.id, condition))
+        }
+        "oneof" => {
+            if expression.len() < 3 {
+                return Err(ParseError::ArgumentCount);
+            }
+            let value = build_value_expression(&expression[1], scope)?;
+            let values = expression[2..]
+                .iter()
+                .map(|candidate| {
+                    let candidate = match candidate {
+                        Expression::Numeric(number) => build_bare_numeric(number, &value, scope)?,
+                        candidate => build_value_expression(candidate, scope)?,
+                    };
+                    match candidate {
+                        ValueExpression::Value(candidate) => Ok(candidate),
+                        _ => Err(ParseError::UnexpectedToken),
+                    }
+                })
+                .collect::<Result<Vec<FieldValue>, ParseError>>()?;
+            Ok(Condition::OneOf(value, ValueSet::new(values)))
+        }
+        "|" => {
+            let conditions = get_conditions(expression, scope)?;
+            Ok(conditions
+                .into_iter()
+                .reduce(|left, right| Condition::Or(Box::new(left), Box::new(right)))
+                .unwrap())
+        }
+        "&" => {
+            let conditions = get_conditions(expression, scope)?;
+            Ok(conditions
+                .into_iter()
+                .reduce(|left, right| Condition::And(Box::new(left), Box::new(right)))
+                .unwrap())
+        }
+        "!" => {
             if expression.len() != 2 {
                 return Err(ParseError::ArgumentCount);
             }
             Ok(Condition::Not(Box::new(build_condition(
                 expression[1].get_expression()?,
-                schema,
+                scope,
             )?)))
         }
-        _ => unreachable!(),
+        _ => Err(ParseError::UnexpectedToken),
     }
 }
 
 fn get_binary_expressions(
     expression: &[Expression],
-    schema: &Schema,
+    scope: Scope,
 ) -> Result<(ValueExpression, ValueExpression), ParseError> {
     if expression.len() != 3 {
         return Err(ParseError::ArgumentCount);
     }
-    Ok((
-        build_value_expression(&expression[1], schema)?,
-        build_value_expression(&expression[2], schema)?,
-    ))
+    // A bare number takes the type of what it is compared with, so
+    // it doesn't need a type
+    match (&expression[1], &expression[2]) {
+        (Expression::Numeric(number), other) if !matches!(other, Expression::Numeric(_)) => {
+            let other = build_value_expression(other, scope)?;
+            Ok((build_bare_numeric(number, &other, scope)?, other))
+        }
+        (other, Expression::Numeric(number)) if !matches!(other, Expression::Numeric(_)) => {
+            let other = build_value_expression(other, scope)?;
+            let number = build_bare_numeric(number, &other, scope)?;
+            Ok((other, number))
+        }
+        (left, right) => Ok((
+            build_value_expression(left, scope)?,
+            build_value_expression(right, scope)?,
+        )),
+    }
 }
 
-fn get_binary_conditions(
-    expression: &[Expression],
-    schema: &Schema,
-) -> Result<(Condition, Condition), ParseError> {
-    if expression.len() != 3 {
+/// Builds a bare number compared with another expression as a value
+/// of the other expression's type.
+fn build_bare_numeric(
+    number: &str,
+    other: &ValueExpression,
+    scope: Scope,
+) -> Result<ValueExpression, ParseError> {
+    let field_type = other
+        .value_type(scope.schema, scope.element)
+        .ok_or(ParseError::UnexpectedToken)?;
+    parse_numeric(number, &field_type)
+        .map(ValueExpression::Value)
+        .ok_or_else(|| ParseError::NumericType {
+            numeric: number.to_string(),
+            field_type,
+        })
+}
+
+/// Parses a number as a value of a numeric [`FieldType`], or
+/// returns `None` if it isn't a valid value of the type.
+fn parse_numeric(number: &str, field_type: &FieldType) -> Option<FieldValue> {
+    match field_type {
+        FieldType::Int => number.parse().ok().map(FieldValue::Int),
+        FieldType::UInt => number.parse().ok().map(FieldValue::UInt),
+        FieldType::Long => number.parse().ok().map(FieldValue::Long),
+        FieldType::ULong => number.parse().ok().map(FieldValue::ULong),
+        FieldType::Float => number.parse().ok().map(FieldValue::Float),
+        _ => None,
+    }
+}
+
+/// Builds the two or more conditions joined by a `&` or `|`.
+fn get_conditions(expression: &[Expression], scope: Scope) -> Result<Vec<Condition>, ParseError> {
+    if expression.len() < 3 {
         return Err(ParseError::ArgumentCount);
     }
-    return Ok((
-        build_condition(expression[1].get_expression()?, schema)?,
-        build_condition(expression[2].get_expression()?, schema)?,
-    ));
+    expression[1..]
+        .iter()
+        .map(|condition| build_condition(condition.get_expression()?, scope))
+        .collect()
 }
 
 fn build_value_expression(
     expression: &Expression,
-    schema: &Schema,
+    scope: Scope,
 ) -> Result<ValueExpression, ParseError> {
     match expression {
         Expression::Identifier(identifier) => match identifier.as_str() {
             "true" => Ok(ValueExpression::Value(FieldValue::Bool(true))),
             "false" => Ok(ValueExpression::Value(FieldValue::Bool(false))),
+            "_" if scope.element.is_some() => Ok(ValueExpression::Placeholder),
             _ => Err(ParseError::UnexpectedToken),
         },
         Expression::Literal(string) => {
             Ok(ValueExpression::Value(FieldValue::String(string.clone())))
         }
-        Expression::Numeric(_number) => {
-            todo!("Numeric parsing")
-        }
+        Expression::Numeric(number) => Err(ParseError::UntypedNumeric(number.clone())),
         Expression::List(expression) => {
             if expression.is_empty() {
                 return Err(ParseError::ArgumentCount);
             }
-            match expression[0].get_identifier()?.as_str() {
+            // Date arithmetic is written with operators
+            let function = match &expression[0] {
+                Expression::Identifier(name) => name.as_str(),
+                operator => operator.get_operator()?,
+            };
+            match function {
                 "tf" => {
                     if expression.len() != 2 {
                         return Err(ParseError::ArgumentCount);
                     }
                     let identifier = expression[1].get_identifier()?;
-                    let field = schema
+                    let field = scope
+                        .schema
                         .fields
                         .iter()
                         .find(|f| &f.name == identifier)
                         .ok_or_else(|| ParseError::UnknownIdentifier(identifier.clone()))?;
                     Ok(ValueExpression::Field(field.id))
                 }
+                "id" => {
+                    if expression.len() != 1 {
+                        return Err(ParseError::ArgumentCount);
+                    }
+                    Ok(ValueExpression::DocumentID)
+                }
                 "f" => {
-                    // TODO support subexpressions in ValueExpression
-                    todo!()
+                    if expression.len() < 2 {
+                        return Err(ParseError::ArgumentCount);
+                    }
+                    let mut path = build_path_start(&expression[1], scope)?;
+                    for member in &expression[2..] {
+                        let identifier = member.get_identifier()?;
+                        let object = match path.value_type(scope.schema, scope.element) {
+                            Some(FieldType::Object(object)) => object,
+                            Some(field_type) => return Err(ParseError::NotAnObject(field_type)),
+                            None => return Err(ParseError::UnexpectedToken),
+                        };
+                        let field = object
+                            .fields
+                            .iter()
+                            .find(|f| &f.name == identifier)
+                            .ok_or_else(|| ParseError::UnknownIdentifier(identifier.clone()))?;
+                        path = ValueExpression::Member(Box::new(path), field.id);
+                    }
+                    Ok(path)
                 }
                 "s" => {
-                    // TODO support subscripts in ValueExpression
-                    todo!()
+                    if expression.len() != 3 {
+                        return Err(ParseError::ArgumentCount);
+                    }
+                    let array = build_path_start(&expression[1], scope)?;
+                    array_element_type(&array, scope)?;
+                    let index = expression[2]
+                        .get_numeric()?
+                        .parse()
+                        .map_err(|_| ParseError::NumericError)?;
+                    Ok(ValueExpression::Element(Box::new(array), index))
+                }
+                "len" => {
+                    if expression.len() != 2 {
+                        return Err(ParseError::ArgumentCount);
+                    }
+                    let array = build_path_start(&expression[1], scope)?;
+                    array_element_type(&array, scope)?;
+                    Ok(ValueExpression::Length(Box::new(array)))
+                }
+                "date" => {
+                    if expression.len() != 2 {
+                        return Err(ParseError::ArgumentCount);
+                    }
+                    let string = expression[1].get_literal()?;
+                    let date = DateTime::parse_from_rfc3339(string)
+                        .map_err(|_| ParseError::InvalidDate(string.clone()))?;
+                    Ok(ValueExpression::Value(FieldValue::DateTime(
+                        date.with_timezone(&Utc),
+                    )))
+                }
+                "now" => {
+                    if expression.len() != 1 {
+                        return Err(ParseError::ArgumentCount);
+                    }
+                    Ok(ValueExpression::Value(FieldValue::DateTime(
+                        DateTime::from(SystemTime::now()),
+                    )))
+                }
+                "+" | "-" => {
+                    if expression.len() != 3 {
+                        return Err(ParseError::ArgumentCount);
+                    }
+                    let date = build_value_expression(&expression[1], scope)?;
+                    match date.value_type(scope.schema, scope.element) {
+                        Some(FieldType::DateTime) => {}
+                        Some(field_type) => return Err(ParseError::NotADate(field_type)),
+                        None => return Err(ParseError::UnexpectedToken),
+                    }
+                    let mut duration = build_duration(expression[2].get_expression()?)?;
+                    if function == "-" {
+                        duration = -duration;
+                    }
+                    // Dates which don't depend on the document are moved now
+                    match date {
+                        ValueExpression::Value(FieldValue::DateTime(date)) => date
+                            .checked_add_signed(duration)
+                            .map(|date| ValueExpression::Value(FieldValue::DateTime(date)))
+                            .ok_or(ParseError::DateOutOfRange),
+                        date => Ok(ValueExpression::Offset(Box::new(date), duration)),
+                    }
                 }
                 "num" => {
                     if expression.len() != 3 {
@@ -255,34 +575,16 @@ fn build_value_expression(
                     }
                     let numeric_type = expression[2].get_identifier()?;
                     let numeric_string = expression[1].get_numeric()?;
-                    let field_value = match numeric_type.as_str() {
-                        "Int" => FieldValue::Int(
-                            numeric_string
-                                .parse()
-                                .map_err(|_| ParseError::NumericError)?,
-                        ),
-                        "UInt" => FieldValue::UInt(
-                            numeric_string
-                                .parse()
-                                .map_err(|_| ParseError::NumericError)?,
-                        ),
-                        "Long" => FieldValue::Long(
-                            numeric_string
-                                .parse()
-                                .map_err(|_| ParseError::NumericError)?,
-                        ),
-                        "ULong" => FieldValue::ULong(
-                            numeric_string
-                                .parse()
-                                .map_err(|_| ParseError::NumericError)?,
-                        ),
-                        "Float" => FieldValue::Float(
-                            numeric_string
-                                .parse()
-                                .map_err(|_| ParseError::NumericError)?,
-                        ),
+                    let field_type = match numeric_type.as_str() {
+                        "Int" => FieldType::Int,
+                        "UInt" => FieldType::UInt,
+                        "Long" => FieldType::Long,
+                        "ULong" => FieldType::ULong,
+                        "Float" => FieldType::Float,
                         _ => return Err(ParseError::UnexpectedToken),
                     };
+                    let field_value = parse_numeric(numeric_string, &field_type)
+                        .ok_or(ParseError::NumericError)?;
                     Ok(ValueExpression::Value(field_value))
                 }
                 _ => Err(ParseError::UnexpectedToken),
@@ -292,6 +594,61 @@ fn build_value_expression(
     }
 }
 
+/// Builds a duration like `(days 30)`, which dates can be moved by.
+fn build_duration(expression: &[Expression]) -> Result<Duration, ParseError> {
+    if expression.len() != 2 {
+        return Err(ParseError::ArgumentCount);
+    }
+    let unit = match expression[0].get_identifier()?.as_str() {
+        "weeks" => 7 * 24 * 60 * 60,
+        "days" => 24 * 60 * 60,
+        "hours" => 60 * 60,
+        "minutes" => 60,
+        "seconds" => 1,
+        _ => return Err(ParseError::UnexpectedToken),
+    };
+    let count: i64 = expression[1]
+        .get_numeric()?
+        .parse()
+        .map_err(|_| ParseError::NumericError)?;
+    // `Duration::seconds` panics beyond a thousandth of the range of
+    // an `i64`
+    count
+        .checked_mul(unit)
+        .filter(|seconds| seconds.checked_mul(1000).is_some())
+        .map(Duration::seconds)
+        .ok_or(ParseError::DateOutOfRange)
+}
+
+/// Builds the start of a `(f ...)` path or the array of an `(s ...)`
+/// subscript, which is either the name of a field, or an expression.
+fn build_path_start(expression: &Expression, scope: Scope) -> Result<ValueExpression, ParseError> {
+    match expression {
+        Expression::Identifier(identifier) if identifier == "_" && scope.element.is_some() => {
+            Ok(ValueExpression::Placeholder)
+        }
+        Expression::Identifier(identifier) => scope
+            .schema
+            .fields
+            .iter()
+            .find(|f| &f.name == identifier)
+            .map(|f| ValueExpression::Field(f.id))
+            .ok_or_else(|| ParseError::UnknownIdentifier(identifier.clone())),
+        Expression::List(_) => build_value_expression(expression, scope),
+        _ => Err(ParseError::UnexpectedToken),
+    }
+}
+
+/// Returns the type of the elements of the array an expression
+/// evaluates to, or fails if it isn't an array.
+fn array_element_type(array: &ValueExpression, scope: Scope) -> Result<FieldType, ParseError> {
+    match array.value_type(scope.schema, scope.element) {
+        Some(FieldType::Array(element)) => Ok(*element),
+        Some(field_type) => Err(ParseError::NotAnArray(field_type)),
+        None => Err(ParseError::UnexpectedToken),
+    }
+}
+
 fn build_read_all(expression: &[Expression]) -> Result<Statement, ParseError> {
     if expression.len() != 2 {
         return Err(ParseError::ArgumentCount);
@@ -304,18 +661,17 @@ fn build_read_all(expression: &[Expression]) -> Result<Statement, ParseError> {
 
 fn build_update_all(
     expression: &[Expression],
-    selections: HashMap<String, &Reference>,
+    selections: HashMap<String, &Schema>,
     reader: impl Read,
 ) -> Result<Statement, ParseError> {
     if expression.len() != 2 {
         return Err(ParseError::ArgumentCount);
     }
     let identifier = expression[1].get_identifier()?;
-    let selection = selections
+    let schema = selections
         .get(identifier)
         .ok_or_else(|| ParseError::UnknownIdentifier(identifier.clone()))?;
-    let document =
-        Document::from_reader(reader, &selection.schema).map_err(ParseError::TransferError)?;
+    let document = Document::from_reader(reader, schema).map_err(ParseError::TransferError)?;
     let statement = Statement::UpdateAll {
         selection: identifier.clone(),
         document,
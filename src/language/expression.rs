@@ -12,7 +12,7 @@ pub enum Expression {
     Identifier(String),
     Numeric(String),
     Literal(String),
-    Operator(char),
+    Operator(String),
     List(Vec<Expression>),
 }
 
@@ -35,11 +35,20 @@ impl Expression {
         }
     }
 
-    /// Returns Ok(char) if this `Expression` is an
+    /// Returns Ok(&str) if this `Expression` is an
     /// operator, or Err(ParseError::UnexpectedToken) otherwise.
-    pub fn get_operator(&self) -> Result<char, ParseError> {
+    pub fn get_operator(&self) -> Result<&str, ParseError> {
         match self {
-            Expression::Operator(c) => Ok(*c),
+            Expression::Operator(s) => Ok(s),
+            _ => Err(ParseError::UnexpectedToken),
+        }
+    }
+
+    /// Returns Ok(&String) if this `Expression` is a
+    /// literal, or Err(ParseError::UnexpectedToken) otherwise.
+    pub fn get_literal(&self) -> Result<&String, ParseError> {
+        match self {
+            Expression::Literal(s) => Ok(s),
             _ => Err(ParseError::UnexpectedToken),
         }
     }
@@ -1,3 +1,4 @@
+use crate::schema::FieldType;
 use crate::transfer::DeserializationError;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
@@ -11,12 +12,28 @@ use std::fmt::{Display, Formatter};
 #[derive(Debug)]
 pub enum ParseError {
     ReadError(std::io::Error),
-    UnexpectedCharacter { position: usize, value: u8 },
+    UnexpectedCharacter {
+        position: usize,
+        value: u8,
+    },
     ArgumentCount,
     UnexpectedToken,
     UnknownIdentifier(String),
     TransferError(DeserializationError),
     NumericError,
+    NumericType {
+        numeric: String,
+        field_type: FieldType,
+    },
+    UntypedNumeric(String),
+    NotAnObject(FieldType),
+    NotAnArray(FieldType),
+    InvalidPattern(String),
+    NotAnEnum(FieldType),
+    NoAssociatedValue(String),
+    NotADate(FieldType),
+    InvalidDate(String),
+    DateOutOfRange,
     UnexpectedEndOfInput,
 }
 
@@ -41,6 +58,39 @@ impl Display for ParseError {
             }
             ParseError::TransferError(e) => write!(formatter, "Transfer parse error: {}", e),
             ParseError::NumericError => write!(formatter, "Error parsing numeric"),
+            ParseError::NumericType {
+                numeric,
+                field_type,
+            } => write!(
+                formatter,
+                "Numeric {} is not a valid {}",
+                numeric, field_type
+            ),
+            ParseError::UntypedNumeric(numeric) => write!(
+                formatter,
+                "Numeric {} must be compared with a field or a typed value",
+                numeric
+            ),
+            ParseError::NotAnObject(field_type) => {
+                write!(formatter, "Expected an Object, found {}", field_type)
+            }
+            ParseError::NotAnArray(field_type) => {
+                write!(formatter, "Expected an Array, found {}", field_type)
+            }
+            ParseError::InvalidPattern(pattern) => {
+                write!(formatter, "Invalid pattern {:?}", pattern)
+            }
+            ParseError::NotAnEnum(field_type) => {
+                write!(formatter, "Expected an Enum, found {}", field_type)
+            }
+            ParseError::NoAssociatedValue(case) => {
+                write!(formatter, "Case {} has no associated value", case)
+            }
+            ParseError::NotADate(field_type) => {
+                write!(formatter, "Expected a DateTime, found {}", field_type)
+            }
+            ParseError::InvalidDate(date) => write!(formatter, "Invalid date {:?}", date),
+            ParseError::DateOutOfRange => write!(formatter, "Date out of range"),
             ParseError::UnexpectedEndOfInput => write!(formatter, "Unexpected end of input"),
         }
     }
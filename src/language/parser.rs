@@ -21,6 +21,9 @@ impl Parser {
         }
     }
 
+    // The input is read byte-by-byte on purpose: a buffered reader would
+    // consume data following the s-expression, such as document JSON.
+    #[allow(clippy::unbuffered_bytes)]
     fn parse_input(mut self, input: impl Read) -> Result<Vec<Expression>, ParseError> {
         for byte in input.bytes() {
             let byte = byte.map_err(ParseError::ReadError)?;
@@ -60,7 +63,10 @@ impl Parser {
                 continue;
             }
             match byte {
-                b'(' => self.output.push(Vec::new()),
+                b'(' => {
+                    self.end_token(byte)?;
+                    self.output.push(Vec::new());
+                }
                 b')' => {
                     self.end_token(byte)?;
                     if self.output.len() == 1 {
@@ -91,7 +97,9 @@ impl Parser {
                     }
                     self.current.push(byte as char);
                 }
-                b'0'..=b'9' | b'-' | b'.' => {
+                // A sign starts a numeric, which is an operator if
+                // nothing follows the sign
+                b'0'..=b'9' | b'-' | b'+' | b'.' => {
                     match self.current_type {
                         None => self.current_type = Some(CurrentType::Numeric),
                         Some(CurrentType::Numeric) => (),
@@ -104,19 +112,19 @@ impl Parser {
                     }
                     self.current.push(byte as char);
                 }
-                b'=' | b'<' | b'>' | b'|' | b'&' | b'!' => match self.current_type {
-                    None => self
-                        .output
-                        .last_mut()
-                        .unwrap()
-                        .push(Expression::Operator(byte as char)),
-                    _ => {
-                        return Err(ParseError::UnexpectedCharacter {
-                            position: self.position,
-                            value: byte,
-                        })
+                b'=' | b'<' | b'>' | b'|' | b'&' | b'!' => {
+                    match self.current_type {
+                        None => self.current_type = Some(CurrentType::Operator),
+                        Some(CurrentType::Operator) => (),
+                        _ => {
+                            return Err(ParseError::UnexpectedCharacter {
+                                position: self.position,
+                                value: byte,
+                            })
+                        }
                     }
-                },
+                    self.current.push(byte as char);
+                }
                 b' ' | b'\n' => self.end_token(byte)?,
                 b'"' => match self.current_type {
                     None => self.current_type = Some(CurrentType::Literal),
@@ -152,6 +160,14 @@ impl Parser {
                 self.current.clear();
                 self.current_type = None;
             }
+            Some(CurrentType::Numeric) if self.current == "-" || self.current == "+" => {
+                self.output
+                    .last_mut()
+                    .unwrap()
+                    .push(Expression::Operator(self.current.clone()));
+                self.current.clear();
+                self.current_type = None;
+            }
             Some(CurrentType::Numeric) => {
                 self.output
                     .last_mut()
@@ -160,6 +176,14 @@ impl Parser {
                 self.current.clear();
                 self.current_type = None;
             }
+            Some(CurrentType::Operator) => {
+                self.output
+                    .last_mut()
+                    .unwrap()
+                    .push(Expression::Operator(self.current.clone()));
+                self.current.clear();
+                self.current_type = None;
+            }
             None => (),
             _ => {
                 return Err(ParseError::UnexpectedCharacter {
@@ -175,6 +199,7 @@ impl Parser {
 enum CurrentType {
     Identifier,
     Numeric,
+    Operator,
     Literal,
 }
 
@@ -1,4 +1,6 @@
-use crate::schema::Document;
+use crate::backend::{Access, Condition, Expression, QueryPlan};
+use crate::schema::{Document, FieldType, FieldValue, Schema};
+use chrono::{Duration, SecondsFormat};
 use std::io::Write;
 
 /// A response to a client statement.
@@ -13,9 +15,17 @@ pub enum Response {
     Committed,
     Closed,
     Selected,
+    Empty,
     Document(Document),
+    Documents(Vec<Document>),
     Updated,
     Deleted,
+    Compacted,
+    Plan {
+        plan: QueryPlan,
+        /// The schema of the queried collection, to name its fields.
+        schema: Schema,
+    },
 }
 
 impl Response {
@@ -27,6 +37,7 @@ impl Response {
             Response::Committed => writeln!(out, "(ok committed)")?,
             Response::Closed => writeln!(out, "(ok closed)")?,
             Response::Selected => writeln!(out, "(ok selected)")?,
+            Response::Empty => writeln!(out, "(ok empty)")?,
             Response::Document(doc) => {
                 let write_result = doc.into_writer(out.by_ref());
                 if let Err(error) = write_result {
@@ -34,9 +45,247 @@ impl Response {
                 }
                 writeln!(out)?;
             }
+            Response::Documents(docs) => {
+                let write_result = Document::list_into_writer(docs, out.by_ref());
+                if let Err(error) = write_result {
+                    writeln!(out, "Serialization error: {}", error)?;
+                }
+                writeln!(out)?;
+            }
             Response::Updated => writeln!(out, "(ok updated)")?,
             Response::Deleted => writeln!(out, "(ok deleted)")?,
+            Response::Compacted => writeln!(out, "(ok compacted)")?,
+            Response::Plan { plan, schema } => {
+                writeln!(out, "(ok plan)")?;
+                write_plan(&mut out, &plan, &schema)?;
+                writeln!(out)?;
+            }
         }
         Ok(())
     }
 }
+
+/// Writes a [`QueryPlan`] as an s-expression, like
+/// `(plan (coll people) (scan (estimate 20)) (filter (= (tf name) "Jo")))`.
+///
+/// Index lookups are written as `(index ([fields]) [conditions]
+/// (estimate [count]))`, where `conditions` are the comparisons the
+/// lookup satisfies, and combined lookups as `(union [a] [b])` and
+/// `(intersect [a] [b])`.
+fn write_plan(out: &mut impl Write, plan: &QueryPlan, schema: &Schema) -> std::io::Result<()> {
+    write!(out, "(plan (coll {}) ", schema.name)?;
+    write_access(out, &plan.access, schema)?;
+    write!(out, " (filter ")?;
+    write_condition(out, &plan.condition, schema, None)?;
+    write!(out, "))")
+}
+
+fn write_access(out: &mut impl Write, access: &Access, schema: &Schema) -> std::io::Result<()> {
+    match access {
+        Access::Scan { estimate } => write!(out, "(scan (estimate {}))", estimate),
+        Access::Index(lookup) => {
+            let names: Vec<String> = lookup
+                .fields
+                .iter()
+                .map(|id| field_name(*id, schema))
+                .collect();
+            write!(out, "(index ({})", names.join(" "))?;
+            for condition in &lookup.conditions {
+                write!(out, " ")?;
+                write_condition(out, condition, schema, None)?;
+            }
+            write!(out, " (estimate {}))", lookup.estimate)
+        }
+        Access::Union(left, right) | Access::Intersection(left, right) => {
+            let name = match access {
+                Access::Union(..) => "union",
+                _ => "intersect",
+            };
+            write!(out, "({} ", name)?;
+            write_access(out, left, schema)?;
+            write!(out, " ")?;
+            write_access(out, right, schema)?;
+            write!(out, ")")
+        }
+    }
+}
+
+fn write_condition(
+    out: &mut impl Write,
+    condition: &Condition,
+    schema: &Schema,
+    element: Option<&FieldType>,
+) -> std::io::Result<()> {
+    let (operator, left, right) = match condition {
+        Condition::Equal(left, right) => ("=", left, right),
+        Condition::NotEqual(left, right) => ("!=", left, right),
+        Condition::GreaterThan(left, right) => (">", left, right),
+        Condition::GreaterOrEqual(left, right) => (">=", left, right),
+        Condition::LessThan(left, right) => ("<", left, right),
+        Condition::LessOrEqual(left, right) => ("<=", left, right),
+        Condition::EqualIgnoreCase(left, right) => ("ieq", left, right),
+        Condition::Prefix(left, right) => ("prefix", left, right),
+        Condition::Suffix(left, right) => ("suffix", left, right),
+        Condition::Contains(left, right) => ("contains", left, right),
+        Condition::In(value, array) => ("in", value, array),
+        Condition::Any(array, inner) | Condition::All(array, inner) => {
+            let name = if matches!(condition, Condition::Any(..)) {
+                "any"
+            } else {
+                "all"
+            };
+            write!(out, "({} ", name)?;
+            write_expression(out, array, schema, element)?;
+            write!(out, " ")?;
+            // `_` in the inner condition refers to the array's elements
+            let inner_element = match array.value_type(schema, element) {
+                Some(FieldType::Array(inner_element)) => Some(*inner_element),
+                _ => None,
+            };
+            write_condition(out, inner, schema, inner_element.as_ref())?;
+            return write!(out, ")");
+        }
+        Condition::Matches(expression, pattern) => {
+            write!(out, "(matches ")?;
+            write_expression(out, expression, schema, element)?;
+            return write!(out, " {:?})", pattern.as_str());
+        }
+        Condition::Case(value, case_id, inner) => {
+            write!(out, "(case ")?;
+            write_expression(out, value, schema, element)?;
+            let case = match value.value_type(schema, element) {
+                Some(FieldType::Enum(cases)) => cases.into_iter().find(|c| c.id == *case_id),
+                _ => None,
+            };
+            match &case {
+                Some(case) => write!(out, " {}", case.name)?,
+                None => write!(out, " {}", case_id)?,
+            }
+            if let Some(inner) = inner {
+                write!(out, " ")?;
+                // `_` in the inner condition refers to the associated value
+                let associated = case.and_then(|c| c.associated_value);
+                write_condition(out, inner, schema, associated.as_ref())?;
+            }
+            return write!(out, ")");
+        }
+        Condition::OneOf(value, values) => {
+            write!(out, "(oneof ")?;
+            write_expression(out, value, schema, element)?;
+            for value in values.values() {
+                write!(out, " ")?;
+                write_value(out, value)?;
+            }
+            return write!(out, ")");
+        }
+        Condition::Or(..) | Condition::And(..) => {
+            let or = matches!(condition, Condition::Or(..));
+            write!(out, "({}", if or { '|' } else { '&' })?;
+            // A chain of the same operator is written as one list
+            let mut branches = vec![condition];
+            while let Some(branch) = branches.pop() {
+                match branch {
+                    Condition::Or(left, right) if or => branches.extend([&**right, &**left]),
+                    Condition::And(left, right) if !or => branches.extend([&**right, &**left]),
+                    branch => {
+                        write!(out, " ")?;
+                        write_condition(out, branch, schema, element)?;
+                    }
+                }
+            }
+            return write!(out, ")");
+        }
+        Condition::Not(condition) => {
+            write!(out, "(! ")?;
+            write_condition(out, condition, schema, element)?;
+            return write!(out, ")");
+        }
+    };
+    write!(out, "({} ", operator)?;
+    write_expression(out, left, schema, element)?;
+    write!(out, " ")?;
+    write_expression(out, right, schema, element)?;
+    write!(out, ")")
+}
+
+fn write_expression(
+    out: &mut impl Write,
+    expression: &Expression,
+    schema: &Schema,
+    element: Option<&FieldType>,
+) -> std::io::Result<()> {
+    match expression {
+        Expression::Value(value) => write_value(out, value),
+        Expression::Field(id) => write!(out, "(tf {})", field_name(*id, schema)),
+        Expression::DocumentID => write!(out, "(id)"),
+        Expression::Member(..) => {
+            // A path is written as one `(f ...)` from its start
+            let mut names = vec![];
+            let mut path = expression;
+            while let Expression::Member(object, id) = path {
+                names.push(*id);
+                path = object;
+            }
+            write!(out, "(f ")?;
+            match path {
+                Expression::Field(id) => write!(out, "{}", field_name(*id, schema))?,
+                start => write_expression(out, start, schema, element)?,
+            }
+            let mut object = path.value_type(schema, element);
+            for id in names.into_iter().rev() {
+                let Some(FieldType::Object(nested)) = object else {
+                    write!(out, " {}", id)?;
+                    continue;
+                };
+                write!(out, " {}", field_name(id, &nested))?;
+                object = Expression::Field(id).value_type(&nested, None);
+            }
+            write!(out, ")")
+        }
+        Expression::Length(array) => {
+            write!(out, "(len ")?;
+            write_expression(out, array, schema, element)?;
+            write!(out, ")")
+        }
+        Expression::Placeholder => write!(out, "_"),
+        Expression::Offset(date, duration) => {
+            let operator = if *duration < Duration::zero() {
+                '-'
+            } else {
+                '+'
+            };
+            write!(out, "({} ", operator)?;
+            write_expression(out, date, schema, element)?;
+            write!(out, " (seconds {}))", duration.num_seconds().abs())
+        }
+        Expression::Element(array, index) => {
+            write!(out, "(s ")?;
+            write_expression(out, array, schema, element)?;
+            write!(out, " {})", index)
+        }
+    }
+}
+
+fn write_value(out: &mut impl Write, value: &FieldValue) -> std::io::Result<()> {
+    match value {
+        FieldValue::Int(i) => write!(out, "(num {} Int)", i),
+        FieldValue::UInt(i) => write!(out, "(num {} UInt)", i),
+        FieldValue::Long(i) => write!(out, "(num {} Long)", i),
+        FieldValue::ULong(i) => write!(out, "(num {} ULong)", i),
+        FieldValue::Float(f) => write!(out, "(num {} Float)", f),
+        FieldValue::DateTime(d) => write!(
+            out,
+            "(date \"{}\")",
+            d.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+        ),
+        value => write!(out, "{}", value),
+    }
+}
+
+fn field_name(id: crate::util::FieldID, schema: &Schema) -> String {
+    schema
+        .fields
+        .iter()
+        .find(|f| f.id == id)
+        .map_or_else(|| id.to_string(), |f| f.name.clone())
+}
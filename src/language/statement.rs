@@ -29,6 +29,11 @@ pub enum Statement {
         transaction: String,
         lock: LockType,
         query: Query,
+        /// Whether every matching document is selected, rather
+        /// than the first.
+        many: bool,
+        /// Whether the statement fails if no document matches.
+        required: bool,
     },
     Create {
         identifier: String,
@@ -49,4 +54,9 @@ pub enum Statement {
     Delete {
         selection: String,
     },
+    Compact,
+    /// Plan a selection's query without selecting anything.
+    Explain {
+        query: Query,
+    },
 }
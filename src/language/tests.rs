@@ -1,4 +1,5 @@
 use super::parser::parse;
+use crate::test_support::{test_field, test_schema};
 
 #[test]
 fn start_parser() {
@@ -7,3 +8,584 @@ fn start_parser() {
     let result = parse(&mut input).expect("Parse failed");
     println!("{:?}", result);
 }
+
+fn people_schema() -> crate::schema::Schema {
+    use crate::schema::{EnumCase, FieldType};
+    let address = FieldType::Object(Box::new(test_schema(
+        "address",
+        0x11,
+        vec![
+            test_field("city", 0x1, FieldType::String),
+            test_field("floor", 0x2, FieldType::Int),
+        ],
+    )));
+    test_schema(
+        "people",
+        0x10,
+        vec![
+            test_field("name", 0x1, FieldType::String),
+            test_field("age", 0x2, FieldType::UInt),
+            test_field("balance", 0x3, FieldType::Float),
+            test_field("address", 0x4, address.clone()),
+            test_field("scores", 0x5, FieldType::Array(Box::new(FieldType::UInt))),
+            test_field("homes", 0x6, FieldType::Array(Box::new(address))),
+            test_field(
+                "payment",
+                0x7,
+                FieldType::Enum(vec![
+                    EnumCase {
+                        name: "cash".to_string(),
+                        id: 0x1,
+                        associated_value: None,
+                    },
+                    EnumCase {
+                        name: "card".to_string(),
+                        id: 0x2,
+                        associated_value: Some(FieldType::UInt),
+                    },
+                ]),
+            ),
+            test_field("joined", 0x8, FieldType::DateTime),
+        ],
+    )
+}
+
+fn build(statement: &str) -> Result<super::Statement, super::ParseError> {
+    let mut input = statement.as_bytes();
+    let tokens = parse(&mut input).expect("Parse failed");
+    super::build_statement(&tokens, &[people_schema()], Default::default(), &mut input)
+}
+
+fn build_condition(condition: &str) -> Result<crate::backend::Condition, super::ParseError> {
+    match build(&format!("(selects p t r (coll people) {})", condition))? {
+        super::Statement::Select { query, .. } => Ok(query.condition),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn write_explained_plan() {
+    use super::{Response, Statement};
+    use crate::backend::{Access, QueryPlan};
+    let statement = build(
+        "(explain (selects p t r (coll people) \
+        (& (= (tf name) \"Jo\") (! (> (tf age) (num 60 UInt))))))",
+    )
+    .expect("Build failed");
+    let Statement::Explain { query } = statement else {
+        panic!("Expected an explain statement");
+    };
+    let plan = QueryPlan {
+        collection: query.collection,
+        access: Access::Scan { estimate: 3 },
+        condition: query.condition,
+    };
+    let mut out = vec![];
+    Response::Plan {
+        plan,
+        schema: people_schema(),
+    }
+    .serialize(&mut out)
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "(ok plan)\n(plan (coll people) (scan (estimate 3)) \
+        (filter (& (= (tf name) \"Jo\") (! (> (tf age) (num 60 UInt))))))\n"
+    );
+}
+
+#[test]
+fn coerce_bare_numerics() {
+    use crate::backend::{Condition, Expression};
+    use crate::schema::FieldValue;
+    assert!(matches!(
+        build_condition("(> (tf age) 60)"),
+        Ok(Condition::GreaterThan(
+            Expression::Field(0x2),
+            Expression::Value(FieldValue::UInt(60))
+        ))
+    ));
+    assert!(matches!(
+        build_condition("(< -2.5 (tf balance))"),
+        Ok(Condition::LessThan(
+            Expression::Value(FieldValue::Float(balance)),
+            Expression::Field(0x3)
+        )) if balance == -2.5
+    ));
+    assert!(matches!(
+        build_condition("(= (num 7 Long) 7)"),
+        Ok(Condition::Equal(
+            Expression::Value(FieldValue::Long(7)),
+            Expression::Value(FieldValue::Long(7))
+        ))
+    ));
+    assert_eq!(
+        build_condition("(> (tf age) -1)")
+            .err()
+            .unwrap()
+            .to_string(),
+        "Numeric -1 is not a valid FieldType::UInt"
+    );
+    assert!(matches!(
+        build_condition("(= (tf name) 3)"),
+        Err(super::ParseError::NumericType { .. })
+    ));
+    assert!(matches!(
+        build_condition("(= 3 3)"),
+        Err(super::ParseError::UntypedNumeric(_))
+    ));
+}
+
+#[test]
+fn build_field_paths() {
+    use crate::backend::{Condition, Expression};
+    use crate::schema::FieldValue;
+    assert!(matches!(
+        build_condition("(> (f age) 60)"),
+        Ok(Condition::GreaterThan(
+            Expression::Field(0x2),
+            Expression::Value(FieldValue::UInt(60))
+        ))
+    ));
+    let Ok(Condition::LessThan(Expression::Member(address, 0x2), floor)) =
+        build_condition("(< (f address floor) -1)")
+    else {
+        panic!("Expected a member of address");
+    };
+    assert!(matches!(*address, Expression::Field(0x4)));
+    assert!(matches!(floor, Expression::Value(FieldValue::Int(-1))));
+    let Ok(Condition::Equal(Expression::Element(scores, 2), score)) =
+        build_condition("(= (s (tf scores) 2) 90)")
+    else {
+        panic!("Expected an element of scores");
+    };
+    assert!(matches!(*scores, Expression::Field(0x5)));
+    assert!(matches!(score, Expression::Value(FieldValue::UInt(90))));
+    assert!(matches!(
+        build_condition("(= (f address street) \"Main\")"),
+        Err(super::ParseError::UnknownIdentifier(name)) if name == "street"
+    ));
+    assert_eq!(
+        build_condition("(= (f name first) \"Jo\")")
+            .err()
+            .unwrap()
+            .to_string(),
+        "Expected an Object, found FieldType::String"
+    );
+    assert!(matches!(
+        build_condition("(= (s (f address) 0) \"Jo\")"),
+        Err(super::ParseError::NotAnArray(_))
+    ));
+    assert!(matches!(
+        build_condition("(= (s scores -1) 1)"),
+        Err(super::ParseError::NumericError)
+    ));
+}
+
+#[test]
+fn write_field_paths() {
+    use super::{Response, Statement};
+    use crate::backend::{Access, QueryPlan};
+    let Statement::Explain { query } = build(
+        "(explain (selects p t r (coll people) \
+        (| (= (f address city) \"Oslo\") (> (s scores 0) 50))))",
+    )
+    .expect("Build failed") else {
+        panic!("Expected an explain statement");
+    };
+    let plan = QueryPlan {
+        collection: query.collection,
+        access: Access::Scan { estimate: 1 },
+        condition: query.condition,
+    };
+    let mut out = vec![];
+    Response::Plan {
+        plan,
+        schema: people_schema(),
+    }
+    .serialize(&mut out)
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "(ok plan)\n(plan (coll people) (scan (estimate 1)) \
+        (filter (| (= (f address city) \"Oslo\") (> (s (tf scores) 0) (num 50 UInt)))))\n"
+    );
+}
+
+#[test]
+fn parse_operators() {
+    use super::expression::Expression;
+    let mut input = "(>= (- (num 1 Int) -2) +3 + != <=)".as_bytes();
+    let tokens = parse(&mut input).expect("Parse failed");
+    let operator = |token: &Expression| token.get_operator().ok().map(str::to_string);
+    let numeric = |token: &Expression| token.get_numeric().ok().cloned();
+    assert_eq!(operator(&tokens[0]).as_deref(), Some(">="));
+    let inner = tokens[1].get_expression().unwrap();
+    assert_eq!(operator(&inner[0]).as_deref(), Some("-"));
+    assert_eq!(numeric(&inner[2]).as_deref(), Some("-2"));
+    assert_eq!(numeric(&tokens[2]).as_deref(), Some("+3"));
+    assert_eq!(operator(&tokens[3]).as_deref(), Some("+"));
+    assert_eq!(operator(&tokens[4]).as_deref(), Some("!="));
+    assert_eq!(operator(&tokens[5]).as_deref(), Some("<="));
+    // An operator may be followed directly by a list
+    let mut input = "(!(= a b))".as_bytes();
+    let tokens = parse(&mut input).expect("Parse failed");
+    assert_eq!(operator(&tokens[0]).as_deref(), Some("!"));
+    assert!(tokens[1].get_expression().is_ok());
+}
+
+#[test]
+fn build_comparisons() {
+    use crate::backend::{Condition, Expression};
+    use crate::schema::FieldValue;
+    assert!(matches!(
+        build_condition("(!= (tf name) \"Jo\")"),
+        Ok(Condition::NotEqual(
+            Expression::Field(0x1),
+            Expression::Value(FieldValue::String(_))
+        ))
+    ));
+    assert!(matches!(
+        build_condition("(<= (tf age) 30)"),
+        Ok(Condition::LessOrEqual(
+            Expression::Field(0x2),
+            Expression::Value(FieldValue::UInt(30))
+        ))
+    ));
+    assert!(matches!(
+        build_condition("(>= -1.5 (tf balance))"),
+        Ok(Condition::GreaterOrEqual(
+            Expression::Value(FieldValue::Float(_)),
+            Expression::Field(0x3)
+        ))
+    ));
+    assert!(matches!(
+        build_condition("(=< (tf age) 30)"),
+        Err(super::ParseError::UnexpectedToken)
+    ));
+}
+
+#[test]
+fn build_text_conditions() {
+    use super::{Response, Statement};
+    use crate::backend::{Access, Condition, Expression, QueryPlan};
+    assert!(matches!(
+        build_condition("(prefix (tf name) \"Jo\")"),
+        Ok(Condition::Prefix(
+            Expression::Field(0x1),
+            Expression::Value(_)
+        ))
+    ));
+    assert!(matches!(
+        build_condition("(ieq \"jo\" (tf name))"),
+        Ok(Condition::EqualIgnoreCase(
+            Expression::Value(_),
+            Expression::Field(0x1)
+        ))
+    ));
+    assert!(matches!(
+        build_condition("(matches (tf name) \"(\")"),
+        Err(super::ParseError::InvalidPattern(pattern)) if pattern == "("
+    ));
+    assert!(matches!(
+        build_condition("(matches (tf name) (tf name))"),
+        Err(super::ParseError::UnexpectedToken)
+    ));
+    assert!(matches!(
+        build_condition("(startswith (tf name) \"Jo\")"),
+        Err(super::ParseError::UnexpectedToken)
+    ));
+
+    let Statement::Explain { query } = build(
+        "(explain (selects p t r (coll people) \
+        (& (suffix (tf name) \"son\") (| (contains (tf name) \"an\") \
+        (matches (tf name) \"^\\\\w+$\")))))",
+    )
+    .expect("Build failed") else {
+        panic!("Expected an explain statement");
+    };
+    let plan = QueryPlan {
+        collection: query.collection,
+        access: Access::Scan { estimate: 1 },
+        condition: query.condition,
+    };
+    let mut out = vec![];
+    Response::Plan {
+        plan,
+        schema: people_schema(),
+    }
+    .serialize(&mut out)
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "(ok plan)\n(plan (coll people) (scan (estimate 1)) \
+        (filter (& (suffix (tf name) \"son\") (| (contains (tf name) \"an\") \
+        (matches (tf name) \"^\\\\w+$\")))))\n"
+    );
+}
+
+#[test]
+fn build_array_conditions() {
+    use super::{Response, Statement};
+    use crate::backend::{Access, Condition, Expression, QueryPlan};
+    use crate::schema::FieldValue;
+    assert!(matches!(
+        build_condition("(in 90 (tf scores))"),
+        Ok(Condition::In(
+            Expression::Value(FieldValue::UInt(90)),
+            Expression::Field(0x5)
+        ))
+    ));
+    let Ok(Condition::Any(Expression::Field(0x5), condition)) =
+        build_condition("(any (tf scores) (> _ 90))")
+    else {
+        panic!("Expected any of scores");
+    };
+    assert!(matches!(
+        *condition,
+        Condition::GreaterThan(
+            Expression::Placeholder,
+            Expression::Value(FieldValue::UInt(90))
+        )
+    ));
+    let Ok(Condition::GreaterThan(Expression::Length(homes), _)) =
+        build_condition("(> (len homes) 1)")
+    else {
+        panic!("Expected the length of homes");
+    };
+    assert!(matches!(*homes, Expression::Field(0x6)));
+    assert!(matches!(
+        build_condition("(> _ 1)"),
+        Err(super::ParseError::UnexpectedToken)
+    ));
+    assert!(matches!(
+        build_condition("(in \"Jo\" (tf name))"),
+        Err(super::ParseError::NotAnArray(_))
+    ));
+    assert!(matches!(
+        build_condition("(all (tf scores) (= (f _ city) \"Oslo\"))"),
+        Err(super::ParseError::NotAnObject(_))
+    ));
+
+    let Statement::Explain { query } = build(
+        "(explain (selects p t r (coll people) \
+        (& (all (tf homes) (& (< (f _ floor) 3) (any (tf scores) (> _ 50)))) \
+        (= (len scores) 2))))",
+    )
+    .expect("Build failed") else {
+        panic!("Expected an explain statement");
+    };
+    let plan = QueryPlan {
+        collection: query.collection,
+        access: Access::Scan { estimate: 1 },
+        condition: query.condition,
+    };
+    let mut out = vec![];
+    Response::Plan {
+        plan,
+        schema: people_schema(),
+    }
+    .serialize(&mut out)
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "(ok plan)\n(plan (coll people) (scan (estimate 1)) \
+        (filter (& (all (tf homes) (& (< (f _ floor) (num 3 Int)) \
+        (any (tf scores) (> _ (num 50 UInt))))) (= (len (tf scores)) (num 2 ULong)))))\n"
+    );
+}
+
+#[test]
+fn build_case_conditions() {
+    use super::{Response, Statement};
+    use crate::backend::{Access, Condition, Expression, QueryPlan};
+    use crate::schema::FieldValue;
+    assert!(matches!(
+        build_condition("(case (tf payment) cash)"),
+        Ok(Condition::Case(Expression::Field(0x7), 0x1, None))
+    ));
+    let Ok(Condition::Case(Expression::Field(0x7), 0x2, Some(condition))) =
+        build_condition("(case (tf payment) card (> _ 100))")
+    else {
+        panic!("Expected the card case");
+    };
+    assert!(matches!(
+        *condition,
+        Condition::GreaterThan(
+            Expression::Placeholder,
+            Expression::Value(FieldValue::UInt(100))
+        )
+    ));
+    assert!(matches!(
+        build_condition("(case (tf payment) cheque)"),
+        Err(super::ParseError::UnknownIdentifier(name)) if name == "cheque"
+    ));
+    assert_eq!(
+        build_condition("(case (tf payment) cash (= _ 1))")
+            .err()
+            .unwrap()
+            .to_string(),
+        "Case cash has no associated value"
+    );
+    assert!(matches!(
+        build_condition("(case (tf name) cash)"),
+        Err(super::ParseError::NotAnEnum(_))
+    ));
+
+    let Statement::Explain { query } = build(
+        "(explain (selects p t r (coll people) \
+        (| (case (tf payment) cash) (case (tf payment) card (<= _ 20)))))",
+    )
+    .expect("Build failed") else {
+        panic!("Expected an explain statement");
+    };
+    let plan = QueryPlan {
+        collection: query.collection,
+        access: Access::Scan { estimate: 1 },
+        condition: query.condition,
+    };
+    let mut out = vec![];
+    Response::Plan {
+        plan,
+        schema: people_schema(),
+    }
+    .serialize(&mut out)
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "(ok plan)\n(plan (coll people) (scan (estimate 1)) \
+        (filter (| (case (tf payment) cash) (case (tf payment) card (<= _ (num 20 UInt))))))\n"
+    );
+}
+
+#[test]
+fn build_variadic_conditions() {
+    use super::{Response, Statement};
+    use crate::backend::{Access, Condition, Expression, QueryPlan};
+    use crate::schema::FieldValue;
+    let Ok(Condition::OneOf(Expression::Field(0x2), values)) =
+        build_condition("(oneof (tf age) 18 (num 21 UInt) 65)")
+    else {
+        panic!("Expected one of ages");
+    };
+    assert!(matches!(
+        values.values(),
+        [
+            FieldValue::UInt(18),
+            FieldValue::UInt(21),
+            FieldValue::UInt(65)
+        ]
+    ));
+    assert!(matches!(
+        build_condition("(oneof (tf age) (tf age))"),
+        Err(super::ParseError::UnexpectedToken)
+    ));
+    assert!(matches!(
+        build_condition("(oneof (tf age))"),
+        Err(super::ParseError::ArgumentCount)
+    ));
+    assert!(matches!(
+        build_condition("(| (= (tf age) 1))"),
+        Err(super::ParseError::ArgumentCount)
+    ));
+    let Ok(Condition::And(left, right)) =
+        build_condition("(& (= (tf age) 1) (= (tf age) 2) (= (tf age) 3))")
+    else {
+        panic!("Expected an and");
+    };
+    assert!(matches!(*left, Condition::And(..)));
+    assert!(matches!(*right, Condition::Equal(..)));
+
+    let Statement::Explain { query } = build(
+        "(explain (selects p t r (coll people) \
+        (| (oneof (tf name) \"Jo\" \"Al\") (& (> (tf age) 1) (< (tf age) 9) (! (= (tf age) 5))) \
+        (= (tf balance) 0.5))))",
+    )
+    .expect("Build failed") else {
+        panic!("Expected an explain statement");
+    };
+    let plan = QueryPlan {
+        collection: query.collection,
+        access: Access::Scan { estimate: 1 },
+        condition: query.condition,
+    };
+    let mut out = vec![];
+    Response::Plan {
+        plan,
+        schema: people_schema(),
+    }
+    .serialize(&mut out)
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "(ok plan)\n(plan (coll people) (scan (estimate 1)) \
+        (filter (| (oneof (tf name) \"Jo\" \"Al\") \
+        (& (> (tf age) (num 1 UInt)) (< (tf age) (num 9 UInt)) (! (= (tf age) (num 5 UInt)))) \
+        (= (tf balance) (num 0.5 Float)))))\n"
+    );
+}
+
+#[test]
+fn build_dates() {
+    use super::{Response, Statement};
+    use crate::backend::{Access, Condition, Expression, QueryPlan};
+    use crate::schema::FieldValue;
+    let date = |condition: &str| match build_condition(condition) {
+        Ok(Condition::GreaterThan(_, Expression::Value(FieldValue::DateTime(date)))) => date,
+        _ => panic!("Expected a date"),
+    };
+    assert_eq!(
+        date("(> (tf joined) (date \"2026-01-01T02:00:00+02:00\"))").to_rfc3339(),
+        "2026-01-01T00:00:00+00:00"
+    );
+    assert_eq!(
+        date("(> (tf joined) (- (date \"2026-01-31T00:00:00Z\") (days 30)))").to_rfc3339(),
+        "2026-01-01T00:00:00+00:00"
+    );
+    let before = chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::now());
+    let month_ago = date("(> (tf joined) (- (now) (days 30)))");
+    let after = chrono::DateTime::<chrono::Utc>::from(std::time::SystemTime::now());
+    let month = chrono::Duration::days(30);
+    assert!(before - month <= month_ago && month_ago <= after - month);
+    assert!(matches!(
+        build_condition("(> (tf joined) (date \"yesterday\"))"),
+        Err(super::ParseError::InvalidDate(_))
+    ));
+    assert!(matches!(
+        build_condition("(> (tf joined) (- (tf name) (days 1)))"),
+        Err(super::ParseError::NotADate(_))
+    ));
+    assert!(matches!(
+        build_condition("(> (tf joined) (+ (now) (fortnights 1)))"),
+        Err(super::ParseError::UnexpectedToken)
+    ));
+    assert!(matches!(
+        build_condition("(> (tf joined) (+ (now) (weeks 99999999999999999)))"),
+        Err(super::ParseError::DateOutOfRange)
+    ));
+
+    let Statement::Explain { query } = build(
+        "(explain (selects p t r (coll people) \
+        (< (+ (tf joined) (hours 2)) (- (date \"2026-01-01T00:00:00.5Z\") (seconds 1)))))",
+    )
+    .expect("Build failed") else {
+        panic!("Expected an explain statement");
+    };
+    let plan = QueryPlan {
+        collection: query.collection,
+        access: Access::Scan { estimate: 1 },
+        condition: query.condition,
+    };
+    let mut out = vec![];
+    Response::Plan {
+        plan,
+        schema: people_schema(),
+    }
+    .serialize(&mut out)
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "(ok plan)\n(plan (coll people) (scan (estimate 1)) \
+        (filter (< (+ (tf joined) (seconds 7200)) (date \"2025-12-31T23:59:59.500Z\"))))\n"
+    );
+}
@@ -13,6 +13,8 @@ mod database;
 mod frontend;
 mod language;
 mod schema;
+#[cfg(test)]
+mod test_support;
 mod transfer;
 mod util;
 
@@ -1,8 +1,13 @@
 use crate::schema::{FieldInstance, Schema};
+use crate::util::DocumentID;
 
 /// A single record in the database.
 #[derive(Clone)]
 pub struct Document {
+    /// The persistent identifier of this `Document`, assigned when
+    /// it is first committed. `None` if it has not been committed,
+    /// and for objects embedded in other documents.
+    pub id: Option<DocumentID>,
     /// The schema to which this `Document` conforms.
     pub schema: Schema,
     /// The fields/value pairs on this instance.
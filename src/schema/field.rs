@@ -13,4 +13,8 @@ pub struct Field {
     pub id: FieldID,
     /// The type of this field.
     pub field_type: FieldType,
+    /// Whether no two documents may have the same value for this
+    /// field, which is enforced with an index.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unique: bool,
 }
@@ -52,6 +52,7 @@ impl FieldValue {
                 name: String::new(),
                 id: 0,
                 fields: vec![],
+                indexes: vec![],
             })),
             Self::Enum(_) => FieldType::Enum(vec![]),
         }
@@ -10,4 +10,8 @@ pub struct Schema {
     pub id: SchemaID,
     /// The field definitions on this schema.
     pub fields: Vec<Field>,
+    /// The secondary indexes on this collection, each a list of
+    /// the names of the fields it is keyed on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub indexes: Vec<Vec<String>>,
 }
@@ -0,0 +1,43 @@
+//! Builders for the collections and documents the tests use.
+use crate::schema::{Document, Field, FieldInstance, FieldType, FieldValue, Schema};
+use crate::util::{FieldID, SchemaID};
+
+/// Defines a field of a test collection, without a unique
+/// constraint.
+pub fn test_field(name: &str, id: FieldID, field_type: FieldType) -> Field {
+    Field {
+        name: name.to_string(),
+        id,
+        field_type,
+        unique: false,
+    }
+}
+
+/// Defines a test collection, without indexes.
+pub fn test_schema(name: &str, id: SchemaID, fields: Vec<Field>) -> Schema {
+    Schema {
+        name: name.to_string(),
+        id,
+        fields,
+        indexes: vec![],
+    }
+}
+
+/// Builds a new document with a value for each field of `schema`,
+/// in order, leaving out the fields after the last value.
+pub fn test_document(schema: Schema, values: Vec<FieldValue>) -> Document {
+    let fields = schema
+        .fields
+        .iter()
+        .zip(values)
+        .map(|(field, value)| FieldInstance {
+            id: field.id,
+            value,
+        })
+        .collect();
+    Document {
+        id: None,
+        schema,
+        fields,
+    }
+}
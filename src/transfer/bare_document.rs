@@ -8,6 +8,11 @@ pub struct BareDocument {
     pub fields: Vec<BareField>,
 }
 
+/// The key under which a [`Document`]'s identifier is serialized.
+///
+/// [`Document`]: crate::schema::Document
+pub const DOCUMENT_ID_KEY: &str = "_id";
+
 /// A member of a [`BareDocument`].
 pub struct BareField {
     pub name: String,
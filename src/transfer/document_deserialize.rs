@@ -1,4 +1,4 @@
-use super::bare_document::{BareDocument, BareField, BareValue};
+use super::bare_document::{BareDocument, BareField, BareValue, DOCUMENT_ID_KEY};
 use crate::schema::{Document, EnumValue, FieldInstance, FieldType, FieldValue, Schema};
 use crate::transfer::DeserializationError;
 use chrono::{DateTime, NaiveDateTime, Utc};
@@ -18,8 +18,11 @@ impl Document {
         schema: &Schema,
     ) -> Result<Document, DeserializationError> {
         let mut deserializer = serde_json::Deserializer::from_reader(reader);
-        let bare = BareDocument::deserialize(&mut deserializer)
+        let mut bare = BareDocument::deserialize(&mut deserializer)
             .map_err(DeserializationError::ParseError)?;
+        // The identifier is assigned by the database, so a client
+        // may send back a document it has read
+        bare.fields.retain(|field| field.name != DOCUMENT_ID_KEY);
         Document::from_bare(bare, schema)
     }
 }
@@ -205,6 +208,7 @@ impl Document {
             })
             .collect();
         let document = Document {
+            id: None,
             fields: fields?,
             schema: schema.clone(),
         };
@@ -1,4 +1,4 @@
-use super::bare_document::{BareDocument, BareField, BareValue};
+use super::bare_document::{BareDocument, BareField, BareValue, DOCUMENT_ID_KEY};
 use super::DeserializationError;
 use crate::schema::{Document, FieldType, FieldValue};
 use serde::ser::{SerializeMap, SerializeSeq};
@@ -11,12 +11,45 @@ impl Document {
     /// into a [`Write`].
     ///
     /// The [`Document`] is first converted into a [`BareDocument`],
-    /// then serialized using [`serde_json::to_writer`].
+    /// then serialized using [`serde_json::to_writer`]. The
+    /// document's identifier, if it has one, is serialized under
+    /// the key `_id`.
     pub fn into_writer(self, mut writer: impl Write) -> Result<(), DeserializationError> {
-        let bare = self.into_bare()?;
+        let bare = self.into_bare_with_id()?;
         writeln!(writer, "(ok document)").unwrap_or(());
         to_writer(writer, &bare).map_err(DeserializationError::ParseError)
     }
+
+    /// Writes a JSON array of [`Document`]s into a [`Write`].
+    ///
+    /// Each document is serialized as by [`Document::into_writer`].
+    pub fn list_into_writer(
+        documents: Vec<Document>,
+        mut writer: impl Write,
+    ) -> Result<(), DeserializationError> {
+        let bare: Result<Vec<BareDocument>, DeserializationError> = documents
+            .into_iter()
+            .map(Document::into_bare_with_id)
+            .collect();
+        let bare = bare?;
+        writeln!(writer, "(ok documents)").unwrap_or(());
+        to_writer(writer, &bare).map_err(DeserializationError::ParseError)
+    }
+
+    fn into_bare_with_id(self) -> Result<BareDocument, DeserializationError> {
+        let id = self.id;
+        let mut bare = self.into_bare()?;
+        if let Some(id) = id {
+            bare.fields.insert(
+                0,
+                BareField {
+                    name: DOCUMENT_ID_KEY.to_string(),
+                    value: BareValue::Integer(id as i64),
+                },
+            );
+        }
+        Ok(bare)
+    }
 }
 
 impl Serialize for BareDocument {
@@ -81,7 +114,7 @@ impl FieldValue {
         match self {
             FieldValue::Int(i) => Ok(BareValue::Integer(i as i64)),
             FieldValue::UInt(i) => Ok(BareValue::Integer(i as i64)),
-            FieldValue::Long(i) => Ok(BareValue::Integer(i as i64)),
+            FieldValue::Long(i) => Ok(BareValue::Integer(i)),
             FieldValue::ULong(i) => Ok(BareValue::Integer(
                 i64::try_from(i).map_err(|_| DeserializationError::Overflow(0))?,
             )),
@@ -1,8 +1,10 @@
 //! A module for utility traits and implementations.
+mod crc32c;
 mod lock_type;
 mod prim_int;
 mod typedefs;
 
+pub use crc32c::Crc32c;
 pub use lock_type::LockType;
 pub use prim_int::{FromByteSlice, PrimInt};
 pub use typedefs::*;
@@ -27,7 +27,9 @@ pub trait PrimInt {
     type Array: FromByteSlice;
 
     fn from_be_bytes(bytes: Self::Array) -> Self;
+    #[allow(dead_code)]
     fn from_le_bytes(bytes: Self::Array) -> Self;
+    #[allow(dead_code)]
     fn from_ne_bytes(bytes: Self::Array) -> Self;
 }
 
@@ -1,4 +1,5 @@
 pub type SchemaID = u64;
+pub type DocumentID = u64;
 pub type BlockLength = u64;
 pub type BlockPosition = u64;
 pub type FieldLength = u32;